@@ -0,0 +1,24 @@
+#include <signal.h>
+#include <stdio.h>
+
+struct point {
+    int x;
+    int y;
+};
+
+enum color { RED, GREEN, BLUE };
+
+int handled;
+
+void handler(int sig) {
+    handled = sig;
+}
+
+int main() {
+    struct point p = {1, 2};
+    enum color c = GREEN;
+    int values[3] = {4, 5, 6};
+    signal(SIGUSR1, handler);
+    raise(SIGUSR1);
+    printf("%d %d %d %d\n", p.x + p.y, c, values[2], handled);
+}
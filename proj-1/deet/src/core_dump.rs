@@ -0,0 +1,532 @@
+use crate::debugger::BreakPoint;
+use crate::inferior::Inferior;
+use crate::target::Target;
+use crate::unwind::{FileMapping, Unwinder};
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use std::cell::{RefCell, RefMut};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+
+const ET_CORE: u16 = 4;
+const EM_X86_64: u16 = 62;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+const NT_PRPSINFO: u32 = 3;
+const NT_AUXV: u32 = 6;
+const NT_FILE: u32 = 0x4649_4c45;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+const PAGE_SIZE: usize = 4096;
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// Offset of pr_reg in struct elf_prstatus
+const PRSTATUS_REGS_OFFSET: usize = 112;
+const PRSTATUS_SIZE: usize = 336;
+/// Offset of pr_psargs in struct elf_prpsinfo, and its size
+const PRPSINFO_ARGS_OFFSET: usize = 56;
+const PRPSINFO_ARGS_SIZE: usize = 80;
+const PRPSINFO_SIZE: usize = 136;
+
+pub enum Error {
+    ErrorOpeningFile,
+    CoreFormatError(&'static str),
+}
+
+/// A PT_LOAD segment of the core, holding the contents of a memory region. Only the first
+/// file_size bytes are in the core: read-only regions mapped from a file are left out.
+struct Segment {
+    address: usize,
+    size: usize,
+    offset: usize,
+    file_size: usize,
+}
+
+/// An ELF core dump of a crashed process, loaded for post-mortem debugging
+pub struct CoreDump {
+    data: memmap::Mmap,
+    segments: Vec<Segment>,
+    /// Regions of files mapped into the address space of the process, from the NT_FILE note
+    files: Vec<FileMapping>,
+    unwinder: RefCell<Unwinder>,
+    /// The registers of the thread that received the signal
+    registers: libc::user_regs_struct,
+    /// The auxiliary vector of the process, from the NT_AUXV note
+    auxv: Vec<u8>,
+    /// The signal that terminated the process
+    pub signal: Option<Signal>,
+    /// The command line of the process
+    pub command: String,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        <[u8; 2]>::try_from(data.get(offset..offset + 2)?).ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        <[u8; 4]>::try_from(data.get(offset..offset + 4)?).ok()?,
+    ))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<usize> {
+    Some(u64::from_le_bytes(<[u8; 8]>::try_from(data.get(offset..offset + 8)?).ok()?) as usize)
+}
+
+/// Iterate over the notes of a PT_NOTE segment as (type, descriptor) pairs
+fn notes(mut data: &[u8]) -> Vec<(u32, &[u8])> {
+    let align = |len: usize| (len + 3) & !3;
+    let mut notes = Vec::new();
+    while let (Some(name_size), Some(desc_size), Some(note_type)) =
+        (read_u32(data, 0), read_u32(data, 4), read_u32(data, 8))
+    {
+        let desc_start = 12 + align(name_size as usize);
+        let desc_end = desc_start + desc_size as usize;
+        match data.get(desc_start..desc_end) {
+            Some(desc) => notes.push((note_type, desc)),
+            None => break,
+        }
+        data = &data[align(desc_end).min(data.len())..];
+    }
+    notes
+}
+
+/// Parse the NT_FILE note: a count and a page size, followed by a (start, end, page offset)
+/// triple for each region, then by the paths of the regions
+fn parse_file_note(desc: &[u8]) -> Option<Vec<FileMapping>> {
+    let count = read_u64(desc, 0)?;
+    let page_size = read_u64(desc, 8)?;
+    let mut paths = desc.get(16 + count * 24..)?.split(|byte| *byte == 0);
+    let mut files = Vec::with_capacity(count);
+    for index in 0..count {
+        let entry = 16 + index * 24;
+        files.push(FileMapping {
+            path: String::from_utf8_lossy(paths.next()?).into_owned(),
+            start: read_u64(desc, entry)?,
+            end: read_u64(desc, entry + 8)?,
+            offset: read_u64(desc, entry + 16)? * page_size,
+        });
+    }
+    Some(files)
+}
+
+impl CoreDump {
+    /// Load a core dump of an x86-64 process
+    pub fn from_file(path: &str) -> Result<CoreDump, Error> {
+        let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
+        let data = unsafe { memmap::Mmap::map(&file) }.or(Err(Error::ErrorOpeningFile))?;
+        if data.get(..4) != Some(b"\x7fELF") || data.get(4) != Some(&2) || data.get(5) != Some(&1) {
+            return Err(Error::CoreFormatError(
+                "not a 64-bit little endian ELF file",
+            ));
+        }
+        if read_u16(&data, 16) != Some(ET_CORE) {
+            return Err(Error::CoreFormatError("not a core file"));
+        }
+        if read_u16(&data, 18) != Some(EM_X86_64) {
+            return Err(Error::CoreFormatError("not an x86-64 core file"));
+        }
+        let truncated = || Error::CoreFormatError("truncated program headers");
+        let program_headers = read_u64(&data, 32).ok_or_else(truncated)?;
+        let header_size = read_u16(&data, 54).ok_or_else(truncated)? as usize;
+        let header_count = read_u16(&data, 56).ok_or_else(truncated)? as usize;
+
+        let mut segments = Vec::new();
+        let mut registers = None;
+        let mut signal = None;
+        let mut command = String::new();
+        let mut files = Vec::new();
+        let mut auxv = Vec::new();
+        for index in 0..header_count {
+            let header = program_headers + index * header_size;
+            let (segment_type, offset, address, file_size, size) = match (
+                read_u32(&data, header),
+                read_u64(&data, header + 8),
+                read_u64(&data, header + 16),
+                read_u64(&data, header + 32),
+                read_u64(&data, header + 40),
+            ) {
+                (Some(a), Some(b), Some(c), Some(d), Some(e)) => (a, b, c, d, e),
+                _ => return Err(truncated()),
+            };
+            match segment_type {
+                PT_LOAD => segments.push(Segment {
+                    address,
+                    size,
+                    offset,
+                    file_size,
+                }),
+                PT_NOTE => {
+                    let segment = data
+                        .get(offset..offset + file_size)
+                        .ok_or(Error::CoreFormatError("truncated notes"))?;
+                    for (note_type, desc) in notes(segment) {
+                        match note_type {
+                            // the first thread is the one that received the signal
+                            NT_PRSTATUS if registers.is_none() => {
+                                let regs = desc
+                                    .get(PRSTATUS_REGS_OFFSET..)
+                                    .filter(|regs| {
+                                        regs.len() >= std::mem::size_of::<libc::user_regs_struct>()
+                                    })
+                                    .ok_or(Error::CoreFormatError("truncated NT_PRSTATUS note"))?;
+                                registers = Some(unsafe {
+                                    std::ptr::read_unaligned(
+                                        regs.as_ptr() as *const libc::user_regs_struct
+                                    )
+                                });
+                                // pr_cursig follows si_signo, si_code and si_errno
+                                signal = read_u16(desc, 12)
+                                    .and_then(|signal| Signal::try_from(signal as i32).ok());
+                            }
+                            NT_PRPSINFO => {
+                                let args = desc
+                                    .get(PRPSINFO_ARGS_OFFSET..)
+                                    .unwrap_or_default()
+                                    .iter()
+                                    .take(PRPSINFO_ARGS_SIZE)
+                                    .take_while(|byte| **byte != 0)
+                                    .cloned()
+                                    .collect::<Vec<u8>>();
+                                command = String::from_utf8_lossy(&args).trim_end().to_string();
+                            }
+                            NT_AUXV => auxv = desc.to_vec(),
+                            NT_FILE => {
+                                files = parse_file_note(desc)
+                                    .ok_or(Error::CoreFormatError("truncated NT_FILE note"))?;
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(CoreDump {
+            data,
+            segments,
+            unwinder: RefCell::new(Unwinder::with_files(&files)),
+            files,
+            registers: registers.ok_or(Error::CoreFormatError("no NT_PRSTATUS note"))?,
+            auxv,
+            signal,
+            command,
+        })
+    }
+
+    /// Read memory starting at addr, up to len bytes or up to the end of the region holding addr:
+    /// from the core, or from the file a region left out of the core was mapped from. Memory not
+    /// backed by either reads as zero, like the anonymous memory it is.
+    fn read_region(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+        let segment = self
+            .segments
+            .iter()
+            .find(|segment| segment.address <= addr && addr < segment.address + segment.size)?;
+        let offset = addr - segment.address;
+        if offset < segment.file_size {
+            let len = len.min(segment.file_size - offset);
+            let start = segment.offset + offset;
+            return self
+                .data
+                .get(start..start + len)
+                .map(|bytes| bytes.to_vec());
+        }
+        let len = len.min(segment.size - offset);
+        match self
+            .files
+            .iter()
+            .find(|file| file.start <= addr && addr < file.end)
+        {
+            Some(file) => {
+                let mut bytes = vec![0; len.min(file.end - addr)];
+                let mut mapped = fs::File::open(&file.path).ok()?;
+                mapped
+                    .seek(SeekFrom::Start((file.offset + addr - file.start) as u64))
+                    .ok()?;
+                mapped.read_exact(&mut bytes).ok()?;
+                Some(bytes)
+            }
+            None => Some(vec![0; len]),
+        }
+    }
+}
+
+impl Target for CoreDump {
+    fn get_registers(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        Ok(self.registers)
+    }
+
+    fn read_bytes(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            match self.read_region(addr + bytes.len(), len - bytes.len()) {
+                Some(region) => bytes.extend(region),
+                None => return Err(nix::Error::Sys(nix::errno::Errno::EIO)),
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn unwinder(&self) -> RefMut<'_, Unwinder> {
+        self.unwinder.borrow_mut()
+    }
+
+    fn auxv(&self) -> Vec<u8> {
+        self.auxv.clone()
+    }
+}
+
+/// A region of the address space of a live process, as listed by /proc/<pid>/maps
+struct Region {
+    start: usize,
+    end: usize,
+    /// PF_R, PF_W and PF_X
+    flags: u32,
+    offset: usize,
+    path: Option<String>,
+}
+
+fn read_regions(pid: Pid) -> io::Result<Vec<Region>> {
+    let mut regions = Vec::new();
+    for line in fs::read_to_string(format!("/proc/{}/maps", pid))?.lines() {
+        // start-end perms offset dev inode path
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 5 {
+            continue;
+        }
+        let mut range = fields[0].split('-');
+        let (start, end, offset) = match (
+            range.next().and_then(|s| usize::from_str_radix(s, 16).ok()),
+            range.next().and_then(|s| usize::from_str_radix(s, 16).ok()),
+            usize::from_str_radix(fields[2], 16).ok(),
+        ) {
+            (Some(start), Some(end), Some(offset)) => (start, end, offset),
+            _ => continue,
+        };
+        // the vsyscall page is not part of the memory of the process
+        if fields.get(5) == Some(&"[vsyscall]") {
+            continue;
+        }
+        let perms = fields[1].as_bytes();
+        let flag = |index: usize, letter: u8, flag: u32| {
+            if perms.get(index) == Some(&letter) {
+                flag
+            } else {
+                0
+            }
+        };
+        regions.push(Region {
+            start,
+            end,
+            flags: flag(0, b'r', PF_R) | flag(1, b'w', PF_W) | flag(2, b'x', PF_X),
+            offset,
+            path: fields
+                .get(5)
+                .filter(|path| path.starts_with('/'))
+                .map(|path| path.to_string()),
+        });
+    }
+    Ok(regions)
+}
+
+fn push_note(notes: &mut Vec<u8>, note_type: u32, desc: &[u8]) {
+    notes.extend_from_slice(&5u32.to_le_bytes());
+    notes.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+    notes.extend_from_slice(&note_type.to_le_bytes());
+    notes.extend_from_slice(b"CORE\0\0\0\0");
+    notes.extend_from_slice(desc);
+    notes.resize((notes.len() + 3) & !3, 0);
+}
+
+fn put_u32(buffer: &mut [u8], offset: usize, value: u32) {
+    buffer[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buffer: &mut [u8], offset: usize, value: usize) {
+    buffer[offset..offset + 8].copy_from_slice(&(value as u64).to_le_bytes());
+}
+
+/// The parent, process group and session of a process, from /proc/<pid>/stat
+fn read_process_ids(pid: Pid) -> io::Result<(u32, u32, u32)> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    // pid (comm) state ppid pgrp session ..., where comm may hold spaces and parentheses
+    let fields: Vec<u32> = stat[stat.rfind(')').unwrap_or(0) + 1..]
+        .split_whitespace()
+        .skip(1)
+        .take(3)
+        .filter_map(|field| field.parse().ok())
+        .collect();
+    match fields.as_slice() {
+        [ppid, pgrp, session] => Ok((*ppid, *pgrp, *session)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "malformed stat")),
+    }
+}
+
+/// Write a core dump of a stopped inferior, which deet --core and gdb can load later. The
+/// original bytes are written in place of breakpoints.
+pub fn generate(
+    inferior: &Inferior,
+    breakpoints: &HashMap<usize, BreakPoint>,
+    path: &str,
+) -> io::Result<()> {
+    let pid = inferior.pid();
+    let regions = read_regions(pid)?;
+    let (ppid, pgrp, session) = read_process_ids(pid)?;
+    let to_io_error = |err: nix::Error| io::Error::other(err.to_string());
+
+    // the thread that stopped comes first, as it is the one a debugger starts out in
+    let (_, current) = inferior.current_thread();
+    let mut threads: Vec<Pid> = inferior.threads().iter().map(|(_, tid)| *tid).collect();
+    threads.sort_by_key(|tid| *tid != current);
+    let mut notes = Vec::new();
+    for (index, tid) in threads.iter().enumerate() {
+        let mut prstatus = vec![0; PRSTATUS_SIZE];
+        let signal = inferior
+            .thread_signal(*tid)
+            .map_or(0, |signal| signal as u32);
+        put_u32(&mut prstatus, 0, signal);
+        prstatus[12..14].copy_from_slice(&(signal as u16).to_le_bytes());
+        put_u32(&mut prstatus, 32, tid.as_raw() as u32);
+        put_u32(&mut prstatus, 36, ppid);
+        put_u32(&mut prstatus, 40, pgrp);
+        put_u32(&mut prstatus, 44, session);
+        let regs = inferior.thread_registers(*tid).map_err(to_io_error)?;
+        let regs = unsafe {
+            std::slice::from_raw_parts(
+                &regs as *const libc::user_regs_struct as *const u8,
+                std::mem::size_of::<libc::user_regs_struct>(),
+            )
+        };
+        prstatus[PRSTATUS_REGS_OFFSET..PRSTATUS_REGS_OFFSET + regs.len()].copy_from_slice(regs);
+        push_note(&mut notes, NT_PRSTATUS, &prstatus);
+        if index > 0 {
+            continue;
+        }
+
+        // the notes about the whole process follow the first thread
+        let mut prpsinfo = vec![0; PRPSINFO_SIZE];
+        // pr_sname, the state letter of a traced process
+        prpsinfo[1] = b't';
+        put_u32(&mut prpsinfo, 16, nix::unistd::getuid().as_raw());
+        put_u32(&mut prpsinfo, 20, nix::unistd::getgid().as_raw());
+        put_u32(&mut prpsinfo, 24, pid.as_raw() as u32);
+        put_u32(&mut prpsinfo, 28, ppid);
+        put_u32(&mut prpsinfo, 32, pgrp);
+        put_u32(&mut prpsinfo, 36, session);
+        let comm = fs::read(format!("/proc/{}/comm", pid))?;
+        let comm: Vec<u8> = comm
+            .into_iter()
+            .filter(|byte| *byte != b'\n')
+            .take(15)
+            .collect();
+        prpsinfo[40..40 + comm.len()].copy_from_slice(&comm);
+        let args = fs::read(format!("/proc/{}/cmdline", pid))?;
+        let args: Vec<u8> = args
+            .iter()
+            .take(PRPSINFO_ARGS_SIZE - 1)
+            .map(|byte| if *byte == 0 { b' ' } else { *byte })
+            .collect();
+        prpsinfo[PRPSINFO_ARGS_OFFSET..PRPSINFO_ARGS_OFFSET + args.len()].copy_from_slice(&args);
+        push_note(&mut notes, NT_PRPSINFO, &prpsinfo);
+        push_note(
+            &mut notes,
+            NT_AUXV,
+            &fs::read(format!("/proc/{}/auxv", pid))?,
+        );
+
+        let files: Vec<&Region> = regions
+            .iter()
+            .filter(|region| region.path.is_some())
+            .collect();
+        let mut file_note = vec![0; 16 + files.len() * 24];
+        put_u64(&mut file_note, 0, files.len());
+        put_u64(&mut file_note, 8, PAGE_SIZE);
+        for (index, region) in files.iter().enumerate() {
+            put_u64(&mut file_note, 16 + index * 24, region.start);
+            put_u64(&mut file_note, 24 + index * 24, region.end);
+            put_u64(&mut file_note, 32 + index * 24, region.offset / PAGE_SIZE);
+        }
+        for region in &files {
+            file_note.extend_from_slice(region.path.as_ref().unwrap().as_bytes());
+            file_note.push(0);
+        }
+        push_note(&mut notes, NT_FILE, &file_note);
+    }
+
+    // ELF header, then a PT_NOTE header and a PT_LOAD header for each region, then the notes,
+    // then the contents of the readable regions starting at a page boundary
+    let header_count = 1 + regions.len();
+    let notes_offset = ELF_HEADER_SIZE + header_count * PROGRAM_HEADER_SIZE;
+    let mut headers = vec![0; notes_offset];
+    headers[..8].copy_from_slice(b"\x7fELF\x02\x01\x01\x00");
+    headers[16..18].copy_from_slice(&ET_CORE.to_le_bytes());
+    headers[18..20].copy_from_slice(&EM_X86_64.to_le_bytes());
+    put_u32(&mut headers, 20, 1);
+    put_u64(&mut headers, 32, ELF_HEADER_SIZE);
+    headers[52..54].copy_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+    headers[54..56].copy_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    headers[56..58].copy_from_slice(&(header_count as u16).to_le_bytes());
+    let mut put_program_header = |index: usize, values: (u32, u32, usize, usize, usize, usize)| {
+        let (segment_type, flags, offset, address, file_size, size) = values;
+        let header = ELF_HEADER_SIZE + index * PROGRAM_HEADER_SIZE;
+        put_u32(&mut headers, header, segment_type);
+        put_u32(&mut headers, header + 4, flags);
+        put_u64(&mut headers, header + 8, offset);
+        put_u64(&mut headers, header + 16, address);
+        put_u64(&mut headers, header + 32, file_size);
+        put_u64(&mut headers, header + 40, size);
+        put_u64(
+            &mut headers,
+            header + 48,
+            if segment_type == PT_LOAD {
+                PAGE_SIZE
+            } else {
+                1
+            },
+        );
+    };
+    put_program_header(0, (PT_NOTE, 0, notes_offset, 0, notes.len(), 0));
+    let mut offset = (notes_offset + notes.len() + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let data_offset = offset;
+    for (index, region) in regions.iter().enumerate() {
+        let size = region.end - region.start;
+        let file_size = if region.flags & PF_R != 0 { size } else { 0 };
+        put_program_header(
+            index + 1,
+            (PT_LOAD, region.flags, offset, region.start, file_size, size),
+        );
+        offset += file_size;
+    }
+
+    let mut output = BufWriter::new(fs::File::create(path)?);
+    output.write_all(&headers)?;
+    output.write_all(&notes)?;
+    output.write_all(&vec![0; data_offset - notes_offset - notes.len()])?;
+    let mut memory = fs::File::open(format!("/proc/{}/mem", pid))?;
+    let mut chunk = vec![0; PAGE_SIZE];
+    for region in regions.iter().filter(|region| region.flags & PF_R != 0) {
+        for addr in (region.start..region.end).step_by(PAGE_SIZE) {
+            // pages that cannot be read, like those of [vvar], are left zeroed
+            let read = memory
+                .seek(SeekFrom::Start(addr as u64))
+                .and_then(|_| memory.read_exact(&mut chunk));
+            if read.is_err() {
+                chunk.iter_mut().for_each(|byte| *byte = 0);
+            }
+            for breakpoint in breakpoints.values().filter(|bp| bp.enabled()) {
+                if addr <= breakpoint.addr() && breakpoint.addr() < addr + PAGE_SIZE {
+                    chunk[breakpoint.addr() - addr] = breakpoint.orig_byte();
+                }
+            }
+            output.write_all(&chunk)?;
+        }
+    }
+    output.flush()
+}
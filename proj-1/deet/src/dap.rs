@@ -0,0 +1,771 @@
+use crate::debugger::Debugger;
+use crate::debugger_command::DebuggerCommand;
+use crate::interpreter::{self, Mode};
+use crate::json::Json;
+use crate::signals;
+use nix::fcntl::{self, OFlag};
+use nix::sys::stat::Mode as FileMode;
+use nix::unistd::{self, Pid};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::os::unix::io::FromRawFd;
+
+/// Frames are identified by thread number and level, as FRAME_IDS_PER_THREAD * thread + level
+const FRAME_IDS_PER_THREAD: i64 = 10000;
+
+/// The stream of Debug Adapter Protocol messages to and from the client, each one a header
+/// giving its length followed by a JSON body
+struct Connection {
+    reader: BufReader<Box<dyn Read>>,
+    writer: Box<dyn Write>,
+    seq: i64,
+}
+
+impl Connection {
+    /// Talk to the client over stdin and stdout. The inferior inherits the standard streams, so
+    /// they are pointed elsewhere (stdout at stderr, stdin at /dev/null) to keep its I/O out of
+    /// the protocol.
+    fn stdio() -> nix::Result<Connection> {
+        let input = unistd::dup(0)?;
+        let output = unistd::dup(1)?;
+        let null = fcntl::open("/dev/null", OFlag::O_RDONLY, FileMode::empty())?;
+        unistd::dup2(null, 0)?;
+        unistd::close(null)?;
+        unistd::dup2(2, 1)?;
+        unsafe {
+            Ok(Connection {
+                reader: BufReader::new(Box::new(File::from_raw_fd(input))),
+                writer: Box::new(File::from_raw_fd(output)),
+                seq: 1,
+            })
+        }
+    }
+
+    /// Wait for a client to connect to the given port of the loopback interface
+    fn tcp(port: u16) -> io::Result<Connection> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        console!("Listening for a DAP client on port {}", port);
+        let (stream, _) = listener.accept()?;
+        Ok(Connection {
+            reader: BufReader::new(Box::new(stream.try_clone()?)),
+            writer: Box::new(stream),
+            seq: 1,
+        })
+    }
+
+    /// Read the next message. Returns None once the client hangs up or sends garbage.
+    fn receive(&mut self) -> Option<Json> {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            if self.reader.read_line(&mut header).ok()? == 0 {
+                return None;
+            }
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(index) = header.find(':') {
+                if header[..index].eq_ignore_ascii_case("Content-Length") {
+                    length = header[index + 1..].trim().parse().ok();
+                }
+            }
+        }
+        let mut body = vec![0; length?];
+        self.reader.read_exact(&mut body).ok()?;
+        Json::parse(&String::from_utf8_lossy(&body))
+    }
+
+    /// Send a message, of type request, response or event, numbering it
+    fn send(&mut self, message_type: &str, mut fields: Vec<(&str, Json)>) {
+        fields.insert(0, ("seq", Json::Int(self.seq)));
+        fields.insert(1, ("type", Json::string(message_type)));
+        self.seq += 1;
+        let body = Json::object(fields).to_string();
+        // a client that went away is noticed at the next receive
+        let _ = write!(
+            self.writer,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let _ = self.writer.flush();
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.send(
+            "event",
+            vec![("event", Json::string(event)), ("body", body)],
+        );
+    }
+}
+
+/// Serves one client, running the debugger for it
+struct Adapter {
+    connection: Connection,
+    debugger: Option<Debugger>,
+    /// Arguments to run the program with, once configured; None when attached
+    args: Option<Vec<String>>,
+    /// Breakpoint numbers set for each source file, or for "" for function breakpoints, which
+    /// setBreakpoints replaces as a whole
+    breakpoints: HashMap<String, Vec<usize>>,
+    /// Records emitted by the debugger that are yet to be sent as events
+    records: Vec<Json>,
+}
+
+/// Run a Debug Adapter Protocol server on stdio, or on a TCP port if given, until the client
+/// disconnects
+pub fn serve(port: Option<u16>) {
+    // until a client is connected, there is no one but the user to tell what happens
+    let connection = match port {
+        Some(port) => Connection::tcp(port).map_err(|err| err.to_string()),
+        None => Connection::stdio().map_err(|err| err.to_string()),
+    };
+    let connection = match connection {
+        Ok(connection) => connection,
+        Err(err) => {
+            console!("Could not open the DAP connection: {}", err);
+            std::process::exit(1);
+        }
+    };
+    interpreter::set_mode(Mode::Dap);
+    run(connection);
+}
+
+/// Serve the client of a connection until it disconnects or hangs up
+fn run(connection: Connection) {
+    let mut adapter = Adapter {
+        connection,
+        debugger: None,
+        args: None,
+        breakpoints: HashMap::new(),
+        records: Vec::new(),
+    };
+    while let Some(request) = adapter.connection.receive() {
+        if !adapter.handle(&request) {
+            return;
+        }
+    }
+    // the client went away without a disconnect request
+    if let Some(debugger) = adapter.debugger.as_mut() {
+        debugger.execute(DebuggerCommand::Quit);
+    }
+}
+
+/// Translate the reason of a stop record into a DAP stopped reason
+fn stop_reason(reason: &str) -> &'static str {
+    match reason {
+        "breakpoint-hit" => "breakpoint",
+        "end-stepping-range" => "step",
+        "signal-received" => "exception",
+        "watchpoint-trigger" => "data breakpoint",
+        "exec" => "entry",
+        _ => "pause",
+    }
+}
+
+fn argument<'a>(arguments: &'a Json, name: &str) -> Result<&'a Json, String> {
+    arguments
+        .get(name)
+        .ok_or_else(|| format!("Missing argument {}", name))
+}
+
+fn int_argument(arguments: &Json, name: &str) -> Result<i64, String> {
+    argument(arguments, name)?
+        .as_i64()
+        .ok_or_else(|| format!("Argument {} is not a number", name))
+}
+
+fn str_argument<'a>(arguments: &'a Json, name: &str) -> Result<&'a str, String> {
+    argument(arguments, name)?
+        .as_str()
+        .ok_or_else(|| format!("Argument {} is not a string", name))
+}
+
+/// Split a frame id into the thread number and frame level it stands for
+fn split_frame_id(frame_id: i64) -> (usize, usize) {
+    (
+        (frame_id / FRAME_IDS_PER_THREAD) as usize,
+        (frame_id % FRAME_IDS_PER_THREAD) as usize,
+    )
+}
+
+impl Adapter {
+    /// Answer a request, then send the events it gave rise to. Returns false once the client
+    /// disconnected.
+    fn handle(&mut self, request: &Json) -> bool {
+        let command = request
+            .get("command")
+            .and_then(Json::as_str)
+            .unwrap_or("")
+            .to_string();
+        let arguments = request
+            .get("arguments")
+            .cloned()
+            .unwrap_or_else(|| Json::object(vec![]));
+        let result = self.dispatch(&command, &arguments);
+        let mut fields = vec![
+            (
+                "request_seq",
+                request.get("seq").cloned().unwrap_or(Json::Null),
+            ),
+            ("command", Json::string(&command)),
+        ];
+        match result {
+            Ok(body) => {
+                fields.push(("success", Json::Bool(true)));
+                fields.push(("body", body));
+            }
+            Err(message) => {
+                fields.push(("success", Json::Bool(false)));
+                fields.push(("message", Json::String(message)));
+            }
+        }
+        self.connection.send("response", fields);
+        if (command == "launch" || command == "attach") && self.debugger.is_some() {
+            self.connection.event("initialized", Json::object(vec![]));
+        }
+        self.send_events();
+        command != "disconnect"
+    }
+
+    fn dispatch(&mut self, command: &str, arguments: &Json) -> Result<Json, String> {
+        match command {
+            "initialize" => Ok(Json::object(vec![
+                ("supportsConfigurationDoneRequest", Json::Bool(true)),
+                ("supportsFunctionBreakpoints", Json::Bool(true)),
+                ("supportsConditionalBreakpoints", Json::Bool(true)),
+                ("supportsEvaluateForHovers", Json::Bool(true)),
+            ])),
+            "launch" => {
+                let program = str_argument(arguments, "program")?;
+                let args = match arguments.get("args").and_then(Json::as_array) {
+                    Some(args) => args
+                        .iter()
+                        .map(|arg| arg.as_str().map(str::to_string))
+                        .collect::<Option<Vec<String>>>()
+                        .ok_or("Argument args is not a list of strings")?,
+                    None => Vec::new(),
+                };
+                File::open(program).map_err(|err| format!("{}: {}", program, err))?;
+                self.debugger = Some(Debugger::new(program, None, None)?);
+                self.args = Some(args);
+                Ok(Json::Null)
+            }
+            "attach" => {
+                let pid = int_argument(arguments, "pid")?;
+                let target = fs::read_link(format!("/proc/{}/exe", pid)).map_err(|err| {
+                    format!("Could not find the executable of process {}: {}", pid, err)
+                })?;
+                self.debugger = Some(Debugger::new(
+                    &target.to_string_lossy(),
+                    Some(Pid::from_raw(pid as i32)),
+                    None,
+                )?);
+                Ok(Json::Null)
+            }
+            "disconnect" => {
+                if let Some(debugger) = self.debugger.as_mut() {
+                    // a launched program is killed unless the client asks to leave it running
+                    let terminate = arguments.get("terminateDebuggee").and_then(Json::as_bool);
+                    if terminate == Some(false) && !debugger.threads().is_empty() {
+                        debugger.execute(DebuggerCommand::Detach);
+                    }
+                    debugger.execute(DebuggerCommand::Quit);
+                }
+                Ok(Json::Null)
+            }
+            _ if self.debugger.is_none() => {
+                Err("No program has been launched or attached to".to_string())
+            }
+            _ => self.dispatch_session(command, arguments),
+        }
+    }
+
+    /// Handle a request that needs a program being debugged
+    fn dispatch_session(&mut self, command: &str, arguments: &Json) -> Result<Json, String> {
+        let debugger = self.debugger.as_mut().unwrap();
+        match command {
+            "setBreakpoints" => {
+                let source = argument(arguments, "source")?;
+                let path = str_argument(source, "path")?.to_string();
+                let lines = arguments
+                    .get("breakpoints")
+                    .and_then(Json::as_array)
+                    .unwrap_or(&[]);
+                self.replace_breakpoints(&path, lines, |line| {
+                    Ok(format!("{}:{}", path, int_argument(line, "line")?))
+                })
+            }
+            "setFunctionBreakpoints" => {
+                let functions = arguments
+                    .get("breakpoints")
+                    .and_then(Json::as_array)
+                    .unwrap_or(&[]);
+                self.replace_breakpoints("", functions, |function| {
+                    Ok(str_argument(function, "name")?.to_string())
+                })
+            }
+            "configurationDone" => {
+                if let Some(args) = self.args.take() {
+                    debugger.execute(DebuggerCommand::Run(args));
+                }
+                Ok(Json::Null)
+            }
+            "threads" => {
+                let threads = debugger
+                    .threads()
+                    .into_iter()
+                    .map(|(id, tid)| {
+                        Json::object(vec![
+                            ("id", Json::Int(id as i64)),
+                            ("name", Json::String(format!("Thread {} (LWP {})", id, tid))),
+                        ])
+                    })
+                    .collect();
+                Ok(Json::object(vec![("threads", Json::Array(threads))]))
+            }
+            "stackTrace" => self.stack_trace(arguments),
+            "scopes" => {
+                let frame_id = int_argument(arguments, "frameId")?;
+                let scope = |name: &str, reference: i64| {
+                    Json::object(vec![
+                        ("name", Json::string(name)),
+                        ("variablesReference", Json::Int(reference)),
+                        ("expensive", Json::Bool(false)),
+                    ])
+                };
+                // each frame has two scopes, numbered after its id
+                Ok(Json::object(vec![(
+                    "scopes",
+                    Json::Array(vec![
+                        scope("Locals", frame_id * 2 + 1),
+                        scope("Arguments", frame_id * 2 + 2),
+                    ]),
+                )]))
+            }
+            "variables" => {
+                let reference = int_argument(arguments, "variablesReference")?;
+                let (thread, level) = split_frame_id((reference - 1) / 2);
+                debugger.focus(thread, level);
+                if debugger.threads().is_empty() {
+                    return Err("The process is not being run".to_string());
+                }
+                let variables = debugger
+                    .frame_variables(reference % 2 == 0)
+                    .map_err(|err| format!("Fail to read variables: {}", err))?
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|var| {
+                        Json::object(vec![
+                            ("name", Json::String(var.name)),
+                            ("value", Json::String(var.value)),
+                            ("type", Json::String(var.type_name)),
+                            ("variablesReference", Json::Int(0)),
+                        ])
+                    })
+                    .collect();
+                Ok(Json::object(vec![("variables", Json::Array(variables))]))
+            }
+            "continue" => {
+                debugger.execute(DebuggerCommand::Cont);
+                Ok(Json::object(vec![(
+                    "allThreadsContinued",
+                    Json::Bool(true),
+                )]))
+            }
+            "next" | "stepIn" | "stepOut" => {
+                let thread = int_argument(arguments, "threadId")?;
+                debugger.focus(thread as usize, 0);
+                debugger.execute(match command {
+                    "next" => DebuggerCommand::Next,
+                    "stepIn" => DebuggerCommand::Step,
+                    _ => DebuggerCommand::Finish,
+                });
+                Ok(Json::Null)
+            }
+            "evaluate" => self.evaluate(arguments),
+            _ => Err(format!("Unsupported request {}", command)),
+        }
+    }
+
+    /// Replace the breakpoints of a source file, or the function breakpoints, with new ones at
+    /// the locations the given breakpoint requests resolve to
+    fn replace_breakpoints(
+        &mut self,
+        key: &str,
+        requests: &[Json],
+        location: impl Fn(&Json) -> Result<String, String>,
+    ) -> Result<Json, String> {
+        let debugger = self.debugger.as_mut().unwrap();
+        for id in self.breakpoints.remove(key).unwrap_or_default() {
+            debugger.execute(DebuggerCommand::Delete(id));
+        }
+        let mut ids = Vec::new();
+        let mut breakpoints = Vec::new();
+        for request in requests {
+            let condition = request
+                .get("condition")
+                .and_then(Json::as_str)
+                .filter(|condition| !condition.trim().is_empty());
+            let mut breakpoint = vec![];
+            match debugger.break_at(&location(request)?, condition) {
+                Some(id) => {
+                    // two requests for lines sharing code get the same breakpoint
+                    if !ids.contains(&id) {
+                        ids.push(id);
+                    }
+                    breakpoint.push(("id", Json::Int(id as i64)));
+                    breakpoint.push(("verified", Json::Bool(true)));
+                }
+                None => breakpoint.push(("verified", Json::Bool(false))),
+            }
+            if let Some(line) = request.get("line") {
+                breakpoint.push(("line", line.clone()));
+            }
+            breakpoints.push(Json::object(breakpoint));
+        }
+        self.breakpoints.insert(key.to_string(), ids);
+        Ok(Json::object(vec![(
+            "breakpoints",
+            Json::Array(breakpoints),
+        )]))
+    }
+
+    fn stack_trace(&mut self, arguments: &Json) -> Result<Json, String> {
+        let debugger = self.debugger.as_mut().unwrap();
+        let thread = int_argument(arguments, "threadId")?;
+        debugger.focus(thread as usize, 0);
+        let frames = debugger
+            .backtrace()
+            .map_err(|err| format!("Fail to unwind the stack: {}", err))?;
+        let total = frames.len();
+        let start = arguments
+            .get("startFrame")
+            .and_then(Json::as_i64)
+            .unwrap_or(0) as usize;
+        let count = match arguments.get("levels").and_then(Json::as_i64) {
+            Some(levels) if levels > 0 => levels as usize,
+            _ => total,
+        };
+        let debug_data = debugger.debug_data();
+        let frames = frames
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(count)
+            .map(|(level, frame)| {
+                let function = debug_data.get_function_from_addr(frame.lookup_pc());
+                let line = debug_data.get_line_from_addr(frame.lookup_pc());
+                let mut fields = vec![
+                    (
+                        "id",
+                        Json::Int(thread * FRAME_IDS_PER_THREAD + level as i64),
+                    ),
+                    (
+                        "name",
+                        Json::String(function.unwrap_or_else(|| "??".to_string())),
+                    ),
+                    ("instructionPointerReference", Json::hex(frame.pc)),
+                    (
+                        "line",
+                        Json::Int(line.as_ref().map_or(0, |line| line.number as i64)),
+                    ),
+                    ("column", Json::Int(0)),
+                ];
+                if let Some(line) = line {
+                    let name = line.file.rsplit('/').next().unwrap_or(&line.file);
+                    fields.push((
+                        "source",
+                        Json::object(vec![
+                            ("name", Json::string(name)),
+                            ("path", Json::string(&line.file)),
+                        ]),
+                    ));
+                }
+                Json::object(fields)
+            })
+            .collect();
+        Ok(Json::object(vec![
+            ("stackFrames", Json::Array(frames)),
+            ("totalFrames", Json::Int(total as i64)),
+        ]))
+    }
+
+    /// Evaluate an expression. In the debug console, the expression is a deet command, and the
+    /// result is what it prints; elsewhere (hovers, watches), it is a variable to print.
+    fn evaluate(&mut self, arguments: &Json) -> Result<Json, String> {
+        let debugger = self.debugger.as_mut().unwrap();
+        let expression = str_argument(arguments, "expression")?;
+        if let Some(frame_id) = arguments.get("frameId").and_then(Json::as_i64) {
+            let (thread, level) = split_frame_id(frame_id);
+            debugger.focus(thread, level);
+        }
+        let repl = arguments.get("context").and_then(Json::as_str) == Some("repl");
+        let command = if repl {
+            match DebuggerCommand::from_line(expression) {
+                Some(DebuggerCommand::Quit) => return Err("Use disconnect to quit".to_string()),
+                Some(command) => command,
+                None => return Err("Unrecognized command.".to_string()),
+            }
+        } else {
+            DebuggerCommand::Print(expression.trim().to_string())
+        };
+        debugger.execute(command);
+        // what the command prints is the result rather than output
+        let mut output = Vec::new();
+        for record in interpreter::take_records() {
+            match (
+                record.get("type").and_then(Json::as_str),
+                record.get("text"),
+            ) {
+                (Some("console"), Some(Json::String(text))) => output.push(text.clone()),
+                _ => self.records.push(record),
+            }
+        }
+        let mut result = output.join("\n");
+        if !repl {
+            // print says "(type) name = value"
+            if let Some(index) = result.find(" = ") {
+                result = result[index + 3..].to_string();
+            }
+        }
+        Ok(Json::object(vec![
+            ("result", Json::String(result)),
+            ("variablesReference", Json::Int(0)),
+        ]))
+    }
+
+    /// Send the records the debugger emitted as DAP events
+    fn send_events(&mut self) {
+        self.records.extend(interpreter::take_records());
+        for record in std::mem::take(&mut self.records) {
+            let field = |name: &str| record.get(name).cloned().unwrap_or(Json::Null);
+            match record.get("type").and_then(Json::as_str).unwrap_or("") {
+                "console" => {
+                    let text = record.get("text").and_then(Json::as_str).unwrap_or("");
+                    self.connection.event(
+                        "output",
+                        Json::object(vec![
+                            ("category", Json::string("console")),
+                            ("output", Json::String(format!("{}\n", text))),
+                        ]),
+                    );
+                }
+                "stopped" => {
+                    let reason = record.get("reason").and_then(Json::as_str).unwrap_or("");
+                    let mut body = vec![
+                        ("reason", Json::string(stop_reason(reason))),
+                        (
+                            "threadId",
+                            record.get("thread").cloned().unwrap_or(Json::Int(1)),
+                        ),
+                        ("allThreadsStopped", Json::Bool(true)),
+                    ];
+                    if let Some(id) = record.get("breakpoint") {
+                        body.push(("hitBreakpointIds", Json::Array(vec![id.clone()])));
+                    }
+                    if let Some(signal) = record.get("signal") {
+                        body.push(("text", signal.clone()));
+                    }
+                    self.connection.event("stopped", Json::object(body));
+                }
+                "exited" => {
+                    self.connection.event(
+                        "exited",
+                        Json::object(vec![("exitCode", field("exit_code"))]),
+                    );
+                    self.connection.event("terminated", Json::object(vec![]));
+                }
+                "signaled" => {
+                    // like a shell, report death by a signal as exit code 128 + signal number
+                    let signal = record
+                        .get("signal")
+                        .and_then(Json::as_str)
+                        .and_then(signals::parse_signal)
+                        .map_or(0, |signal| signal as i64);
+                    self.connection.event(
+                        "exited",
+                        Json::object(vec![("exitCode", Json::Int(128 + signal))]),
+                    );
+                    self.connection.event("terminated", Json::object(vec![]));
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::samples::build_sample;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    /// What the adapter sends, kept to be read once the session is over
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn connection(input: Vec<u8>, output: Box<dyn Write>) -> Connection {
+        Connection {
+            reader: BufReader::new(Box::new(Cursor::new(input))),
+            writer: output,
+            seq: 1,
+        }
+    }
+
+    fn request(seq: i64, command: &str, arguments: Vec<(&str, Json)>) -> String {
+        let body = Json::object(vec![
+            ("seq", Json::Int(seq)),
+            ("type", Json::string("request")),
+            ("command", Json::string(command)),
+            ("arguments", Json::object(arguments)),
+        ])
+        .to_string();
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    /// Run a session with the given requests, returning the messages the adapter sent
+    fn session(requests: &[String]) -> Vec<Json> {
+        interpreter::set_mode(Mode::Dap);
+        let output = Output::default();
+        run(connection(
+            requests.concat().into_bytes(),
+            Box::new(output.clone()),
+        ));
+        let sent = output.0.borrow().clone();
+        let mut sent = connection(sent, Box::new(io::sink()));
+        std::iter::from_fn(|| sent.receive()).collect()
+    }
+
+    fn response<'a>(messages: &'a [Json], command: &str) -> &'a Json {
+        messages
+            .iter()
+            .find(|message| {
+                message.get("type") == Some(&Json::string("response"))
+                    && message.get("command") == Some(&Json::string(command))
+            })
+            .unwrap_or_else(|| panic!("no response to {}", command))
+    }
+
+    #[test]
+    fn scripted_session() {
+        let (program, source) = build_sample("function_calls");
+        let messages = session(&[
+            request(1, "initialize", vec![("adapterID", Json::string("deet"))]),
+            request(2, "launch", vec![("program", Json::string(&program))]),
+            request(
+                3,
+                "setBreakpoints",
+                vec![
+                    (
+                        "source",
+                        Json::object(vec![("path", Json::string(&source))]),
+                    ),
+                    (
+                        "breakpoints",
+                        Json::Array(vec![Json::object(vec![("line", Json::Int(11))])]),
+                    ),
+                ],
+            ),
+            request(4, "configurationDone", vec![]),
+            request(5, "stackTrace", vec![("threadId", Json::Int(1))]),
+            request(6, "disconnect", vec![]),
+        ]);
+        let _ = fs::remove_file(&program);
+
+        for command in &[
+            "initialize",
+            "launch",
+            "setBreakpoints",
+            "configurationDone",
+            "stackTrace",
+            "disconnect",
+        ] {
+            let response = response(&messages, command);
+            assert_eq!(
+                response.get("success"),
+                Some(&Json::Bool(true)),
+                "{}",
+                response
+            );
+        }
+        let capabilities = response(&messages, "initialize").get("body").unwrap();
+        assert_eq!(
+            capabilities.get("supportsConfigurationDoneRequest"),
+            Some(&Json::Bool(true))
+        );
+        assert!(messages
+            .iter()
+            .any(|message| message.get("event") == Some(&Json::string("initialized"))));
+
+        let breakpoints = response(&messages, "setBreakpoints")
+            .get("body")
+            .and_then(|body| body.get("breakpoints"))
+            .and_then(Json::as_array)
+            .unwrap();
+        assert_eq!(breakpoints.len(), 1);
+        assert_eq!(breakpoints[0].get("verified"), Some(&Json::Bool(true)));
+        assert_eq!(breakpoints[0].get("line"), Some(&Json::Int(11)));
+
+        let stopped = messages
+            .iter()
+            .find(|message| message.get("event") == Some(&Json::string("stopped")))
+            .and_then(|message| message.get("body"))
+            .expect("no stopped event");
+        assert_eq!(stopped.get("reason"), Some(&Json::string("breakpoint")));
+
+        let frames = response(&messages, "stackTrace")
+            .get("body")
+            .and_then(|body| body.get("stackFrames"))
+            .and_then(Json::as_array)
+            .unwrap();
+        let names: Vec<&str> = frames
+            .iter()
+            .filter_map(|frame| frame.get("name").and_then(Json::as_str))
+            .collect();
+        assert_eq!(names, vec!["func2", "func1", "main"]);
+        assert_eq!(frames[0].get("line"), Some(&Json::Int(11)));
+    }
+
+    #[test]
+    fn failed_launch_is_reported() {
+        let source = format!("{}/samples/hello.c", env!("CARGO_MANIFEST_DIR"));
+        let messages = session(&[
+            request(1, "launch", vec![("program", Json::string(&source))]),
+            request(2, "threads", vec![]),
+            request(3, "disconnect", vec![]),
+        ]);
+        let launch = response(&messages, "launch");
+        assert_eq!(launch.get("success"), Some(&Json::Bool(false)));
+        assert!(launch
+            .get("message")
+            .and_then(Json::as_str)
+            .unwrap()
+            .contains("hello.c"));
+        // the server keeps serving the client
+        assert_eq!(
+            response(&messages, "threads").get("success"),
+            Some(&Json::Bool(false))
+        );
+        assert_eq!(
+            response(&messages, "disconnect").get("success"),
+            Some(&Json::Bool(true))
+        );
+    }
+}
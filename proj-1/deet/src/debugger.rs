@@ -1,15 +1,42 @@
-use crate::debugger_command::DebuggerCommand;
-use crate::dwarf_data::{DwarfData, Error as DwarfError};
-use crate::inferior::{Inferior, Status};
+use crate::core_dump::{self, CoreDump};
+use crate::debugger_command::{DebuggerCommand, ExamineFormat, ForkMode};
+use crate::disassembler;
+use crate::dwarf_data::{
+    DwarfData, Encoding, Error as DwarfError, Line, Location, Type, TypeKind, Variable,
+};
+use crate::expression::{self, BinaryOperator, Expr, Expression, TypeName, UnaryOperator};
+use crate::inferior::{self, Inferior, Status};
+use crate::interpreter;
+use crate::json::Json;
+use crate::location::{self, Place};
+use crate::shared_library::{self, AT_ENTRY};
+use crate::signals::{self, SignalTable};
+use crate::target::Target;
+use crate::unwind::Frame;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
 
 #[derive(Clone, PartialEq)]
 pub struct BreakPoint {
     id: usize,
     addr: usize,
     orig_byte: u8,
+    enabled: bool,
+    condition: Option<Expression>,
+    hit_count: usize,
+    ignore_count: usize,
+    /// The location the breakpoint was set at, resolved again when the inferior executes a new
+    /// program
+    location: String,
+    /// Commands to run whenever the breakpoint is hit
+    commands: Vec<String>,
 }
 
 impl BreakPoint {
@@ -18,6 +45,12 @@ impl BreakPoint {
             id,
             addr,
             orig_byte: 0,
+            enabled: true,
+            condition: None,
+            hit_count: 0,
+            ignore_count: 0,
+            location: String::new(),
+            commands: Vec::new(),
         }
     }
 
@@ -25,6 +58,14 @@ impl BreakPoint {
         self.addr
     }
 
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn orig_byte(&self) -> u8 {
+        self.orig_byte
+    }
+
     pub fn set_byte(&mut self, orig_byte: u8) {
         self.orig_byte = orig_byte
     }
@@ -38,53 +79,357 @@ impl std::fmt::Display for BreakPoint {
     }
 }
 
+/// A hardware watchpoint, held in one of the debug registers DR0-DR3
+pub struct Watchpoint {
+    id: usize,
+    addr: usize,
+    expression: String,
+    entity_type: Type,
+    value: Vec<u8>,
+    old_value: Vec<u8>,
+    enabled: bool,
+}
+
+/// An expression printed whenever the inferior stops
+struct Display {
+    id: usize,
+    expression: Expression,
+}
+
+/// A value of the inferior, which an expression designates
+struct Lvalue {
+    entity_type: Type,
+    place: Place,
+    /// Offset in bits from the start of place, and width in bits, of a bit field
+    bit_field: Option<(usize, usize)>,
+}
+
+impl Lvalue {
+    /// The number of bytes the value spans
+    fn size(&self) -> usize {
+        match self.bit_field {
+            Some((bit_offset, bit_size)) => (bit_offset + bit_size).div_ceil(8),
+            None => self.entity_type.size,
+        }
+    }
+}
+
+/// The error of using a value that the compiler optimized out
+const OPTIMIZED_OUT: &str = "value has been optimized out";
+
+/// Format a value that may have been optimized out, like gdb does
+fn format_value(entity_type: &Type, bytes: Option<Vec<u8>>) -> String {
+    match bytes {
+        Some(bytes) => entity_type.format_value(&bytes),
+        None => "<optimized out>".to_string(),
+    }
+}
+
+/// A number that an operator of an expression works on
+#[derive(Clone, Copy)]
+enum Number {
+    Integer(i64),
+    Float(f64),
+}
+
+impl Number {
+    fn as_i64(self) -> i64 {
+        match self {
+            Number::Integer(value) => value,
+            Number::Float(value) => value as i64,
+        }
+    }
+
+    fn as_f64(self) -> f64 {
+        match self {
+            Number::Integer(value) => value as f64,
+            Number::Float(value) => value,
+        }
+    }
+
+    fn is_zero(self) -> bool {
+        match self {
+            Number::Integer(value) => value == 0,
+            Number::Float(value) => value == 0.0,
+        }
+    }
+}
+
+/// The integer type of the given size and signedness, int or long
+fn integer_type(size: usize, signed: bool) -> Type {
+    let name = match (size, signed) {
+        (8, true) => "long",
+        (8, false) => "unsigned long",
+        (_, true) => "int",
+        (_, false) => "unsigned int",
+    };
+    expression::base_type(name).unwrap()
+}
+
+fn is_float(entity_type: &Type) -> bool {
+    matches!(entity_type.kind, TypeKind::Base(Encoding::Float))
+}
+
+/// Only float and double values can be computed with; long double can merely be printed
+fn check_float_size(entity_type: &Type) -> Result<(), String> {
+    if is_float(entity_type) && entity_type.size != 4 && entity_type.size != 8 {
+        return Err(format!(
+            "Cannot compute with values of type {}",
+            entity_type.name
+        ));
+    }
+    Ok(())
+}
+
+/// The type an integer operand is promoted to before an operation, following C. Pointers
+/// count as unsigned long.
+fn promote(entity_type: &Type) -> Type {
+    match entity_type.kind {
+        TypeKind::Pointer(_) => integer_type(8, false),
+        _ if entity_type.size < 4 => integer_type(4, true),
+        _ => integer_type(entity_type.size.min(8), entity_type.is_signed()),
+    }
+}
+
+/// The type of the result of an operation on integers of two types, following the usual
+/// arithmetic conversions of C
+fn common_type(left: &Type, right: &Type) -> Type {
+    let (left, right) = (promote(left), promote(right));
+    if left.size > right.size || (left.size == right.size && !left.is_signed()) {
+        left
+    } else {
+        right
+    }
+}
+
+/// Convert a number to a value of the given type, like a cast does
+fn convert(number: Number, entity_type: &Type) -> Number {
+    match entity_type.kind {
+        TypeKind::Base(Encoding::Float) => Number::Float(number.as_f64()),
+        TypeKind::Base(Encoding::Boolean) => Number::Integer(!number.is_zero() as i64),
+        _ => Number::Integer(number.as_i64()),
+    }
+}
+
+/// The little-endian bytes of a number, as a value of the given type
+fn number_bytes(entity_type: &Type, number: Number) -> Vec<u8> {
+    let raw = match convert(number, entity_type) {
+        Number::Float(value) if entity_type.size == 4 => {
+            (value as f32).to_bits().to_le_bytes().to_vec()
+        }
+        Number::Float(value) => value.to_bits().to_le_bytes().to_vec(),
+        Number::Integer(value) => value.to_le_bytes().to_vec(),
+    };
+    let mut bytes = raw;
+    bytes.resize(entity_type.size, 0);
+    bytes
+}
+
+/// A value that an expression computes, which lives nowhere in the inferior
+fn rvalue(entity_type: Type, number: Number) -> Lvalue {
+    Lvalue {
+        place: Place::Value(number_bytes(&entity_type, number)),
+        entity_type,
+        bit_field: None,
+    }
+}
+
+/// The result of a comparison or logical operation, an int that is 1 for true and 0 for false
+fn boolean(value: bool) -> Lvalue {
+    rvalue(integer_type(4, true), Number::Integer(value as i64))
+}
+
+/// A variable as shown to a frontend
+pub struct Value {
+    pub name: String,
+    pub type_name: String,
+    pub value: String,
+}
+
 pub struct Debugger {
     target: String,
     history_path: String,
     readline: Editor<()>,
     inferior: Option<Inferior>,
+    /// A core dump to examine while there is no inferior
+    core: Option<CoreDump>,
     debug_data: DwarfData,
     breakpoints: HashMap<usize, BreakPoint>,
+    next_breakpoint_id: usize,
+    watchpoints: [Option<Watchpoint>; 4],
+    displays: Vec<Display>,
+    next_display_id: usize,
+    /// Level of the frame selected by up, down and frame, counting from the innermost frame
+    selected_frame: usize,
+    /// The source file being listed and the next line that list shows
+    listing: Option<(String, usize)>,
+    /// The format and unit size of the last x command
+    examine_format: (char, usize),
+    /// The address following the memory shown by the last x command
+    next_examine_addr: Option<usize>,
+    signals: SignalTable,
+    /// The signal the inferior last stopped with, delivered to it when it is continued
+    pending_signal: Option<Signal>,
+    follow_fork_mode: ForkMode,
+    /// Whether to detach from the process not followed after a fork, or keep it in the
+    /// background
+    detach_on_fork: bool,
+    /// Number of the current inferior, as listed by info inferiors
+    inferior_id: usize,
+    next_inferior_id: usize,
+    /// Inferiors kept stopped after a fork while another one is debugged
+    background_inferiors: Vec<(usize, Inferior)>,
+    /// A parent we follow the vfork child of, which shares its memory with the child until the
+    /// child calls exec or exits. Only then can its breakpoints be removed to detach from it.
+    vfork_parent: Option<Inferior>,
+    /// Whether the breakpoints were removed from the memory the current inferior shares with a
+    /// vfork child we detached from, and must be inserted again once the child is done with it
+    vfork_breakpoints_removed: bool,
+    /// Number of the thread the inferior last stopped in
+    thread_id: usize,
+    /// The internal breakpoint telling that the dynamic linker changed the list of shared
+    /// libraries: at the entry point of the program until the dynamic linker has run, then at
+    /// r_debug.r_brk
+    solib_breakpoint: Option<usize>,
+    /// Breakpoints whose location does not resolve until a shared library is loaded
+    pending_breakpoints: Vec<BreakPoint>,
+    /// Lines of input from command files and breakpoint commands, run before reading more input
+    queued_commands: VecDeque<String>,
+    /// Whether to read commands from stdin instead of the terminal, without prompting
+    batch: bool,
+    /// The command being run, reported to a frontend once it is done
+    command_line: Option<String>,
+}
+
+/// Flags of the eflags register shown by info registers, by bit
+const EFLAGS: [(u64, &str); 9] = [
+    (0, "CF"),
+    (2, "PF"),
+    (4, "AF"),
+    (6, "ZF"),
+    (7, "SF"),
+    (8, "TF"),
+    (9, "IF"),
+    (10, "DF"),
+    (11, "OF"),
+];
+
+/// Longest string shown by x/s
+const MAX_STRING_LENGTH: usize = 200;
+
+/// Longest x86-64 instruction, in bytes
+const MAX_INSTRUCTION_LENGTH: usize = 15;
+
+/// Most units shown by a single x command
+const MAX_EXAMINE_COUNT: usize = 1 << 16;
+
+/// Number of source lines shown by list
+const LIST_SIZE: usize = 10;
+
+/// The first line of a listing centered on the given line
+fn first_listed_line(center: usize) -> usize {
+    center.saturating_sub(LIST_SIZE / 2).max(1)
+}
+
+/// Format one unit of memory read by x. symbolize names the function an address is in, for the
+/// a format.
+fn format_unit<F>(bytes: &[u8], format: char, symbolize: F) -> String
+where
+    F: Fn(usize) -> String,
+{
+    let mut raw = [0u8; 8];
+    raw[..bytes.len()].copy_from_slice(bytes);
+    let value = u64::from_le_bytes(raw);
+    let shift = 64 - 8 * bytes.len() as u32;
+    let signed = ((value << shift) as i64) >> shift;
+    match format {
+        'd' => signed.to_string(),
+        'u' => value.to_string(),
+        'o' if value == 0 => "0".to_string(),
+        'o' => format!("0{:o}", value),
+        't' => format!("{:0width$b}", value, width = 8 * bytes.len()),
+        'c' => format!("{} '{}'", signed, (value as u8 as char).escape_default()),
+        'a' => format!("{:#x}{}", value, symbolize(value as usize)),
+        _ => format!("0x{:0width$x}", value, width = 2 * bytes.len()),
+    }
 }
 
 fn parse_address(address: &str) -> Option<usize> {
     let address_without_0x = if address.to_lowercase().starts_with("0x") {
         &address[2..]
     } else {
-        &address[..]
+        address
     };
     usize::from_str_radix(address_without_0x, 16).ok()
 }
 
+/// A breakpoint location: *addr, [file:]line or [file:]function
 enum BreakPointType<'a> {
     Raw(&'a str),
-    Line(usize),
-    Func(&'a str),
+    Line(Option<&'a str>, usize),
+    Func(Option<&'a str>, &'a str),
 }
 
-fn get_breakpoint_type(breakpoint: &str) -> BreakPointType {
-    if breakpoint.starts_with('*') {
-        return BreakPointType::Raw(&breakpoint[1..]);
+fn get_breakpoint_type(breakpoint: &str) -> BreakPointType<'_> {
+    if let Some(address) = breakpoint.strip_prefix('*') {
+        return BreakPointType::Raw(address);
     }
-    match usize::from_str_radix(breakpoint, 10) {
-        Ok(line) => BreakPointType::Line(line),
-        Err(_) => BreakPointType::Func(breakpoint),
+    let (file, location) = match breakpoint.rfind(':') {
+        Some(index) => (Some(&breakpoint[..index]), &breakpoint[index + 1..]),
+        None => (None, breakpoint),
+    };
+    match location.parse() {
+        Ok(line) => BreakPointType::Line(file, line),
+        Err(_) => BreakPointType::Func(file, location),
+    }
+}
+
+/// Strip the legacy and REX prefixes of an x86-64 instruction
+fn skip_prefixes(instruction: &[u8]) -> &[u8] {
+    let len = instruction
+        .iter()
+        .take_while(|byte| matches!(byte, 0x66 | 0x67 | 0xf2 | 0xf3 | 0x40..=0x4f))
+        .count();
+    &instruction[len..]
+}
+
+/// Check if the instruction is a near call (`call rel32` or `call r/m64`)
+fn is_call(instruction: &[u8]) -> bool {
+    match skip_prefixes(instruction) {
+        [0xe8, ..] => true,
+        [0xff, modrm, ..] => (modrm >> 3) & 0x7 == 2,
+        _ => false,
     }
 }
 
+/// Check if the instruction is a near return
+fn is_ret(instruction: &[u8]) -> bool {
+    matches!(skip_prefixes(instruction), [0xc3, ..] | [0xc2, ..])
+}
+
+/// Read the lines of a command file
+fn read_script(path: &str) -> io::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(|line| line.to_string())
+        .collect())
+}
+
 impl Debugger {
-    /// Initializes the debugger.
-    pub fn new(target: &str) -> Debugger {
+    /// Initializes the debugger, attaching to the process pid or loading the core dump if given.
+    /// Fails if the target, the process or the core dump cannot be loaded.
+    pub fn new(target: &str, pid: Option<Pid>, core: Option<&str>) -> Result<Debugger, String> {
         // (milestone 3): initialize the DwarfData
         let debug_data = match DwarfData::from_file(target) {
             Ok(val) => val,
             Err(DwarfError::ErrorOpeningFile) => {
-                println!("Could not open file {}", target);
-                std::process::exit(1);
+                return Err(format!("Could not open file {}", target));
             }
             Err(DwarfError::DwarfFormatError(err)) => {
-                println!("Could not debugging symbols from {}: {:?}", target, err);
-                std::process::exit(1);
+                return Err(format!(
+                    "Could not debugging symbols from {}: {:?}",
+                    target, err
+                ));
             }
         };
 
@@ -93,210 +438,3319 @@ impl Debugger {
         // Attempt to load history from ~/.deet_history if it exists
         let _ = readline.load_history(&history_path);
 
-        Debugger {
+        let mut debugger = Debugger {
             target: target.to_string(),
             history_path,
             readline,
             inferior: None,
+            core: None,
             debug_data,
             breakpoints: HashMap::new(),
+            next_breakpoint_id: 1,
+            watchpoints: [None, None, None, None],
+            displays: Vec::new(),
+            next_display_id: 1,
+            selected_frame: 0,
+            listing: None,
+            examine_format: ('x', 4),
+            next_examine_addr: None,
+            signals: SignalTable::new(),
+            pending_signal: None,
+            follow_fork_mode: ForkMode::Parent,
+            detach_on_fork: true,
+            inferior_id: 1,
+            next_inferior_id: 2,
+            background_inferiors: Vec::new(),
+            vfork_parent: None,
+            vfork_breakpoints_removed: false,
+            thread_id: 1,
+            solib_breakpoint: None,
+            pending_breakpoints: Vec::new(),
+            queued_commands: VecDeque::new(),
+            batch: false,
+            command_line: None,
+        };
+        if let Some(pid) = pid {
+            debugger.attach(pid)?;
+        }
+        if let Some(path) = core {
+            debugger.load_core(path)?;
+        }
+        Ok(debugger)
+    }
+
+    /// Load a core dump to examine, and tell how the process it was taken from ended
+    fn load_core(&mut self, path: &str) -> Result<(), String> {
+        let core = match CoreDump::from_file(path) {
+            Ok(core) => core,
+            Err(core_dump::Error::ErrorOpeningFile) => {
+                return Err(format!("Could not open core file {}", path));
+            }
+            Err(core_dump::Error::CoreFormatError(err)) => {
+                return Err(format!("Could not read core file {}: {}", path, err));
+            }
+        };
+        console!("Core was generated by `{}'.", core.command);
+        if let Some(signal) = core.signal {
+            console!(
+                "Program terminated with signal {}, {}.",
+                signal,
+                signals::describe_signal(signal)
+            );
+        }
+        let rip = core.get_registers().unwrap().rip;
+        let signal = core.signal;
+        let entry = core.aux_value(AT_ENTRY);
+        self.core = Some(core);
+        if let Some(entry) = entry {
+            self.relocate_program(entry);
+        }
+        self.update_shared_libraries();
+        self.print_location(rip as usize);
+        let fields = match signal {
+            Some(signal) => vec![("signal", Json::string(signal.as_ref()))],
+            None => vec![],
+        };
+        self.emit_stop("core", fields);
+        Ok(())
+    }
+
+    /// Read commands from stdin rather than from the terminal, for running without a TTY
+    pub fn set_batch(&mut self) {
+        self.batch = true;
+    }
+
+    /// Run the commands of a file after those already queued, before reading any input
+    pub fn queue_script(&mut self, path: &str) {
+        match read_script(path) {
+            Ok(lines) => self.queued_commands.extend(lines),
+            Err(err) => console!("{}: {}.", path, err),
+        }
+    }
+
+    /// Run the commands of a file before those already queued, for the source command
+    fn source(&mut self, path: &str) {
+        match read_script(path) {
+            Ok(lines) => {
+                for line in lines.into_iter().rev() {
+                    self.queued_commands.push_front(line);
+                }
+            }
+            Err(err) => console!("{}: {}.", path, err),
+        }
+    }
+
+    /// Read the commands to run whenever a breakpoint is hit, up to a line saying "end"
+    fn read_breakpoint_commands(&mut self, id: Option<usize>) {
+        let id = id.unwrap_or(self.next_breakpoint_id - 1);
+        if self.breakpoint_entries_mut(id).is_empty() {
+            console!("No breakpoint number {}.", id);
+        }
+        if self.queued_commands.is_empty() && !self.batch && !interpreter::machine_readable() {
+            console!("Type commands for breakpoint(s) {}, one per line.", id);
+            console!("End with a line saying just \"end\".");
+        }
+        let mut commands = Vec::new();
+        while let Some(line) = self.next_line(">") {
+            let line = line.trim();
+            if line == "end" {
+                break;
+            }
+            if !line.is_empty() {
+                commands.push(line.to_string());
+            }
+        }
+        for breakpoint in self.breakpoint_entries_mut(id) {
+            breakpoint.commands = commands.clone();
+        }
+    }
+
+    /// The live inferior, or else the core dump being examined
+    fn process(&self) -> Option<&dyn Target> {
+        match &self.inferior {
+            Some(inferior) => Some(inferior),
+            None => self.core.as_ref().map(|core| core as &dyn Target),
         }
     }
 
+    /// Read and run commands until the user quits
     pub fn run(&mut self) {
         loop {
-            match self.get_next_command() {
-                DebuggerCommand::Run(args) => {
-                    if self.inferior.is_some() {
-                        self.inferior.as_mut().unwrap().kill().unwrap();
-                    }
-                    if let Some(inferior) =
-                        Inferior::new(&self.target, &args, &mut self.breakpoints)
-                    {
-                        // Create the inferior
-                        self.inferior = Some(inferior);
-                        match self.inferior.as_mut().unwrap().continue_run(None).unwrap() {
-                            Status::Exited(exit_code) => {
-                                println!("Child exited (status {})", exit_code);
-                                self.inferior = None;
-                            }
-                            Status::Signaled(singal) => {
-                                println!("Child exited with {}", singal);
-                                self.inferior = None;
-                            }
-                            Status::Stopped(signal, rip) => {
-                                println!("Child stopped with {} at address {:#x}", signal, rip);
-                                let function = self.debug_data.get_function_from_addr(rip);
-                                let line = self.debug_data.get_line_from_addr(rip);
-                                match (function, line) {
-                                    (Some(function), Some(line)) => {
-                                        println!("Stopped at {} ({})", function, line)
-                                    }
-                                    (_, _) => {
-                                        println!("Fail to resolve stopping function and line")
-                                    }
-                                }
-                            }
-                        }
-                    } else {
-                        println!("Error starting subprocess");
+            let command = self.get_next_command();
+            if !self.execute(command) {
+                self.command_done();
+                return;
+            }
+        }
+    }
+
+    /// Run a command, returning false if it is the one to quit
+    pub fn execute(&mut self, command: DebuggerCommand) -> bool {
+        match command {
+            DebuggerCommand::Run(args) => {
+                self.release_inferior();
+                if let Some(inferior) = Inferior::new(&self.target, &args) {
+                    // Create the inferior
+                    self.inferior = Some(inferior);
+                    self.program_loaded();
+                    if let Err(err) = self.install_watchpoints() {
+                        console!("Fail to install watchpoints: {}", err);
                     }
+                    let status = self.resume_inferior();
+                    self.report_status(status);
+                } else {
+                    console!("Error starting subprocess");
                 }
-                DebuggerCommand::Cont => {
-                    if self.inferior.is_none() {
-                        println!("The process is not being run");
-                        continue;
-                    }
-                    // check if stop in breakpoint
-                    let rip = self.inferior.as_ref().unwrap().get_previous_ins().unwrap();
-                    if self.breakpoints.contains_key(&rip) {
-                        println!(
-                            "Previously Stopped at breakpoint: {}\n",
-                            self.breakpoints.get(&rip).unwrap()
-                        );
-                        if !self
-                            .inferior
-                            .as_mut()
-                            .unwrap()
-                            .step_breakpoint(rip, self.breakpoints.get(&rip).unwrap().orig_byte)
-                        {
-                            println!("Failed to step by the breakpoint");
-                            continue;
-                        }
-                    }
-                    match self.inferior.as_mut().unwrap().continue_run(None).unwrap() {
-                        Status::Exited(exit_code) => {
-                            println!("Child exited (status {})", exit_code);
-                            self.inferior = None;
-                        }
-                        Status::Signaled(singal) => {
-                            println!("Child exited with {}", singal);
-                            self.inferior = None;
-                        }
-                        Status::Stopped(signal, rip) => {
-                            println!("Child stopped with {} at address {:#x}", signal, rip);
-                            let function = self.debug_data.get_function_from_addr(rip);
-                            let line = self.debug_data.get_line_from_addr(rip);
-                            match (function, line) {
-                                (Some(function), Some(line)) => {
-                                    println!("Stopped at {} ({})", function, line)
-                                }
-                                (_, _) => {
-                                    println!("Fail to resolve stopping function and line")
-                                }
-                            }
-                        }
+            }
+            DebuggerCommand::Cont => {
+                if self.inferior.is_none() {
+                    console!("The process is not being run");
+                    return true;
+                }
+                // check if stop in breakpoint
+                let rip = self.inferior.as_ref().unwrap().get_registers().unwrap().rip;
+                if let Some(breakpoint) = self.get_breakpoint(rip as usize) {
+                    console!("Previously Stopped at breakpoint: {}\n", breakpoint);
+                }
+                let status = self.resume_inferior();
+                self.report_status(status);
+            }
+            DebuggerCommand::Step => {
+                if self.inferior.is_none() {
+                    console!("The process is not being run");
+                    return true;
+                }
+                let status = self.step_line(true);
+                self.report_status(status);
+            }
+            DebuggerCommand::Next => {
+                if self.inferior.is_none() {
+                    console!("The process is not being run");
+                    return true;
+                }
+                let status = self.step_line(false);
+                self.report_status(status);
+            }
+            DebuggerCommand::StepInstruction(count) => {
+                if self.inferior.is_none() {
+                    console!("The process is not being run");
+                    return true;
+                }
+                let status = self.step_instructions(count, false);
+                self.report_instruction_status(status);
+            }
+            DebuggerCommand::NextInstruction(count) => {
+                if self.inferior.is_none() {
+                    console!("The process is not being run");
+                    return true;
+                }
+                let status = self.step_instructions(count, true);
+                self.report_instruction_status(status);
+            }
+            DebuggerCommand::Finish => {
+                if self.inferior.is_none() {
+                    console!("The process is not being run");
+                    return true;
+                }
+                let rip = self.inferior.as_ref().unwrap().get_registers().unwrap().rip;
+                match self.debug_data.get_function_from_addr(rip as usize) {
+                    Some(function) if function == "main" => {
+                        console!("\"finish\" not meaningful in the outermost frame.");
+                        return true;
                     }
+                    Some(function) => console!("Run till exit from {}", function),
+                    None => console!("Run till exit from {:#x}", rip),
                 }
-                DebuggerCommand::Back => {
-                    if self.inferior.is_none() {
-                        println!("The process is not being run");
-                        continue;
+                let status = self.finish();
+                self.report_status(status);
+            }
+            DebuggerCommand::Up(count) => {
+                if self.process().is_none() {
+                    console!("The process is not being run");
+                    return true;
+                }
+                let level = self.selected_frame + count;
+                match self.frame_count() {
+                    Ok(frames) if self.selected_frame + 1 >= frames => {
+                        console!("Initial frame selected; you cannot go up.")
                     }
-                    self.inferior
-                        .as_mut()
-                        .unwrap()
-                        .print_backtrace(&self.debug_data)
-                        .unwrap();
-                }
-                DebuggerCommand::Break(breakpoint) => {
-                    let breakpoint = match get_breakpoint_type(&breakpoint) {
-                        BreakPointType::Raw(address) => parse_address(address).unwrap(),
-                        // unable to get lines info in dwarf file, don't know why
-                        BreakPointType::Line(line) => {
-                            match self.debug_data.get_addr_for_line(None, line) {
-                                Some(addr) => addr,
-                                None => {
-                                    println!("Failed to find the address of line {}", line);
-                                    continue;
-                                }
-                            }
-                        }
-                        BreakPointType::Func(func) => {
-                            match self.debug_data.get_addr_for_function(None, func) {
-                                Some(addr) => addr,
-                                None => {
-                                    println!("Failed to find the address of function {}", func);
-                                    continue;
-                                }
-                            }
-                        }
-                    };
-                    if !self.breakpoints.contains_key(&breakpoint) {
-                        // add breakpoint to global Hashmap, without knowing the orig_byte
-                        self.breakpoints.insert(
-                            breakpoint,
-                            BreakPoint::new(self.breakpoints.len() + 1, breakpoint),
-                        );
-                        // add breakpoint when process is stopped
-                        if self.inferior.is_some() {
-                            match self.inferior.as_mut().unwrap().write_breakpoint(breakpoint) {
-                                Ok(orig_byte) => self
-                                    .breakpoints
-                                    .get_mut(&breakpoint)
-                                    .unwrap()
-                                    .set_byte(orig_byte),
-                                Err(_) => {
-                                    println!("Fail to insert breakpoint at {:#x}", breakpoint);
-                                    continue;
-                                }
-                            }
-                        }
+                    Ok(frames) => self.select_frame(level.min(frames - 1)),
+                    Err(err) => console!("Fail to unwind the stack: {}", err),
+                }
+            }
+            DebuggerCommand::Down(count) => {
+                if self.process().is_none() {
+                    console!("The process is not being run");
+                    return true;
+                }
+                if self.selected_frame == 0 {
+                    console!("Bottom (innermost) frame selected; you cannot go down.");
+                    return true;
+                }
+                self.select_frame(self.selected_frame.saturating_sub(count));
+            }
+            DebuggerCommand::Frame(level) => {
+                if self.process().is_none() {
+                    console!("The process is not being run");
+                    return true;
+                }
+                self.select_frame(level.unwrap_or(self.selected_frame));
+            }
+            DebuggerCommand::InfoLocals => {
+                if self.process().is_none() {
+                    console!("The process is not being run");
+                    return true;
+                }
+                if let Err(err) = self.print_frame_variables(false) {
+                    console!("Fail to read local variables: {}", err);
+                }
+            }
+            DebuggerCommand::InfoArgs => {
+                if self.process().is_none() {
+                    console!("The process is not being run");
+                    return true;
+                }
+                if let Err(err) = self.print_frame_variables(true) {
+                    console!("Fail to read arguments: {}", err);
+                }
+            }
+            DebuggerCommand::InfoRegisters(names) => {
+                if self.process().is_none() {
+                    console!("The process is not being run");
+                    return true;
+                }
+                if let Err(err) = self.print_registers(&names) {
+                    console!("Fail to read registers: {}", err);
+                }
+            }
+            DebuggerCommand::SetVariable(expression) => {
+                if self.inferior.is_none() {
+                    console!("The process is not being run");
+                    return true;
+                }
+                if let Err(err) = self.set_variable(&expression) {
+                    console!("{}", err);
+                }
+            }
+            DebuggerCommand::Examine(examine_format, address) => {
+                if self.process().is_none() {
+                    console!("The process is not being run");
+                    return true;
+                }
+                self.examine(&examine_format, address.as_deref());
+            }
+            DebuggerCommand::Disassemble(location) => self.disassemble(location.as_deref()),
+            DebuggerCommand::GenerateCore(path) => {
+                if self.inferior.is_none() {
+                    console!("The process is not being run");
+                    return true;
+                }
+                let inferior = self.inferior.as_ref().unwrap();
+                let path = path.unwrap_or_else(|| format!("core.{}", inferior.pid()));
+                match core_dump::generate(inferior, &self.breakpoints, &path) {
+                    Ok(()) => console!("Saved corefile {}", path),
+                    Err(err) => console!("Can't create a corefile {}: {}", path, err),
+                }
+            }
+            DebuggerCommand::SetFollowForkMode(mode) => self.follow_fork_mode = mode,
+            DebuggerCommand::SetDetachOnFork(detach) => self.detach_on_fork = detach,
+            DebuggerCommand::InfoInferiors => self.print_inferiors(),
+            DebuggerCommand::Inferior(id) => self.switch_inferior(id),
+            DebuggerCommand::InfoThreads => self.print_threads(),
+            DebuggerCommand::InfoSharedLibrary => self.print_shared_libraries(),
+            DebuggerCommand::Thread(id) => self.switch_thread(id),
+            DebuggerCommand::InfoSignals(signal) => match signal {
+                Some(name) => match signals::parse_signal(&name) {
+                    Some(signal) => self.signals.print(&[signal]),
+                    None if name.parse::<i32>().is_ok() => {
+                        console!("Only signals 1-31 are valid as numeric signals.")
                     }
-                    println!(
-                        "Set breakpoint {} at {}",
-                        self.breakpoints.get(&breakpoint).as_ref().unwrap().id,
-                        breakpoint
-                    )
-                }
-                DebuggerCommand::Quit => {
-                    if self.inferior.is_some() {
-                        self.inferior.as_mut().unwrap().kill().unwrap();
+                    None => console!("Unrecognized signal: {}", name),
+                },
+                None => self.signals.print(&[]),
+            },
+            DebuggerCommand::Handle(name, keywords) => self.handle_signal(&name, &keywords),
+            DebuggerCommand::List(location) => self.list(location.as_deref()),
+            DebuggerCommand::Print(name) => {
+                if self.process().is_none() {
+                    console!("The process is not being run");
+                    return true;
+                }
+                if let Err(err) = self.print_expression(&name) {
+                    console!("Fail to read variable {}: {}", name, err);
+                }
+            }
+            DebuggerCommand::Back => {
+                if self.process().is_none() {
+                    console!("The process is not being run");
+                    return true;
+                }
+                if let Err(err) = self.process().unwrap().print_backtrace(&self.debug_data) {
+                    console!("Fail to unwind the stack: {}", err);
+                }
+            }
+            DebuggerCommand::Break(breakpoint, condition) => {
+                self.break_at(&breakpoint, condition.as_deref());
+            }
+            DebuggerCommand::Watch(expression) => {
+                if let Err(err) = self.set_watchpoint(&expression) {
+                    console!("Fail to set watchpoint on {}: {}", expression, err);
+                }
+            }
+            DebuggerCommand::Display(expression) => match expression {
+                Some(expression) => self.add_display(&expression),
+                None => self.print_displays(),
+            },
+            DebuggerCommand::Undisplay(id) => self.delete_display(id),
+            DebuggerCommand::InfoDisplay => self.print_display_list(),
+            DebuggerCommand::InfoBreakpoints => self.print_breakpoints(),
+            DebuggerCommand::Commands(id) => self.read_breakpoint_commands(id),
+            DebuggerCommand::Source(path) => self.source(&path),
+            DebuggerCommand::Ignore(id, count) => {
+                let breakpoints = self.breakpoint_entries_mut(id);
+                if breakpoints.is_empty() {
+                    console!("No breakpoint number {}.", id);
+                    return true;
+                }
+                for breakpoint in breakpoints {
+                    breakpoint.ignore_count = count;
+                }
+                console!("Will ignore next {} crossings of breakpoint {}.", count, id);
+            }
+            DebuggerCommand::Delete(id) => {
+                if let Err(err) = self.delete_breakpoint(id) {
+                    console!("Fail to delete breakpoint {}: {}", id, err);
+                }
+            }
+            DebuggerCommand::Disable(id) => {
+                if let Err(err) = self.enable_breakpoint(id, false) {
+                    console!("Fail to disable breakpoint {}: {}", id, err);
+                }
+            }
+            DebuggerCommand::Enable(id) => {
+                if let Err(err) = self.enable_breakpoint(id, true) {
+                    console!("Fail to enable breakpoint {}: {}", id, err);
+                }
+            }
+            DebuggerCommand::Attach(pid) => {
+                if let Err(err) = self.attach(pid) {
+                    console!("{}", err);
+                }
+            }
+            DebuggerCommand::Detach => {
+                if self.inferior.is_none() {
+                    console!("The process is not being run");
+                    return true;
+                }
+                self.detach();
+            }
+            DebuggerCommand::Quit => {
+                self.release_inferior();
+                for (_, mut inferior) in self.background_inferiors.drain(..) {
+                    if inferior.is_attached() {
+                        let _ = inferior.detach(&self.breakpoints);
+                    } else {
+                        let _ = inferior.kill();
                     }
-                    return;
                 }
+                return false;
             }
         }
+        true
     }
 
-    /// This function prompts the user to enter a command, and continues re-prompting until the user
-    /// enters a valid command. It uses DebuggerCommand::from_tokens to do the command parsing.
-    ///
-    /// You don't need to read, understand, or modify this function.
-    fn get_next_command(&mut self) -> DebuggerCommand {
-        loop {
-            // Print prompt and get next line of user input
-            match self.readline.readline("(deet) ") {
-                Err(ReadlineError::Interrupted) => {
-                    // User pressed ctrl+c. We're going to ignore it
-                    println!("Type \"quit\" to exit");
+    /// Attach to a running process, replacing the current inferior
+    fn attach(&mut self, pid: Pid) -> Result<(), String> {
+        self.release_inferior();
+        match Inferior::attach(pid) {
+            Ok(inferior) => {
+                console!("Attaching to process {}", pid);
+                self.inferior = Some(inferior);
+                self.program_loaded();
+                self.selected_frame = 0;
+                self.pending_signal = None;
+                if let Err(err) = self.install_watchpoints() {
+                    console!("Fail to install watchpoints: {}", err);
                 }
-                Err(ReadlineError::Eof) => {
-                    // User pressed ctrl+d, which is the equivalent of "quit" for our purposes
-                    return DebuggerCommand::Quit;
+                let rip = self.inferior.as_ref().unwrap().get_registers().unwrap().rip;
+                self.print_location(rip as usize);
+                self.emit_stop("attach", vec![]);
+                Ok(())
+            }
+            Err(err) => Err(format!("Could not attach to process {}: {}", pid, err)),
+        }
+    }
+
+    /// Remove all breakpoints from the inferior and let it go
+    fn detach(&mut self) {
+        let mut inferior = self.inferior.take().unwrap();
+        match inferior.detach(&self.breakpoints) {
+            Ok(()) => console!("Detaching from process {}", inferior.pid()),
+            Err(err) => console!("Fail to detach from process {}: {}", inferior.pid(), err),
+        }
+    }
+
+    /// Let go of the current inferior: detach from it if it was attached, kill it otherwise
+    fn release_inferior(&mut self) {
+        match &self.inferior {
+            Some(inferior) if inferior.is_attached() => self.detach(),
+            Some(_) => {
+                let mut inferior = self.inferior.take().unwrap();
+                if let Err(err) = inferior.kill() {
+                    console!("Fail to kill process {}: {}", inferior.pid(), err);
                 }
-                Err(err) => {
-                    panic!("Unexpected I/O error: {:?}", err);
+            }
+            None => (),
+        }
+    }
+
+    /// Report the status of the inferior after it was resumed, and drop it if it terminated
+    fn report_status(&mut self, status: Result<Status, nix::Error>) {
+        self.selected_frame = 0;
+        self.pending_signal = None;
+        match status {
+            Ok(Status::Exited(exit_code)) => {
+                console!("Child exited (status {})", exit_code);
+                interpreter::emit("exited", vec![("exit_code", Json::Int(exit_code as i64))]);
+                self.inferior = None;
+                self.release_vfork_parent("exit");
+            }
+            Ok(Status::Signaled(singal)) => {
+                console!("Child exited with {}", singal);
+                interpreter::emit("signaled", vec![("signal", Json::string(singal.as_ref()))]);
+                self.inferior = None;
+                self.release_vfork_parent("exit");
+            }
+            Ok(Status::Execed(rip)) => {
+                self.print_location(rip);
+                self.emit_stop("exec", vec![]);
+            }
+            Ok(Status::Forked(..)) | Ok(Status::VforkDone) => (),
+            Ok(Status::Stopped(signal, rip)) => {
+                self.report_thread_switch();
+                if signal != Signal::SIGTRAP && self.signals.policy(signal).pass {
+                    self.pending_signal = Some(signal);
                 }
-                Ok(line) => {
-                    if line.trim().len() == 0 {
-                        continue;
+                console!("Child stopped with {} at address {:#x}", signal, rip);
+                self.print_location(rip);
+                let breakpoint = self
+                    .get_breakpoint(rip)
+                    .filter(|_| signal == Signal::SIGTRAP)
+                    .map(|breakpoint| breakpoint.id);
+                match breakpoint {
+                    Some(id) => {
+                        self.emit_stop("breakpoint-hit", vec![("breakpoint", Json::Int(id as i64))])
                     }
-                    self.readline.add_history_entry(line.as_str());
-                    if let Err(err) = self.readline.save_history(&self.history_path) {
-                        println!(
-                            "Warning: failed to save history file at {}: {}",
-                            self.history_path, err
-                        );
+                    None if signal == Signal::SIGTRAP => {
+                        self.emit_stop("end-stepping-range", vec![])
                     }
-                    let tokens: Vec<&str> = line.split_whitespace().collect();
-                    if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
-                        return cmd;
-                    } else {
-                        println!("Unrecognized command.");
+                    None => self.emit_stop(
+                        "signal-received",
+                        vec![("signal", Json::string(signal.as_ref()))],
+                    ),
+                }
+                self.print_displays();
+                // the commands of a breakpoint run before any further input
+                if signal == Signal::SIGTRAP {
+                    let commands = self
+                        .get_breakpoint(rip)
+                        .map(|breakpoint| breakpoint.commands.clone())
+                        .unwrap_or_default();
+                    for command in commands.into_iter().rev() {
+                        self.queued_commands.push_front(command);
                     }
                 }
             }
+            Ok(Status::Watchpoint(index, rip)) => {
+                self.report_thread_switch();
+                if let Some(watchpoint) = &self.watchpoints[index] {
+                    console!(
+                        "\nHardware watchpoint {}: {}\n",
+                        watchpoint.id,
+                        watchpoint.expression
+                    );
+                    let entity_type = &watchpoint.entity_type;
+                    console!(
+                        "Old value = {}",
+                        entity_type.format_value(&watchpoint.old_value)
+                    );
+                    console!(
+                        "New value = {}",
+                        entity_type.format_value(&watchpoint.value)
+                    );
+                }
+                self.print_location(rip);
+                if let Some(watchpoint) = &self.watchpoints[index] {
+                    let entity_type = &watchpoint.entity_type;
+                    let fields = vec![
+                        ("watchpoint", Json::Int(watchpoint.id as i64)),
+                        ("expression", Json::string(&watchpoint.expression)),
+                        (
+                            "old_value",
+                            Json::String(entity_type.format_value(&watchpoint.old_value)),
+                        ),
+                        (
+                            "new_value",
+                            Json::String(entity_type.format_value(&watchpoint.value)),
+                        ),
+                    ];
+                    self.emit_stop("watchpoint-trigger", fields);
+                }
+                self.print_displays();
+            }
+            Err(err) => console!("Fail to resume the inferior: {}", err),
+        }
+    }
+
+    /// Send a frontend the stop record for where the inferior or core dump is, with the given
+    /// reason and additional fields
+    fn emit_stop(&self, reason: &str, mut fields: Vec<(&str, Json)>) {
+        if !interpreter::machine_readable() {
+            return;
+        }
+        let regs = match self.process().map(|process| process.get_registers()) {
+            Some(Ok(regs)) => regs,
+            _ => return,
+        };
+        let rip = regs.rip as usize;
+        let line = self.debug_data.get_line_from_addr(rip);
+        let mut record = vec![
+            ("reason", Json::string(reason)),
+            ("address", Json::hex(rip)),
+            (
+                "function",
+                self.debug_data
+                    .get_function_from_addr(rip)
+                    .map_or(Json::Null, Json::String),
+            ),
+            (
+                "file",
+                line.as_ref()
+                    .map_or(Json::Null, |line| Json::string(&line.file)),
+            ),
+            (
+                "line",
+                line.as_ref()
+                    .map_or(Json::Null, |line| Json::Int(line.number as i64)),
+            ),
+        ];
+        if let Some(inferior) = &self.inferior {
+            record.push(("thread", Json::Int(inferior.current_thread().0 as i64)));
+        }
+        record.append(&mut fields);
+        let registers = inferior::REGISTER_NAMES
+            .iter()
+            .filter_map(|name| {
+                inferior::get_register(&regs, name).map(|value| (*name, Json::hex(value as usize)))
+            })
+            .collect();
+        record.push(("registers", Json::object(registers)));
+        interpreter::emit("stopped", record);
+    }
+
+    /// Tell the user when the inferior stopped in another thread than last time
+    fn report_thread_switch(&mut self) {
+        let inferior = self.inferior.as_ref().unwrap();
+        let (id, tid) = inferior.current_thread();
+        if id != self.thread_id && inferior.threads().len() > 1 {
+            console!("[Switching to thread {} (LWP {})]", id, tid);
+        }
+        self.thread_id = id;
+    }
+
+    /// Print the function and source line of an address the inferior stopped at
+    fn print_location(&mut self, rip: usize) {
+        let function = self.debug_data.get_function_from_addr(rip);
+        let line = self.debug_data.get_line_from_addr(rip);
+        match (function, line) {
+            (Some(function), Some(line)) => {
+                console!("Stopped at {} ({})", function, line);
+                self.print_current_line(&line);
+            }
+            (_, _) => console!("Fail to resolve stopping function and line"),
+        }
+    }
+
+    /// Print the source line a frame is executing, and center the next list around it
+    fn print_current_line(&mut self, line: &Line) {
+        self.print_source_lines(&line.file, line.number, line.number);
+        self.listing = Some((line.file.clone(), first_listed_line(line.number)));
+    }
+
+    /// The source line of the selected frame, or the line of main if the process is not running
+    fn default_source_line(&self) -> Option<Line> {
+        match self.process() {
+            Some(_) => {
+                let frame = self.selected_frame().ok()?;
+                self.debug_data.get_line_from_addr(frame.lookup_pc())
+            }
+            None => {
+                let main = self.debug_data.get_addr_for_function(None, "main")?;
+                self.debug_data.get_line_from_addr(main)
+            }
         }
     }
+
+    /// List source lines: the lines after the last listing, the lines around a line number of
+    /// the current source file, or the lines around the start of a function
+    fn list(&mut self, location: Option<&str>) {
+        let current_file = match &self.listing {
+            Some((file, _)) => Some(file.clone()),
+            None => self.default_source_line().map(|line| line.file),
+        };
+        let (file, first) = match location {
+            None => match (&self.listing, self.default_source_line()) {
+                (Some((file, next)), _) => (file.clone(), *next),
+                (None, Some(line)) => (line.file, first_listed_line(line.number)),
+                (None, None) => {
+                    console!("No source file to list.");
+                    return;
+                }
+            },
+            Some(location) => match location.parse::<usize>() {
+                Ok(number) => match current_file {
+                    Some(file) => (file, first_listed_line(number)),
+                    None => {
+                        console!("No source file to list.");
+                        return;
+                    }
+                },
+                Err(_) => {
+                    let line = self
+                        .debug_data
+                        .get_addr_for_function(None, location)
+                        .and_then(|addr| self.debug_data.get_line_from_addr(addr));
+                    match line {
+                        Some(line) => (line.file, first_listed_line(line.number)),
+                        None => {
+                            console!("Function \"{}\" not defined.", location);
+                            return;
+                        }
+                    }
+                }
+            },
+        };
+        self.print_source_lines(&file, first, first + LIST_SIZE - 1);
+    }
+
+    /// Print the lines first..=last of a source file. Lines holding breakpoints are marked with
+    /// "b", and the line of the selected frame with ">".
+    fn print_source_lines(&mut self, file: &str, first: usize, last: usize) {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(err) => {
+                console!("Fail to read source file {}: {}", file, err);
+                return;
+            }
+        };
+        let lines: Vec<&str> = source.lines().collect();
+        if first > lines.len() {
+            console!(
+                "Line number {} out of range; \"{}\" has {} lines.",
+                first,
+                file,
+                lines.len()
+            );
+            return;
+        }
+        let current_line = match self.process() {
+            Some(_) => self.default_source_line(),
+            None => None,
+        };
+        let breakpoint_lines: Vec<usize> = self
+            .breakpoints
+            .values()
+            .filter_map(|breakpoint| self.debug_data.get_line_from_addr(breakpoint.addr))
+            .filter(|line| line.file == file)
+            .map(|line| line.number)
+            .collect();
+        let last = last.min(lines.len());
+        for number in first..=last {
+            let breakpoint_marker = if breakpoint_lines.contains(&number) {
+                'b'
+            } else {
+                ' '
+            };
+            let current_marker = match &current_line {
+                Some(line) if line.file == file && line.number == number => '>',
+                _ => ' ',
+            };
+            console!(
+                "{}{}{:>4}\t{}",
+                breakpoint_marker,
+                current_marker,
+                number,
+                lines[number - 1]
+            );
+        }
+        self.listing = Some((file.to_string(), last + 1));
+    }
+
+    /// Count the frames on the stack of the inferior, up to main
+    fn frame_count(&self) -> Result<usize, nix::Error> {
+        Ok(self.process().unwrap().backtrace(&self.debug_data)?.len())
+    }
+
+    /// The frame selected by up, down and frame
+    fn selected_frame(&self) -> Result<Frame, String> {
+        let mut frames = self
+            .process()
+            .unwrap()
+            .backtrace(&self.debug_data)
+            .map_err(|err| err.to_string())?;
+        if frames.is_empty() {
+            return Err("No stack.".to_string());
+        }
+        let level = self.selected_frame.min(frames.len() - 1);
+        Ok(frames.swap_remove(level))
+    }
+
+    /// Select the frame at the given level and print it
+    fn select_frame(&mut self, level: usize) {
+        let frames = match self.process().unwrap().backtrace(&self.debug_data) {
+            Ok(frames) => frames,
+            Err(err) => {
+                console!("Fail to unwind the stack: {}", err);
+                return;
+            }
+        };
+        match frames.get(level) {
+            Some(frame) => {
+                self.selected_frame = level;
+                console!(
+                    "#{:<3}{}",
+                    level,
+                    inferior::describe_frame(frame, &self.debug_data)
+                );
+                if let Some(line) = self.debug_data.get_line_from_addr(frame.lookup_pc()) {
+                    self.print_current_line(&line);
+                }
+            }
+            None => console!("No frame at level {}.", level),
+        }
+    }
+
+    /// Wake up the inferior until it stops or terminates, delivering the signal it last stopped
+    /// with. Breakpoints whose condition does not hold or that are ignored, watchpoints whose
+    /// value did not change, and signals that the signal table does not stop at, are stepped by
+    /// transparently.
+    fn resume_inferior(&mut self) -> Result<Status, nix::Error> {
+        let mut signal = self.pending_signal.take();
+        loop {
+            match self.continue_inferior(signal.take())? {
+                Status::Stopped(Signal::SIGTRAP, rip) if !self.should_stop_at(rip) => continue,
+                Status::Watchpoint(index, _) if !self.update_watchpoint(index)? => continue,
+                Status::Forked(child, vfork) => self.follow_fork(child, vfork)?,
+                Status::Execed(_) => self.follow_exec()?,
+                Status::VforkDone => self.vfork_done(),
+                Status::Stopped(received, _)
+                    if received != Signal::SIGTRAP && !self.signals.policy(received).stop =>
+                {
+                    let policy = self.signals.policy(received);
+                    if policy.print {
+                        console!(
+                            "Program received signal {}, {}.",
+                            received,
+                            signals::describe_signal(received)
+                        );
+                    }
+                    if policy.pass {
+                        signal = Some(received);
+                    }
+                }
+                status => return Ok(status),
+            }
+        }
+    }
+
+    /// Change how a signal is handled and print its new policy
+    fn handle_signal(&mut self, name: &str, keywords: &[String]) {
+        let signal = match signals::parse_signal(name) {
+            Some(signal) => signal,
+            None => {
+                console!("Unrecognized signal: {}", name);
+                return;
+            }
+        };
+        for keyword in keywords {
+            if !self.signals.handle(signal, keyword) {
+                console!("Unrecognized or ambiguous flag word: \"{}\".", keyword);
+                return;
+            }
+        }
+        self.signals.print(&[signal]);
+    }
+
+    /// Single step the inferior by one instruction, delivering the given signal and following the
+    /// processes it forks on the way. An exec ends the step, since the instruction stepped is
+    /// gone.
+    fn step_inferior(&mut self, mut signal: Option<Signal>) -> Result<Status, nix::Error> {
+        loop {
+            match self
+                .inferior
+                .as_mut()
+                .unwrap()
+                .step_instruction(&self.breakpoints, signal.take())?
+            {
+                Status::Forked(child, vfork) => self.follow_fork(child, vfork)?,
+                Status::VforkDone => self.vfork_done(),
+                Status::Execed(rip) => {
+                    self.follow_exec()?;
+                    return Ok(Status::Execed(rip));
+                }
+                status => return Ok(status),
+            }
+        }
+    }
+
+    /// Take over the child the current inferior forked, then follow either process according to
+    /// follow-fork-mode. The other one is detached from, or kept in the background if
+    /// detach-on-fork is off.
+    fn follow_fork(&mut self, child_pid: Pid, vfork: bool) -> Result<(), nix::Error> {
+        let child = self.inferior.as_mut().unwrap().fork_child(child_pid)?;
+        let parent_pid = self.inferior.as_ref().unwrap().pid();
+        let fork = if vfork { "vfork" } else { "fork" };
+        let child_id = self.next_inferior_id;
+        self.next_inferior_id += 1;
+        match self.follow_fork_mode {
+            ForkMode::Parent if self.detach_on_fork => {
+                // a vfork child shares the memory of the parent, so removing the breakpoints
+                // from it removes them from the parent until the child is done
+                let mut child = child;
+                child.detach(&self.breakpoints)?;
+                self.vfork_breakpoints_removed = vfork;
+                console!(
+                    "[Detaching after {} from child process {}]",
+                    fork,
+                    child_pid
+                );
+            }
+            ForkMode::Parent => {
+                console!("[New inferior {} (process {})]", child_id, child_pid);
+                self.background_inferiors.push((child_id, child));
+            }
+            ForkMode::Child => {
+                console!(
+                    "[Attaching after process {} {} to child process {}]",
+                    parent_pid,
+                    fork,
+                    child_pid
+                );
+                let parent = self.inferior.replace(child).unwrap();
+                let parent_id = std::mem::replace(&mut self.inferior_id, child_id);
+                // debug registers are not inherited by the child
+                self.install_watchpoints()?;
+                if !self.detach_on_fork {
+                    self.background_inferiors.push((parent_id, parent));
+                } else if vfork {
+                    self.vfork_parent = Some(parent);
+                } else {
+                    let mut parent = parent;
+                    parent.detach(&self.breakpoints)?;
+                    console!(
+                        "[Detaching after {} from parent process {}]",
+                        fork,
+                        parent_pid
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The vfork child of the current inferior no longer uses its memory
+    fn vfork_done(&mut self) {
+        if self.vfork_breakpoints_removed {
+            self.vfork_breakpoints_removed = false;
+            self.inferior
+                .as_mut()
+                .unwrap()
+                .install_breakpoints(&mut self.breakpoints);
+        }
+    }
+
+    /// Detach from the parent of the vfork child we followed, now that the child stopped using
+    /// its memory
+    fn release_vfork_parent(&mut self, event: &str) {
+        if let Some(mut parent) = self.vfork_parent.take() {
+            match parent.detach(&self.breakpoints) {
+                Ok(()) => console!(
+                    "[Detaching vfork parent process {} after child {}]",
+                    parent.pid(),
+                    event
+                ),
+                Err(err) => console!("Fail to detach from process {}: {}", parent.pid(), err),
+            }
+        }
+    }
+
+    /// The current inferior replaced its program with exec. Load the debugging information of the
+    /// new program if it is a different one, and set the breakpoints again in the new image.
+    fn follow_exec(&mut self) -> Result<(), nix::Error> {
+        self.release_vfork_parent("exec");
+        let pid = self.inferior.as_ref().unwrap().pid();
+        let path = match fs::read_link(format!("/proc/{}/exe", pid)) {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(_) => self.target.clone(),
+        };
+        console!("process {} is executing new program: {}", pid, path);
+        if fs::canonicalize(&path).ok() != fs::canonicalize(&self.target).ok() {
+            match DwarfData::from_file(&path) {
+                Ok(debug_data) => {
+                    self.debug_data = debug_data;
+                    self.target = path;
+                }
+                Err(err) => console!("Could not load debugging symbols from {}: {:?}", path, err),
+            }
+            self.listing = None;
+        }
+        // the breakpoints vanished along with the old program, and are set again where their
+        // locations are in the new one
+        let breakpoints: Vec<BreakPoint> = self.breakpoints.drain().map(|(_, bp)| bp).collect();
+        for breakpoint in breakpoints.into_iter().filter(|bp| bp.id != 0) {
+            self.pend_breakpoint(breakpoint);
+        }
+        self.program_loaded();
+        Ok(())
+    }
+
+    /// The inferior started the program or we attached to it: move the program to where the
+    /// process loaded it, insert the breakpoints, and find the shared libraries loaded so far
+    fn program_loaded(&mut self) {
+        self.breakpoints.retain(|_, breakpoint| breakpoint.id != 0);
+        self.solib_breakpoint = None;
+        // the libraries of the last process are gone, and may be loaded elsewhere in this one
+        let debug_data = &self.debug_data;
+        let addrs: Vec<usize> = self
+            .breakpoints
+            .keys()
+            .copied()
+            .filter(|addr| debug_data.in_library(*addr))
+            .collect();
+        for addr in addrs {
+            let breakpoint = self.breakpoints.remove(&addr).unwrap();
+            self.pend_breakpoint(breakpoint);
+        }
+        self.debug_data.keep_libraries(&[]);
+        if let Some(entry) = self.inferior.as_ref().unwrap().aux_value(AT_ENTRY) {
+            self.relocate_program(entry);
+        }
+        self.inferior
+            .as_mut()
+            .unwrap()
+            .install_breakpoints(&mut self.breakpoints);
+        self.resolve_pending_breakpoints();
+        self.update_shared_libraries();
+    }
+
+    /// Move the program, and the breakpoints and watchpoints in it, to where the process loaded
+    /// it given its entry point. Only a position-independent executable moves.
+    fn relocate_program(&mut self, entry: usize) {
+        let debug_data = &self.debug_data;
+        let (moved, kept): (Vec<BreakPoint>, Vec<BreakPoint>) = self
+            .breakpoints
+            .drain()
+            .map(|(_, bp)| bp)
+            .partition(|bp| debug_data.in_program(bp.addr));
+        let moved_watchpoints: Vec<bool> = self
+            .watchpoints
+            .iter()
+            .map(|wp| matches!(wp, Some(wp) if debug_data.in_program(wp.addr)))
+            .collect();
+        let delta = self.debug_data.relocate_program(entry);
+        for mut breakpoint in moved {
+            breakpoint.addr = breakpoint.addr.wrapping_add(delta);
+            self.breakpoints.insert(breakpoint.addr, breakpoint);
+        }
+        for breakpoint in kept {
+            self.breakpoints.insert(breakpoint.addr, breakpoint);
+        }
+        for (watchpoint, moved) in self.watchpoints.iter_mut().zip(moved_watchpoints) {
+            if let (Some(watchpoint), true) = (watchpoint, moved) {
+                watchpoint.addr = watchpoint.addr.wrapping_add(delta);
+            }
+        }
+    }
+
+    /// Bring the shared libraries up to date with the list of the dynamic linker: load the
+    /// debugging information of new ones, set the pending breakpoints that resolve in them, and
+    /// make the breakpoints in unloaded ones pending again
+    fn update_shared_libraries(&mut self) {
+        let process = match self.process() {
+            Some(process) => process,
+            None => return,
+        };
+        let r_debug = shared_library::find_r_debug(process, &self.debug_data);
+        let libraries =
+            r_debug.and_then(|r_debug| shared_library::read_libraries(process, r_debug));
+        let event_addr = match (&self.inferior, r_debug) {
+            (Some(inferior), Some(r_debug)) => {
+                shared_library::breakpoint_address(inferior, r_debug)
+            }
+            // the dynamic linker has yet to run, and has loaded the libraries the program needs
+            // once the program reaches its entry point
+            (Some(inferior), None) if self.debug_data.dynamic_section().is_some() => {
+                inferior.aux_value(AT_ENTRY)
+            }
+            _ => None,
+        };
+        if let Some(libraries) = libraries {
+            if let Some(inferior) = self.inferior.as_mut() {
+                inferior.update_unwinder();
+            }
+            let debug_data = &self.debug_data;
+            let addrs: Vec<usize> = self
+                .breakpoints
+                .keys()
+                .copied()
+                .filter(|addr| debug_data.in_library(*addr))
+                .collect();
+            self.debug_data.keep_libraries(&libraries);
+            for addr in addrs {
+                if !self.debug_data.in_library(addr) {
+                    let breakpoint = self.breakpoints.remove(&addr).unwrap();
+                    self.pend_breakpoint(breakpoint);
+                }
+            }
+            for (path, bias) in &libraries {
+                if self.debug_data.has_library(path, *bias) {
+                    continue;
+                }
+                if let Err(err) = self.debug_data.load_library(path, *bias) {
+                    console!("Could not load debugging symbols from {}: {:?}", path, err);
+                }
+            }
+            self.resolve_pending_breakpoints();
+        }
+        if let Some(addr) = event_addr {
+            self.set_solib_breakpoint(addr);
+        }
+    }
+
+    /// Move the internal breakpoint for changes to the list of shared libraries to addr
+    fn set_solib_breakpoint(&mut self, addr: usize) {
+        if self.solib_breakpoint == Some(addr) {
+            return;
+        }
+        let inferior = self.inferior.as_mut().unwrap();
+        if let Some(old_addr) = self.solib_breakpoint.take() {
+            if matches!(self.breakpoints.get(&old_addr), Some(bp) if bp.id == 0) {
+                let breakpoint = self.breakpoints.remove(&old_addr).unwrap();
+                let _ = inferior.write_byte(old_addr, breakpoint.orig_byte);
+            }
+        }
+        // a breakpoint of the user at addr does the job as well
+        if self.breakpoints.contains_key(&addr) {
+            self.solib_breakpoint = Some(addr);
+            return;
+        }
+        if let Ok(orig_byte) = inferior.write_breakpoint(addr) {
+            let mut breakpoint = BreakPoint::new(0, addr);
+            breakpoint.set_byte(orig_byte);
+            self.breakpoints.insert(addr, breakpoint);
+            self.solib_breakpoint = Some(addr);
+        }
+    }
+
+    /// Keep a breakpoint whose locations are gone until its location resolves again
+    fn pend_breakpoint(&mut self, breakpoint: BreakPoint) {
+        if !self
+            .pending_breakpoints
+            .iter()
+            .any(|pending| pending.id == breakpoint.id)
+        {
+            self.pending_breakpoints.push(breakpoint);
+        }
+    }
+
+    /// Set the pending breakpoints whose location resolves now
+    fn resolve_pending_breakpoints(&mut self) {
+        for breakpoint in std::mem::take(&mut self.pending_breakpoints) {
+            let addrs = self
+                .resolve_location(&breakpoint.location)
+                .unwrap_or_default();
+            let mut resolved = false;
+            for addr in addrs {
+                if self.breakpoints.contains_key(&addr) {
+                    continue;
+                }
+                let mut location = BreakPoint {
+                    addr,
+                    ..breakpoint.clone()
+                };
+                if let (Some(inferior), true) = (self.inferior.as_mut(), location.enabled) {
+                    match inferior.write_breakpoint(addr) {
+                        Ok(orig_byte) => location.set_byte(orig_byte),
+                        Err(_) => continue,
+                    }
+                }
+                self.breakpoints.insert(addr, location);
+                resolved = true;
+            }
+            if !resolved {
+                self.pending_breakpoints.push(breakpoint);
+            }
+        }
+    }
+
+    /// List the inferiors, marking the current one with "*"
+    fn print_inferiors(&self) {
+        let mut inferiors: Vec<(usize, &Inferior)> = self
+            .background_inferiors
+            .iter()
+            .map(|(id, inferior)| (*id, inferior))
+            .collect();
+        if let Some(inferior) = &self.inferior {
+            inferiors.push((self.inferior_id, inferior));
+        }
+        inferiors.sort_by_key(|(id, _)| *id);
+        console!("  Num  Description       Executable");
+        if self.inferior.is_none() {
+            console!("* {:<4} {:<17} {}", self.inferior_id, "<null>", self.target);
+        }
+        for (id, inferior) in inferiors {
+            let marker = if id == self.inferior_id { '*' } else { ' ' };
+            let executable = fs::read_link(format!("/proc/{}/exe", inferior.pid()))
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            console!(
+                "{} {:<4} {:<17} {}",
+                marker,
+                id,
+                format!("process {}", inferior.pid()),
+                executable
+            );
+        }
+    }
+
+    /// Make a background inferior the current one
+    fn switch_inferior(&mut self, id: usize) {
+        let index = match self
+            .background_inferiors
+            .iter()
+            .position(|(other, _)| *other == id)
+        {
+            Some(index) => index,
+            None if id == self.inferior_id && self.inferior.is_some() => return,
+            None => {
+                console!("Inferior ID {} not known.", id);
+                return;
+            }
+        };
+        let (id, inferior) = self.background_inferiors.remove(index);
+        if let Some(current) = self.inferior.replace(inferior) {
+            self.background_inferiors.push((self.inferior_id, current));
+        }
+        self.inferior_id = id;
+        self.selected_frame = 0;
+        self.pending_signal = None;
+        console!(
+            "[Switching to inferior {} [process {}]]",
+            id,
+            self.inferior.as_ref().unwrap().pid()
+        );
+        if let Err(err) = self.install_watchpoints() {
+            console!("Fail to install watchpoints: {}", err);
+        }
+        if let Ok(regs) = self.inferior.as_ref().unwrap().get_registers() {
+            self.print_location(regs.rip as usize);
+        }
+    }
+
+    /// List the shared libraries loaded into the process, with where their code is
+    fn print_shared_libraries(&self) {
+        let libraries = self.debug_data.libraries();
+        if libraries.is_empty() {
+            console!("No shared libraries loaded at this time.");
+            return;
+        }
+        console!(
+            "{:<20}{:<20}{:<12}Shared Object Library",
+            "From",
+            "To",
+            "Syms Read"
+        );
+        for library in &libraries {
+            console!(
+                "{:<#20x}{:<#20x}{:<12}{}",
+                library.text_start,
+                library.text_end,
+                if library.has_debug_info {
+                    "Yes"
+                } else {
+                    "Yes (*)"
+                },
+                library.path
+            );
+        }
+        if libraries.iter().any(|library| !library.has_debug_info) {
+            console!("(*): Shared library is missing debugging information.");
+        }
+    }
+
+    /// List the threads of the inferior with the frame each one is in
+    fn print_threads(&self) {
+        let inferior = match &self.inferior {
+            Some(inferior) => inferior,
+            None => {
+                console!("No threads.");
+                return;
+            }
+        };
+        let (current, _) = inferior.current_thread();
+        console!("  Id   Target Id          Frame");
+        for (id, tid) in inferior.threads() {
+            let marker = if id == current { '*' } else { ' ' };
+            let frame = match inferior.thread_frame(tid) {
+                Ok(frame) => inferior::describe_frame(&frame, &self.debug_data),
+                Err(err) => format!("<unavailable: {}>", err),
+            };
+            console!(
+                "{} {:<4} {:<18} {}",
+                marker,
+                id,
+                format!("LWP {}", tid),
+                frame
+            );
+        }
+    }
+
+    /// Make another thread the current one, or tell which one is current
+    fn switch_thread(&mut self, id: Option<usize>) {
+        let inferior = match self.inferior.as_mut() {
+            Some(inferior) => inferior,
+            None => {
+                console!("No thread selected.");
+                return;
+            }
+        };
+        let id = match id {
+            Some(id) => id,
+            None => {
+                let (id, tid) = inferior.current_thread();
+                console!("[Current thread is {} (LWP {})]", id, tid);
+                return;
+            }
+        };
+        if !inferior.select_thread(id) {
+            console!("Invalid thread ID: {}", id);
+            return;
+        }
+        self.thread_id = id;
+        console!(
+            "[Switching to thread {} (LWP {})]",
+            id,
+            inferior.current_thread().1
+        );
+        self.select_frame(0);
+    }
+
+    /// The debugging information of the target
+    pub fn debug_data(&self) -> &DwarfData {
+        &self.debug_data
+    }
+
+    /// The threads of the inferior, by number and thread id
+    pub fn threads(&self) -> Vec<(usize, Pid)> {
+        self.inferior
+            .as_ref()
+            .map_or_else(Vec::new, |inferior| inferior.threads())
+    }
+
+    /// Make a thread the current one and select a frame of it, without printing anything, for a
+    /// frontend that names them in each request
+    pub fn focus(&mut self, thread: usize, level: usize) {
+        if let Some(inferior) = self.inferior.as_mut() {
+            if inferior.select_thread(thread) {
+                self.thread_id = thread;
+            }
+        }
+        self.selected_frame = level;
+    }
+
+    /// The frames of the stack of the current thread, innermost first, or none if there is no
+    /// process
+    pub fn backtrace(&self) -> Result<Vec<Frame>, nix::Error> {
+        match self.process() {
+            Some(process) => process.backtrace(&self.debug_data),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Resolve a breakpoint location to the addresses to set it at, or tell why it does not
+    /// resolve
+    fn resolve_location(&self, location: &str) -> Result<Vec<usize>, String> {
+        let addrs = match get_breakpoint_type(location) {
+            BreakPointType::Raw(address) => match parse_address(address) {
+                Some(addr) => vec![addr],
+                None => return Err(format!("Invalid address {}", address)),
+            },
+            BreakPointType::Line(file, line) => {
+                // a bare line number refers to the file being listed or executed
+                let default_file = match file {
+                    Some(_) => None,
+                    None => match &self.listing {
+                        Some((file, _)) => Some(file.clone()),
+                        None => self.default_source_line().map(|line| line.file),
+                    },
+                };
+                let addrs = self
+                    .debug_data
+                    .get_addrs_for_line(file.or(default_file.as_deref()), line);
+                if addrs.is_empty() {
+                    return Err(format!("Failed to find the address of line {}", location));
+                }
+                addrs
+            }
+            BreakPointType::Func(file, func) => {
+                let addrs = self.debug_data.get_addrs_for_function(file, func);
+                if addrs.is_empty() {
+                    return Err(format!(
+                        "Failed to find the address of function {}",
+                        location
+                    ));
+                }
+                // stop once the arguments are in place, not at the entry point
+                addrs
+                    .into_iter()
+                    .map(|addr| self.debug_data.skip_prologue(addr))
+                    .collect()
+            }
+        };
+        Ok(addrs)
+    }
+
+    /// Set a breakpoint with a condition given in source form, returning its number
+    pub fn break_at(&mut self, location: &str, condition: Option<&str>) -> Option<usize> {
+        let condition = match condition {
+            Some(condition) => match self.parse_expression(condition) {
+                Ok(condition) => Some(condition),
+                Err(err) => {
+                    console!("Invalid breakpoint condition: {}", err);
+                    return None;
+                }
+            },
+            None => None,
+        };
+        self.set_breakpoint(location, condition)
+    }
+
+    /// Set a breakpoint at every address a location resolves to. All of them share the same
+    /// breakpoint number, and are listed, enabled and deleted together. A location that does
+    /// not resolve in a dynamically linked program may be in a shared library it has yet to
+    /// load, and makes a pending breakpoint. Returns the number.
+    fn set_breakpoint(&mut self, location: &str, condition: Option<Expression>) -> Option<usize> {
+        let addrs = match self.resolve_location(location) {
+            Ok(addrs) => addrs,
+            Err(err)
+                if self.debug_data.dynamic_section().is_some() && !location.starts_with('*') =>
+            {
+                let id = self.next_breakpoint_id;
+                self.next_breakpoint_id += 1;
+                let mut breakpoint = BreakPoint::new(id, 0);
+                breakpoint.condition = condition;
+                breakpoint.location = location.to_string();
+                self.pending_breakpoints.push(breakpoint);
+                console!("{}", err);
+                console!("Breakpoint {} ({}) pending.", id, location);
+                return Some(id);
+            }
+            Err(err) => {
+                console!("{}", err);
+                return None;
+            }
+        };
+        // a location that already holds a breakpoint keeps it, with the new condition
+        let new_addrs: Vec<usize> = addrs
+            .iter()
+            .copied()
+            .filter(|addr| !self.breakpoints.contains_key(addr))
+            .collect();
+        if new_addrs.is_empty() {
+            for addr in &addrs {
+                let breakpoint = self.breakpoints.get_mut(addr).unwrap();
+                breakpoint.condition = condition.clone();
+                console!("Set breakpoint {} at {:#x}", breakpoint.id, addr);
+            }
+            return addrs.first().map(|addr| self.breakpoints[addr].id);
+        }
+        let id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+        for &addr in &new_addrs {
+            // add breakpoint to global Hashmap, without knowing the orig_byte
+            let mut breakpoint = BreakPoint::new(id, addr);
+            breakpoint.condition = condition.clone();
+            breakpoint.location = location.to_string();
+            // add breakpoint when process is stopped
+            if let Some(inferior) = self.inferior.as_mut() {
+                match inferior.write_breakpoint(addr) {
+                    Ok(orig_byte) => breakpoint.set_byte(orig_byte),
+                    Err(_) => {
+                        console!("Fail to insert breakpoint at {:#x}", addr);
+                        continue;
+                    }
+                }
+            }
+            self.breakpoints.insert(addr, breakpoint);
+        }
+        if new_addrs.len() == 1 {
+            console!("Set breakpoint {} at {:#x}", id, new_addrs[0]);
+            return Some(id);
+        }
+        console!("Set breakpoint {} at {} locations:", id, new_addrs.len());
+        for addr in new_addrs {
+            console!("  {:#x} {}", addr, self.describe_address(addr));
+        }
+        Some(id)
+    }
+
+    /// Describe the function and source line of a code address
+    fn describe_address(&self, addr: usize) -> String {
+        match (
+            self.debug_data.get_function_from_addr(addr),
+            self.debug_data.get_line_from_addr(addr),
+        ) {
+            (Some(function), Some(line)) => format!("in {} at {}", function, line),
+            (Some(function), None) => format!("in {}", function),
+            (_, _) => String::new(),
+        }
+    }
+
+    /// Check if the inferior should stop at the breakpoint it just hit, counting the hit
+    fn should_stop_at(&mut self, addr: usize) -> bool {
+        if self.solib_breakpoint == Some(addr) {
+            self.update_shared_libraries();
+            if !matches!(self.get_breakpoint(addr), Some(bp) if bp.id != 0) {
+                return false;
+            }
+        }
+        let breakpoint = match self.get_breakpoint(addr) {
+            Some(breakpoint) => breakpoint,
+            None => return true,
+        };
+        if let Some(condition) = &breakpoint.condition {
+            match self.test_condition(condition) {
+                Ok(true) => (),
+                Ok(false) => return false,
+                Err(err) => {
+                    console!(
+                        "Error in testing breakpoint condition {}: {}",
+                        breakpoint.id,
+                        err
+                    );
+                    return true;
+                }
+            }
+        }
+        // the locations of a breakpoint share its hit count and ignore count
+        let id = breakpoint.id;
+        let mut ignored = false;
+        for breakpoint in self.breakpoints.values_mut().filter(|bp| bp.id == id) {
+            breakpoint.hit_count += 1;
+            if breakpoint.ignore_count > 0 {
+                breakpoint.ignore_count -= 1;
+                ignored = true;
+            }
+        }
+        !ignored
+    }
+
+    /// Test a breakpoint condition where the breakpoint is hit, whatever frame is selected
+    fn test_condition(&self, condition: &Expression) -> Result<bool, String> {
+        let frame = self
+            .inferior
+            .as_ref()
+            .unwrap()
+            .current_frame()
+            .map_err(|err| err.to_string())?;
+        let value = self.evaluate(&condition.root, &frame)?;
+        self.is_true(value)
+    }
+
+    /// Wake up the inferior until it stops or terminates, delivering signal to it. If it sits on
+    /// a breakpoint, step by the breakpoint first. If it stops at a breakpoint, rewind rip to the
+    /// breakpoint address.
+    fn continue_inferior(&mut self, signal: Option<Signal>) -> Result<Status, nix::Error> {
+        let inferior = self.inferior.as_mut().unwrap();
+        let rip = inferior.get_registers()?.rip as usize;
+        if let Some(breakpoint) = self.breakpoints.get(&rip).filter(|bp| bp.enabled) {
+            match inferior.step_breakpoint(rip, breakpoint.orig_byte, None)? {
+                Status::Stopped(Signal::SIGTRAP, _) => (),
+                status => return Ok(status),
+            }
+        }
+        match inferior.continue_run(signal)? {
+            Status::Stopped(Signal::SIGTRAP, rip)
+                if self
+                    .breakpoints
+                    .get(&(rip - 1))
+                    .is_some_and(|bp| bp.enabled) =>
+            {
+                inferior.set_rip(rip - 1)?;
+                Ok(Status::Stopped(Signal::SIGTRAP, rip - 1))
+            }
+            status => Ok(status),
+        }
+    }
+
+    /// Read the instruction bytes at addr, with the original bytes in place of breakpoints
+    fn read_instruction(&self, addr: usize) -> Result<[u8; 8], nix::Error> {
+        let word = self.inferior.as_ref().unwrap().read_word(addr)? as u64;
+        let mut instruction = word.to_le_bytes();
+        self.hide_breakpoints(addr, &mut instruction);
+        Ok(instruction)
+    }
+
+    /// Replace the breakpoints in memory read from addr with the original bytes
+    fn hide_breakpoints(&self, addr: usize, bytes: &mut [u8]) {
+        for (offset, byte) in bytes.iter_mut().enumerate() {
+            if let Some(breakpoint) = self.get_breakpoint(addr + offset) {
+                *byte = breakpoint.orig_byte;
+            }
+        }
+    }
+
+    /// Single step the inferior until it reaches another source line, delivering the signal it last
+    /// stopped with. Calls into functions with debugging information are stepped into if
+    /// step_into is set, other calls are run until they return.
+    fn step_line(&mut self, step_into: bool) -> Result<Status, nix::Error> {
+        let mut rip = self.inferior.as_ref().unwrap().get_registers()?.rip as usize;
+        let mut line = match self.debug_data.get_line_from_addr(rip) {
+            Some(line) => line,
+            None => {
+                console!("Single stepping until exit from a function without line information");
+                return self.finish();
+            }
+        };
+        let mut signal = self.pending_signal.take();
+        loop {
+            let instruction = self.read_instruction(rip)?;
+            let status = self.step_inferior(signal.take())?;
+            rip = match status {
+                Status::Stopped(Signal::SIGTRAP, rip) => rip,
+                status => return Ok(status),
+            };
+            let new_line = self.debug_data.get_line_from_addr(rip);
+            if is_call(&instruction) {
+                if let (true, Some(new_line)) = (step_into, &new_line) {
+                    // skip the prologue of the callee, stop at the first line of its body
+                    line = new_line.clone();
+                    continue;
+                }
+                let frame = self.inferior.as_ref().unwrap().get_registers()?.rsp as usize;
+                let return_addr = self.inferior.as_ref().unwrap().read_word(frame)?;
+                match self.run_until_return(return_addr, frame)? {
+                    Status::Stopped(Signal::SIGTRAP, addr) if addr == return_addr => continue,
+                    status => return Ok(status),
+                }
+            }
+            match new_line {
+                // returned into the middle of the caller's line, finish that line
+                Some(new_line) if is_ret(&instruction) => line = new_line,
+                Some(new_line) if new_line.file != line.file || new_line.number != line.number => {
+                    return Ok(status)
+                }
+                Some(_) => (),
+                // returned out of main
+                None if is_ret(&instruction) => return self.resume_inferior(),
+                None => (),
+            }
+        }
+    }
+
+    /// Single step count instructions, delivering the signal the inferior last stopped with. Calls
+    /// are run until they return if step_over_calls is set.
+    fn step_instructions(
+        &mut self,
+        count: usize,
+        step_over_calls: bool,
+    ) -> Result<Status, nix::Error> {
+        let rip = self.inferior.as_ref().unwrap().get_registers()?.rip as usize;
+        let mut status = Status::Stopped(Signal::SIGTRAP, rip);
+        let mut signal = self.pending_signal.take();
+        for _ in 0..count {
+            let rip = self.inferior.as_ref().unwrap().get_registers()?.rip as usize;
+            let instruction = self.read_instruction(rip)?;
+            status = self.step_inferior(signal.take())?;
+            match status {
+                Status::Stopped(Signal::SIGTRAP, _) => (),
+                status => return Ok(status),
+            }
+            if step_over_calls && is_call(&instruction) {
+                let frame = self.inferior.as_ref().unwrap().get_registers()?.rsp as usize;
+                let return_addr = self.inferior.as_ref().unwrap().read_word(frame)?;
+                status = self.run_until_return(return_addr, frame)?;
+                match status {
+                    Status::Stopped(Signal::SIGTRAP, addr) if addr == return_addr => (),
+                    status => return Ok(status),
+                }
+            }
+        }
+        Ok(status)
+    }
+
+    /// Report the status of the inferior after stepping by instructions, followed by the
+    /// instruction it stopped at
+    fn report_instruction_status(&mut self, status: Result<Status, nix::Error>) {
+        self.report_status(status);
+        if self.inferior.is_none() {
+            return;
+        }
+        if let Ok(regs) = self.inferior.as_ref().unwrap().get_registers() {
+            let rip = regs.rip as usize;
+            self.print_instructions(rip, rip + MAX_INSTRUCTION_LENGTH, 1);
+        }
+    }
+
+    /// Run the inferior until the function whose return address is stored at frame returns to
+    /// return_addr
+    fn run_until_return(&mut self, return_addr: usize, frame: usize) -> Result<Status, nix::Error> {
+        // insert a temporary breakpoint at the return address, in place of a disabled one
+        let temporary = self.get_breakpoint(return_addr).is_none();
+        let disabled = if temporary {
+            self.breakpoints.remove(&return_addr)
+        } else {
+            None
+        };
+        if temporary {
+            let orig_byte = self
+                .inferior
+                .as_mut()
+                .unwrap()
+                .write_breakpoint(return_addr)?;
+            let mut breakpoint = BreakPoint::new(0, return_addr);
+            breakpoint.set_byte(orig_byte);
+            self.breakpoints.insert(return_addr, breakpoint);
+        }
+        let thread = self.inferior.as_ref().unwrap().current_thread();
+        let status = loop {
+            match self.resume_inferior() {
+                // a recursive call of the function returned, or another thread hit the
+                // breakpoint, keep going
+                Ok(Status::Stopped(Signal::SIGTRAP, rip))
+                    if temporary
+                        && rip == return_addr
+                        && (self.inferior.as_ref().unwrap().current_thread() != thread
+                            || self.inferior.as_ref().unwrap().get_registers()?.rsp as usize
+                                <= frame) => {}
+                status => break status,
+            }
+        };
+        if temporary {
+            // the breakpoint is gone if the inferior executed a new program meanwhile
+            let breakpoint = self.breakpoints.remove(&return_addr);
+            if let (Ok(Status::Stopped(..)), Some(breakpoint))
+            | (Ok(Status::Watchpoint(..)), Some(breakpoint)) = (&status, breakpoint)
+            {
+                self.inferior
+                    .as_mut()
+                    .unwrap()
+                    .write_byte(return_addr, breakpoint.orig_byte)?;
+            }
+        }
+        if let Some(breakpoint) = disabled {
+            self.breakpoints.insert(return_addr, breakpoint);
+        }
+        status
+    }
+
+    /// Get the enabled breakpoint at addr
+    fn get_breakpoint(&self, addr: usize) -> Option<&BreakPoint> {
+        self.breakpoints
+            .get(&addr)
+            .filter(|breakpoint| breakpoint.enabled)
+    }
+
+    /// Find the addresses of all locations of the breakpoint with the given id
+    fn find_breakpoint(&self, id: usize) -> Vec<usize> {
+        let mut addrs: Vec<usize> = self
+            .breakpoints
+            .values()
+            .filter(|breakpoint| breakpoint.id == id && id != 0)
+            .map(|breakpoint| breakpoint.addr)
+            .collect();
+        addrs.sort();
+        addrs
+    }
+
+    /// All locations of the breakpoint with the given id, or the breakpoint itself if it is
+    /// pending
+    fn breakpoint_entries_mut(&mut self, id: usize) -> Vec<&mut BreakPoint> {
+        self.breakpoints
+            .values_mut()
+            .chain(self.pending_breakpoints.iter_mut())
+            .filter(|breakpoint| breakpoint.id == id && id != 0)
+            .collect()
+    }
+
+    /// Find the debug register holding the watchpoint with the given id
+    fn find_watchpoint(&self, id: usize) -> Option<usize> {
+        self.watchpoints
+            .iter()
+            .position(|watchpoint| watchpoint.as_ref().is_some_and(|wp| wp.id == id))
+    }
+
+    /// The condition, counts and commands of a breakpoint, as listed by info breakpoints
+    fn describe_breakpoint_settings(breakpoint: &BreakPoint) -> String {
+        let mut settings = String::new();
+        if let Some(condition) = &breakpoint.condition {
+            settings += &format!("\n\tstop only if {}", condition);
+        }
+        if breakpoint.hit_count > 0 {
+            settings += &format!(
+                "\n\tbreakpoint already hit {} time(s)",
+                breakpoint.hit_count
+            );
+        }
+        if breakpoint.ignore_count > 0 {
+            settings += &format!(
+                "\n\tWill ignore next {} crossings of breakpoint.",
+                breakpoint.ignore_count
+            );
+        }
+        for command in &breakpoint.commands {
+            settings += &format!("\n        {}", command);
+        }
+        settings
+    }
+
+    /// List all breakpoints and watchpoints ordered by id
+    fn print_breakpoints(&self) {
+        let mut entries = Vec::new();
+        let mut ids: Vec<usize> = self
+            .breakpoints
+            .values()
+            .map(|bp| bp.id)
+            .filter(|id| *id != 0)
+            .collect();
+        ids.sort();
+        ids.dedup();
+        for id in ids {
+            let addrs = self.find_breakpoint(id);
+            let breakpoint = &self.breakpoints[&addrs[0]];
+            let mut entry = if addrs.len() == 1 {
+                format!(
+                    "{:<8}{:<15}{:<4}{:<#20x}{}",
+                    breakpoint.id,
+                    "breakpoint",
+                    if breakpoint.enabled { "y" } else { "n" },
+                    breakpoint.addr,
+                    self.describe_address(breakpoint.addr)
+                )
+            } else {
+                let enabled = addrs.iter().any(|addr| self.breakpoints[addr].enabled);
+                format!(
+                    "{:<8}{:<15}{:<4}{:<20}",
+                    breakpoint.id,
+                    "breakpoint",
+                    if enabled { "y" } else { "n" },
+                    "<MULTIPLE>"
+                )
+            };
+            entry += &Debugger::describe_breakpoint_settings(breakpoint);
+            if addrs.len() > 1 {
+                for (index, addr) in addrs.iter().enumerate() {
+                    let location = &self.breakpoints[addr];
+                    entry += &format!(
+                        "\n{:<23}{:<4}{:<#20x}{}",
+                        format!("{}.{}", id, index + 1),
+                        if location.enabled { "y" } else { "n" },
+                        addr,
+                        self.describe_address(*addr)
+                    );
+                }
+            }
+            entries.push((id, entry));
+        }
+        for breakpoint in &self.pending_breakpoints {
+            let entry = format!(
+                "{:<8}{:<15}{:<4}{:<20}{}{}",
+                breakpoint.id,
+                "breakpoint",
+                if breakpoint.enabled { "y" } else { "n" },
+                "<PENDING>",
+                breakpoint.location,
+                Debugger::describe_breakpoint_settings(breakpoint)
+            );
+            entries.push((breakpoint.id, entry));
+        }
+        for watchpoint in self.watchpoints.iter().flatten() {
+            let entry = format!(
+                "{:<8}{:<15}{:<4}{:<#20x}{}",
+                watchpoint.id,
+                "hw watchpoint",
+                if watchpoint.enabled { "y" } else { "n" },
+                watchpoint.addr,
+                watchpoint.expression
+            );
+            entries.push((watchpoint.id, entry));
+        }
+        if entries.is_empty() {
+            console!("No breakpoints or watchpoints.");
+            return;
+        }
+        entries.sort_by_key(|(id, _)| *id);
+        console!(
+            "{:<8}{:<15}{:<4}{:<20}What",
+            "Num",
+            "Type",
+            "Enb",
+            "Address"
+        );
+        for (_, entry) in entries {
+            console!("{}", entry);
+        }
+    }
+
+    /// Remove a breakpoint or watchpoint, restoring the original instruction or the debug
+    /// registers in the running inferior
+    fn delete_breakpoint(&mut self, id: usize) -> Result<(), nix::Error> {
+        if let Some(index) = self.find_watchpoint(id) {
+            self.watchpoints[index] = None;
+            console!("Deleted watchpoint {}", id);
+            return self.install_watchpoints();
+        }
+        let addrs = self.find_breakpoint(id);
+        let pending = self.pending_breakpoints.len();
+        self.pending_breakpoints
+            .retain(|breakpoint| breakpoint.id != id);
+        if addrs.is_empty() && self.pending_breakpoints.len() == pending {
+            console!("No breakpoint number {}.", id);
+            return Ok(());
+        }
+        for addr in addrs {
+            let breakpoint = self.breakpoints.remove(&addr).unwrap();
+            if let (Some(inferior), true) = (self.inferior.as_mut(), breakpoint.enabled) {
+                inferior.write_byte(addr, breakpoint.orig_byte)?;
+            }
+        }
+        console!("Deleted breakpoint {}", id);
+        Ok(())
+    }
+
+    /// Enable or disable a breakpoint or watchpoint, patching the running inferior accordingly
+    fn enable_breakpoint(&mut self, id: usize, enabled: bool) -> Result<(), nix::Error> {
+        if let Some(index) = self.find_watchpoint(id) {
+            self.watchpoints[index].as_mut().unwrap().enabled = enabled;
+            return self.install_watchpoints();
+        }
+        let addrs = self.find_breakpoint(id);
+        let mut pending = false;
+        for breakpoint in self.pending_breakpoints.iter_mut().filter(|bp| bp.id == id) {
+            breakpoint.enabled = enabled;
+            pending = true;
+        }
+        if addrs.is_empty() && !pending {
+            console!("No breakpoint number {}.", id);
+            return Ok(());
+        }
+        for addr in addrs {
+            let breakpoint = self.breakpoints.get_mut(&addr).unwrap();
+            if breakpoint.enabled == enabled {
+                continue;
+            }
+            if let Some(inferior) = self.inferior.as_mut() {
+                if enabled {
+                    breakpoint.orig_byte = inferior.write_breakpoint(addr)?;
+                } else {
+                    inferior.write_byte(addr, breakpoint.orig_byte)?;
+                }
+            }
+            breakpoint.enabled = enabled;
+        }
+        Ok(())
+    }
+
+    /// Set a hardware watchpoint on a variable, or on the word at *addr
+    fn set_watchpoint(&mut self, expression: &str) -> Result<(), nix::Error> {
+        let (addr, entity_type) = if let Some(address) = expression.strip_prefix('*') {
+            match parse_address(address) {
+                Some(addr) => (
+                    addr,
+                    Type::new("long".to_string(), 8, TypeKind::Base(Encoding::Signed)),
+                ),
+                None => {
+                    console!("Invalid address {}", address);
+                    return Ok(());
+                }
+            }
+        } else {
+            let frame = match &self.inferior {
+                Some(_) => match self.selected_frame() {
+                    Ok(frame) => Some(frame),
+                    Err(err) => {
+                        console!("{}", err);
+                        return Ok(());
+                    }
+                },
+                None => None,
+            };
+            let pc = frame.as_ref().map_or(0, |frame| frame.lookup_pc());
+            let var = match self.debug_data.get_variable(pc, expression) {
+                Some(var) => var,
+                None => {
+                    console!("No symbol \"{}\" in current context.", expression);
+                    return Ok(());
+                }
+            };
+            let addr = match (&frame, &var.location) {
+                (_, Location::Address(addr)) => *addr,
+                (Some(frame), _) => match self.locate_variable(var, frame) {
+                    Ok(Place::Memory(addr)) => addr,
+                    Ok(_) => {
+                        console!("Cannot watch {}: it is not in memory", expression);
+                        return Ok(());
+                    }
+                    Err(err) => {
+                        console!("{}", err);
+                        return Ok(());
+                    }
+                },
+                (None, _) => {
+                    console!("The process is not being run");
+                    return Ok(());
+                }
+            };
+            (addr, var.entity_type.clone())
+        };
+        let size = entity_type.size;
+        if ![1, 2, 4, 8].contains(&size) || addr % size != 0 {
+            console!(
+                "Cannot watch {}: only aligned values of 1, 2, 4 or 8 bytes can be watched",
+                expression
+            );
+            return Ok(());
+        }
+        let index = match self.watchpoints.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                console!(
+                    "All {} hardware watchpoints are in use",
+                    self.watchpoints.len()
+                );
+                return Ok(());
+            }
+        };
+        self.watchpoints[index] = Some(Watchpoint {
+            id: self.next_breakpoint_id,
+            addr,
+            expression: expression.to_string(),
+            entity_type,
+            value: Vec::new(),
+            old_value: Vec::new(),
+            enabled: true,
+        });
+        console!(
+            "Hardware watchpoint {}: {}",
+            self.next_breakpoint_id,
+            expression
+        );
+        self.next_breakpoint_id += 1;
+        self.install_watchpoints()
+    }
+
+    /// Write the enabled watchpoints into the debug registers of the inferior, and record the
+    /// current values they watch
+    fn install_watchpoints(&mut self) -> Result<(), nix::Error> {
+        let inferior = match self.inferior.as_mut() {
+            Some(inferior) => inferior,
+            None => return Ok(()),
+        };
+        let mut control = 0;
+        for (index, watchpoint) in self.watchpoints.iter_mut().enumerate() {
+            match watchpoint.as_mut().filter(|watchpoint| watchpoint.enabled) {
+                Some(watchpoint) => {
+                    let size = watchpoint.entity_type.size;
+                    watchpoint.value = inferior.read_bytes(watchpoint.addr, size)?;
+                    inferior.write_debug_register(index, watchpoint.addr)?;
+                    // DR7: local enable bit, then break on data writes (R/W = 01) of LEN bytes
+                    let len = match size {
+                        1 => 0b00,
+                        2 => 0b01,
+                        8 => 0b10,
+                        _ => 0b11,
+                    };
+                    control |= 1 << (index * 2);
+                    control |= (0b01 | len << 2) << (16 + index * 4);
+                }
+                None => inferior.write_debug_register(index, 0)?,
+            }
+        }
+        inferior.write_debug_register(7, control)
+    }
+
+    /// Record the new value of a triggered watchpoint. Returns false if the value did not change.
+    fn update_watchpoint(&mut self, index: usize) -> Result<bool, nix::Error> {
+        let watchpoint = match self.watchpoints[index].as_mut() {
+            Some(watchpoint) => watchpoint,
+            None => return Ok(true),
+        };
+        let value = self
+            .inferior
+            .as_ref()
+            .unwrap()
+            .read_bytes(watchpoint.addr, watchpoint.entity_type.size)?;
+        if value == watchpoint.value {
+            return Ok(false);
+        }
+        watchpoint.old_value = std::mem::replace(&mut watchpoint.value, value);
+        Ok(true)
+    }
+
+    /// Compute the canonical frame address of a frame, i.e. the value of rsp before the call
+    /// instruction that entered its function
+    fn canonical_frame_address(&self, frame: &Frame) -> Result<usize, nix::Error> {
+        if let Some(cfa) = frame.cfa {
+            return Ok(cfa);
+        }
+        // without call frame information, assume the function keeps a frame pointer. Only the
+        // innermost frame can lack it, since unwinding stops at such a frame.
+        let regs = self.process().unwrap().get_registers()?;
+        let rip = regs.rip as usize;
+        // the frame pointer is not set up until "push rbp; mov rbp, rsp" in the prologue has run
+        let function_addr = self.debug_data.get_function(rip).map(|func| func.address);
+        Ok(match function_addr {
+            Some(function_addr) => {
+                let mut push_rbp = function_addr;
+                // skip endbr64
+                if self.read_instruction(push_rbp)?[..4] == [0xf3, 0x0f, 0x1e, 0xfa] {
+                    push_rbp += 4;
+                }
+                if rip <= push_rbp {
+                    regs.rsp as usize + 8
+                } else if rip == push_rbp + 1 {
+                    regs.rsp as usize + 16
+                } else {
+                    regs.rbp as usize + 16
+                }
+            }
+            None => regs.rbp as usize + 16,
+        })
+    }
+
+    /// Run the inferior until the current function returns to its caller
+    fn finish(&mut self) -> Result<Status, nix::Error> {
+        // the return address is pushed right below the canonical frame address
+        let inferior = self.inferior.as_ref().unwrap();
+        let frame = self.canonical_frame_address(&inferior.current_frame()?)? - 8;
+        let return_addr = inferior.read_word(frame)?;
+        self.run_until_return(return_addr, frame)
+    }
+
+    /// What location expressions need to know about a frame to find variables in it
+    fn location_context<'a>(&'a self, frame: &'a Frame) -> location::Context<'a> {
+        let cfa = self.canonical_frame_address(frame).ok();
+        let mut context = location::Context {
+            target: self.process().unwrap(),
+            frame,
+            cfa,
+            frame_base: cfa,
+        };
+        // DW_AT_frame_base is usually DW_OP_call_frame_cfa, but may be a register such as rbp
+        let frame_base = self
+            .debug_data
+            .get_function(frame.lookup_pc())
+            .and_then(|function| function.frame_base.as_ref());
+        if let Some(frame_base) = frame_base {
+            context.frame_base = match location::locate(frame_base, &context, 8) {
+                Ok(Place::Memory(addr)) => Some(addr),
+                Ok(Place::Value(bytes)) => {
+                    let mut raw = [0; 8];
+                    raw.copy_from_slice(&bytes[..8]);
+                    Some(usize::from_le_bytes(raw))
+                }
+                _ => None,
+            };
+        }
+        context
+    }
+
+    /// Find where a variable is in the given frame
+    fn locate_variable(&self, var: &Variable, frame: &Frame) -> Result<Place, String> {
+        location::locate(
+            &var.location,
+            &self.location_context(frame),
+            var.entity_type.size,
+        )
+    }
+
+    /// Read the bytes of a variable in the given frame. Returns None if the variable was
+    /// optimized out.
+    fn read_variable(&self, var: &Variable, frame: &Frame) -> Result<Option<Vec<u8>>, String> {
+        self.read_lvalue(&Lvalue {
+            entity_type: var.entity_type.clone(),
+            place: self.locate_variable(var, frame)?,
+            bit_field: None,
+        })
+    }
+
+    /// Parse an expression, telling the names of types apart from those of variables with the
+    /// debugging information
+    fn parse_expression(&self, text: &str) -> Result<Expression, String> {
+        Expression::parse(text, &|name| self.debug_data.get_type(name).is_some())
+    }
+
+    /// Evaluate an expression as seen from the given frame. Assignments are left to
+    /// evaluate_expression, as they change the inferior.
+    fn evaluate(&self, expr: &Expr, frame: &Frame) -> Result<Lvalue, String> {
+        match expr {
+            Expr::Integer {
+                value,
+                unsigned,
+                long,
+            } => {
+                // literals are never negative, so a negative value did not fit in a long
+                let unsigned = *unsigned || *value < 0;
+                let fits = if unsigned {
+                    *value as u64 <= u32::MAX as u64
+                } else {
+                    *value <= i32::MAX as i64
+                };
+                let size = if *long || !fits { 8 } else { 4 };
+                Ok(rvalue(
+                    integer_type(size, !unsigned),
+                    Number::Integer(*value),
+                ))
+            }
+            Expr::Float(value) => Ok(rvalue(
+                expression::base_type("double").unwrap(),
+                Number::Float(*value),
+            )),
+            Expr::Char(value) => Ok(rvalue(
+                expression::base_type("char").unwrap(),
+                Number::Integer(*value as i8 as i64),
+            )),
+            Expr::Variable(name) => self.evaluate_variable(name, frame),
+            Expr::Register(name) => self.evaluate_register(name),
+            Expr::Unary(operator, operand) => {
+                let operand = self.evaluate(operand, frame)?;
+                self.unary(*operator, operand)
+            }
+            Expr::Binary(BinaryOperator::And, left, right) => Ok(boolean(
+                self.is_true(self.evaluate(left, frame)?)?
+                    && self.is_true(self.evaluate(right, frame)?)?,
+            )),
+            Expr::Binary(BinaryOperator::Or, left, right) => Ok(boolean(
+                self.is_true(self.evaluate(left, frame)?)?
+                    || self.is_true(self.evaluate(right, frame)?)?,
+            )),
+            Expr::Binary(operator, left, right) => {
+                let left = self.evaluate(left, frame)?;
+                let right = self.evaluate(right, frame)?;
+                self.binary(*operator, left, right)
+            }
+            Expr::Conditional(condition, value, otherwise) => {
+                if self.is_true(self.evaluate(condition, frame)?)? {
+                    self.evaluate(value, frame)
+                } else {
+                    self.evaluate(otherwise, frame)
+                }
+            }
+            Expr::Assign(..) => {
+                Err("An assignment must be the outermost operation of an expression.".to_string())
+            }
+            Expr::Member(value, name) => {
+                let value = self.evaluate(value, frame)?;
+                self.member(value, name)
+            }
+            Expr::Index(value, index) => {
+                let value = self.evaluate(value, frame)?;
+                match self.number(self.evaluate(index, frame)?)? {
+                    (_, Number::Integer(index)) => self.subscript(value, index),
+                    (_, Number::Float(_)) => Err("Invalid array index.".to_string()),
+                }
+            }
+            Expr::Cast(type_name, value) => {
+                let entity_type = self.resolve_type(type_name)?;
+                let value = self.evaluate(value, frame)?;
+                self.cast(entity_type, value)
+            }
+            Expr::SizeofType(type_name) => {
+                let entity_type = self.resolve_type(type_name)?;
+                Ok(self.size_of(&entity_type))
+            }
+            Expr::SizeofValue(value) => {
+                let value = self.evaluate(value, frame)?;
+                Ok(self.size_of(&value.entity_type))
+            }
+        }
+    }
+
+    /// A variable visible from the given frame, or else an enumerator
+    fn evaluate_variable(&self, name: &str, frame: &Frame) -> Result<Lvalue, String> {
+        if let Some(var) = self.debug_data.get_variable(frame.lookup_pc(), name) {
+            return Ok(Lvalue {
+                entity_type: var.entity_type.clone(),
+                place: self.locate_variable(var, frame)?,
+                bit_field: None,
+            });
+        }
+        match self.debug_data.get_enumerator(name) {
+            Some((entity_type, value)) => Ok(rvalue(entity_type.clone(), Number::Integer(value))),
+            None => Err(format!("No symbol \"{}\" in current context.", name)),
+        }
+    }
+
+    /// A register of the current thread. Like in gdb, $rip is a pointer to code, $rsp and $rbp
+    /// are untyped pointers and the other registers are longs.
+    fn evaluate_register(&self, name: &str) -> Result<Lvalue, String> {
+        let regs = self
+            .process()
+            .unwrap()
+            .get_registers()
+            .map_err(|err| err.to_string())?;
+        let value =
+            inferior::get_register(&regs, name).ok_or(format!("Invalid register `{}'", name))?;
+        let entity_type = match name {
+            "rip" | "pc" => Type::pointer_to(Some(Type::new(
+                "void ()".to_string(),
+                1,
+                TypeKind::Function,
+            ))),
+            "rsp" | "sp" | "rbp" | "fp" => Type::pointer_to(None),
+            _ => integer_type(8, true),
+        };
+        Ok(rvalue(entity_type, Number::Integer(value as i64)))
+    }
+
+    /// The type a type name in a cast or in sizeof designates
+    fn resolve_type(&self, type_name: &TypeName) -> Result<Type, String> {
+        let base = match type_name.base.as_str() {
+            "void" => None,
+            name => Some(
+                expression::base_type(name)
+                    .or_else(|| self.debug_data.get_type(name).cloned())
+                    .ok_or(format!("No symbol \"{}\" in current context.", name))?,
+            ),
+        };
+        if type_name.pointers == 0 {
+            return base.ok_or_else(|| "Invalid cast.".to_string());
+        }
+        let mut entity_type = Type::pointer_to(base);
+        for _ in 1..type_name.pointers {
+            entity_type = Type::pointer_to(Some(entity_type));
+        }
+        Ok(entity_type)
+    }
+
+    /// The size of a type, as an unsigned long like sizeof yields
+    fn size_of(&self, entity_type: &Type) -> Lvalue {
+        let size = self.debug_data.complete_type(entity_type).size;
+        rvalue(integer_type(8, false), Number::Integer(size as i64))
+    }
+
+    /// The number an operand of an arithmetic, comparison or logical operator holds, along
+    /// with its type. An array decays into a pointer to its first element.
+    fn number(&self, lvalue: Lvalue) -> Result<(Type, Number), String> {
+        let entity_type = self.debug_data.complete_type(&lvalue.entity_type).clone();
+        match &entity_type.kind {
+            TypeKind::Array(element, _) => match lvalue.place {
+                Place::Memory(addr) => Ok((
+                    Type::pointer_to(Some(element.as_ref().clone())),
+                    Number::Integer(addr as i64),
+                )),
+                _ => Err("Attempt to take address of value not located in memory.".to_string()),
+            },
+            TypeKind::Struct(_) | TypeKind::Function => {
+                Err("Argument to arithmetic operation not a number or boolean.".to_string())
+            }
+            _ => {
+                check_float_size(&entity_type)?;
+                let bytes = self.read_lvalue(&lvalue)?.ok_or(OPTIMIZED_OUT)?;
+                let number = match entity_type.kind {
+                    TypeKind::Base(Encoding::Float) => {
+                        let mut raw = [0u8; 8];
+                        raw[..bytes.len()].copy_from_slice(&bytes);
+                        if entity_type.size == 4 {
+                            let mut raw32 = [0u8; 4];
+                            raw32.copy_from_slice(&raw[..4]);
+                            Number::Float(f32::from_le_bytes(raw32) as f64)
+                        } else {
+                            Number::Float(f64::from_le_bytes(raw))
+                        }
+                    }
+                    _ => Number::Integer(
+                        entity_type
+                            .integer_value(&bytes)
+                            .ok_or("Argument to arithmetic operation not a number or boolean.")?,
+                    ),
+                };
+                Ok((entity_type, number))
+            }
+        }
+    }
+
+    /// Whether a value counts as true in a condition
+    fn is_true(&self, value: Lvalue) -> Result<bool, String> {
+        Ok(!self.number(value)?.1.is_zero())
+    }
+
+    fn unary(&self, operator: UnaryOperator, operand: Lvalue) -> Result<Lvalue, String> {
+        match operator {
+            UnaryOperator::Dereference => self.dereference(operand),
+            UnaryOperator::AddressOf => match (&operand.place, operand.bit_field) {
+                (Place::Memory(addr), None) => Ok(rvalue(
+                    Type::pointer_to(Some(operand.entity_type.clone())),
+                    Number::Integer(*addr as i64),
+                )),
+                _ => Err("Attempt to take address of value not located in memory.".to_string()),
+            },
+            _ => {
+                let (entity_type, number) = self.number(operand)?;
+                match (operator, number) {
+                    (UnaryOperator::Not, number) => Ok(boolean(number.is_zero())),
+                    (UnaryOperator::Negate, Number::Float(value)) => {
+                        Ok(rvalue(entity_type, Number::Float(-value)))
+                    }
+                    (UnaryOperator::Plus, Number::Float(_)) => Ok(rvalue(entity_type, number)),
+                    (_, Number::Float(_)) => Err("Integer only operation.".to_string()),
+                    (UnaryOperator::Negate, Number::Integer(value)) => Ok(rvalue(
+                        promote(&entity_type),
+                        Number::Integer(value.wrapping_neg()),
+                    )),
+                    (UnaryOperator::BitNot, Number::Integer(value)) => {
+                        Ok(rvalue(promote(&entity_type), Number::Integer(!value)))
+                    }
+                    (_, number) => Ok(rvalue(promote(&entity_type), number)),
+                }
+            }
+        }
+    }
+
+    /// Apply a binary operator. Adding an integer to a pointer, or subtracting two pointers,
+    /// counts in elements like in C.
+    fn binary(
+        &self,
+        operator: BinaryOperator,
+        left: Lvalue,
+        right: Lvalue,
+    ) -> Result<Lvalue, String> {
+        let (left_type, left) = self.number(left)?;
+        let (right_type, right) = self.number(right)?;
+        let is_pointer = |entity_type: &Type| matches!(entity_type.kind, TypeKind::Pointer(_));
+        let element_size = |pointer: &Type| match &pointer.kind {
+            TypeKind::Pointer(Some(pointee)) => self.debug_data.complete_type(pointee).size.max(1),
+            _ => 1,
+        };
+        match (operator, is_pointer(&left_type), is_pointer(&right_type)) {
+            (BinaryOperator::Sub, true, true) => {
+                let difference =
+                    left.as_i64().wrapping_sub(right.as_i64()) / element_size(&left_type) as i64;
+                return Ok(rvalue(integer_type(8, true), Number::Integer(difference)));
+            }
+            (BinaryOperator::Add, true, false)
+            | (BinaryOperator::Sub, true, false)
+            | (BinaryOperator::Add, false, true) => {
+                let (pointer_type, pointer, offset) = if is_pointer(&left_type) {
+                    (left_type, left, right)
+                } else {
+                    (right_type, right, left)
+                };
+                if let Number::Float(_) = offset {
+                    return Err(
+                        "Argument to arithmetic operation not a number or boolean.".to_string()
+                    );
+                }
+                let mut offset = offset
+                    .as_i64()
+                    .wrapping_mul(element_size(&pointer_type) as i64);
+                if operator == BinaryOperator::Sub {
+                    offset = offset.wrapping_neg();
+                }
+                let addr = pointer.as_i64().wrapping_add(offset);
+                return Ok(rvalue(pointer_type, Number::Integer(addr)));
+            }
+            _ => (),
+        }
+
+        let floating = is_float(&left_type) || is_float(&right_type);
+        let ordering = if floating {
+            left.as_f64().partial_cmp(&right.as_f64())
+        } else if common_type(&left_type, &right_type).is_signed() {
+            Some(left.as_i64().cmp(&right.as_i64()))
+        } else {
+            Some((left.as_i64() as u64).cmp(&(right.as_i64() as u64)))
+        };
+        let truth = match operator {
+            BinaryOperator::Lt => Some(ordering == Some(Ordering::Less)),
+            BinaryOperator::Le => Some(matches!(
+                ordering,
+                Some(Ordering::Less) | Some(Ordering::Equal)
+            )),
+            BinaryOperator::Gt => Some(ordering == Some(Ordering::Greater)),
+            BinaryOperator::Ge => Some(matches!(
+                ordering,
+                Some(Ordering::Greater) | Some(Ordering::Equal)
+            )),
+            BinaryOperator::Eq => Some(ordering == Some(Ordering::Equal)),
+            BinaryOperator::Ne => Some(ordering != Some(Ordering::Equal)),
+            BinaryOperator::And => Some(!left.is_zero() && !right.is_zero()),
+            BinaryOperator::Or => Some(!left.is_zero() || !right.is_zero()),
+            _ => None,
+        };
+        if let Some(truth) = truth {
+            return Ok(boolean(truth));
+        }
+
+        if floating {
+            let (left, right) = (left.as_f64(), right.as_f64());
+            let value = match operator {
+                BinaryOperator::Add => left + right,
+                BinaryOperator::Sub => left - right,
+                BinaryOperator::Mul => left * right,
+                BinaryOperator::Div => left / right,
+                _ => return Err("Integer only operation.".to_string()),
+            };
+            return Ok(rvalue(
+                expression::base_type("double").unwrap(),
+                Number::Float(value),
+            ));
+        }
+        let result_type = match operator {
+            BinaryOperator::Shl | BinaryOperator::Shr => promote(&left_type),
+            _ => common_type(&left_type, &right_type),
+        };
+        let signed = result_type.is_signed();
+        let (left, right) = (left.as_i64(), right.as_i64());
+        let value = match operator {
+            BinaryOperator::Add => left.wrapping_add(right),
+            BinaryOperator::Sub => left.wrapping_sub(right),
+            BinaryOperator::Mul => left.wrapping_mul(right),
+            BinaryOperator::Div | BinaryOperator::Mod if right == 0 => {
+                return Err("Division by zero".to_string())
+            }
+            BinaryOperator::Div if signed => left.wrapping_div(right),
+            BinaryOperator::Div => ((left as u64) / (right as u64)) as i64,
+            BinaryOperator::Mod if signed => left.wrapping_rem(right),
+            BinaryOperator::Mod => ((left as u64) % (right as u64)) as i64,
+            BinaryOperator::Shl => left.wrapping_shl(right as u32),
+            BinaryOperator::Shr if signed => left.wrapping_shr(right as u32),
+            BinaryOperator::Shr => (left as u64).wrapping_shr(right as u32) as i64,
+            BinaryOperator::BitAnd => left & right,
+            BinaryOperator::BitXor => left ^ right,
+            BinaryOperator::BitOr => left | right,
+            _ => unreachable!(),
+        };
+        Ok(rvalue(result_type, Number::Integer(value)))
+    }
+
+    /// Convert a value to the given type. A structure can only be cast to its own type.
+    fn cast(&self, entity_type: Type, value: Lvalue) -> Result<Lvalue, String> {
+        let complete = self.debug_data.complete_type(&entity_type);
+        if let TypeKind::Struct(_) | TypeKind::Array(..) | TypeKind::Function = complete.kind {
+            if complete.name != self.debug_data.complete_type(&value.entity_type).name {
+                return Err("Invalid cast.".to_string());
+            }
+            return Ok(Lvalue {
+                entity_type,
+                ..value
+            });
+        }
+        check_float_size(complete)?;
+        let (_, number) = self.number(value)?;
+        Ok(rvalue(entity_type, number))
+    }
+
+    /// Evaluate an expression as seen from the given frame, carrying out the assignment it
+    /// makes, if any: x = 5 writes to the memory of the inferior and $rax = 0 to its registers
+    fn evaluate_expression(
+        &mut self,
+        expression: &Expression,
+        frame: &Frame,
+    ) -> Result<Lvalue, String> {
+        let (target, value) = match &expression.root {
+            Expr::Assign(target, value) => (target, value),
+            root => return self.evaluate(root, frame),
+        };
+        let value = self.evaluate(value, frame)?;
+        if let Expr::Register(name) = target.as_ref() {
+            let (_, number) = self.number(value)?;
+            self.set_register(name, number.as_i64())?;
+        } else {
+            let target = self.evaluate(target, frame)?;
+            self.assign(&target, value)?;
+        }
+        self.evaluate(target, frame)
+    }
+
+    /// Write a value, converted to the type of the lvalue, into the memory the lvalue designates
+    fn assign(&mut self, target: &Lvalue, value: Lvalue) -> Result<(), String> {
+        let addr = match target.place {
+            Place::Memory(addr) => addr,
+            _ => return Err("Left operand of assignment is not an lvalue.".to_string()),
+        };
+        let entity_type = self.debug_data.complete_type(&target.entity_type).clone();
+        let mut bytes = match entity_type.kind {
+            TypeKind::Struct(_) | TypeKind::Array(..) | TypeKind::Function => {
+                if self.debug_data.complete_type(&value.entity_type).name != entity_type.name {
+                    return Err("Invalid cast.".to_string());
+                }
+                self.read_lvalue(&value)?.ok_or(OPTIMIZED_OUT)?
+            }
+            _ => {
+                check_float_size(&entity_type)?;
+                let (_, number) = self.number(value)?;
+                number_bytes(&entity_type, number)
+            }
+        };
+        let size = target.size();
+        if let Some((bit_offset, bit_size)) = target.bit_field {
+            // keep the bits around the bit field
+            let old = self
+                .process()
+                .unwrap()
+                .read_bytes(addr, size)
+                .map_err(|_| format!("Cannot access memory at address {:#x}", addr))?;
+            let mut raw = [0u8; 8];
+            raw[..size.min(8)].copy_from_slice(&old[..size.min(8)]);
+            let mut new = [0u8; 8];
+            new[..bytes.len().min(8)].copy_from_slice(&bytes[..bytes.len().min(8)]);
+            let mask = match bit_size {
+                64 => !0,
+                _ => (1u64 << bit_size) - 1,
+            } << bit_offset;
+            let word = (u64::from_le_bytes(raw) & !mask)
+                | ((u64::from_le_bytes(new) << bit_offset) & mask);
+            bytes = word.to_le_bytes()[..size.min(8)].to_vec();
+        }
+        self.inferior
+            .as_mut()
+            .unwrap()
+            .write_bytes(addr, &bytes)
+            .map_err(|_| format!("Cannot access memory at address {:#x}", addr))
+    }
+
+    /// Evaluate an expression for its side effect, like set var x = 5
+    fn set_variable(&mut self, text: &str) -> Result<(), String> {
+        let frame = self.selected_frame()?;
+        let expression = self.parse_expression(text)?;
+        self.evaluate_expression(&expression, &frame).map(|_| ())
+    }
+
+    /// Read the bytes of a value, shifting a bit field into place. Returns None if the value was
+    /// optimized out.
+    fn read_lvalue(&self, lvalue: &Lvalue) -> Result<Option<Vec<u8>>, String> {
+        let size = lvalue.size();
+        let bytes = match &lvalue.place {
+            Place::Memory(addr) => self
+                .process()
+                .unwrap()
+                .read_bytes(*addr, size)
+                .map_err(|_| format!("Cannot access memory at address {:#x}", addr))?,
+            Place::Value(bytes) => {
+                let mut bytes = bytes.clone();
+                bytes.resize(size, 0);
+                bytes
+            }
+            Place::OptimizedOut => return Ok(None),
+        };
+        Ok(Some(match lvalue.bit_field {
+            Some((bit_offset, bit_size)) => lvalue
+                .entity_type
+                .extract_bits(&bytes, bit_offset, bit_size),
+            None => bytes,
+        }))
+    }
+
+    /// The value a pointer points to, or the first element of an array
+    fn dereference(&self, lvalue: Lvalue) -> Result<Lvalue, String> {
+        let (pointee, place) = match &lvalue.entity_type.kind {
+            TypeKind::Pointer(Some(pointee)) => {
+                let bytes = self.read_lvalue(&lvalue)?.ok_or(OPTIMIZED_OUT)?;
+                let addr = lvalue.entity_type.integer_value(&bytes).unwrap_or(0) as usize;
+                (pointee.as_ref(), Place::Memory(addr))
+            }
+            TypeKind::Array(element, _) => (element.as_ref(), lvalue.place),
+            _ => return Err("Attempt to take contents of a non-pointer value.".to_string()),
+        };
+        Ok(Lvalue {
+            entity_type: self.debug_data.complete_type(pointee).clone(),
+            place,
+            bit_field: None,
+        })
+    }
+
+    /// An element of an array, or the value index elements away from where a pointer points
+    fn subscript(&self, lvalue: Lvalue, index: i64) -> Result<Lvalue, String> {
+        if !matches!(
+            lvalue.entity_type.kind,
+            TypeKind::Pointer(Some(_)) | TypeKind::Array(..)
+        ) {
+            return Err(format!(
+                "cannot subscript something of type `{}'",
+                lvalue.entity_type.name
+            ));
+        }
+        let element = self.dereference(lvalue)?;
+        let size = element.entity_type.size;
+        Ok(Lvalue {
+            place: element
+                .place
+                .offset(index.wrapping_mul(size as i64) as isize, size)?,
+            ..element
+        })
+    }
+
+    /// A member of a structure or union
+    fn member(&self, lvalue: Lvalue, name: &str) -> Result<Lvalue, String> {
+        // like gdb, accept . on a pointer to a structure
+        let lvalue = match lvalue.entity_type.kind {
+            TypeKind::Pointer(Some(_)) => self.dereference(lvalue)?,
+            _ => lvalue,
+        };
+        let entity_type = self.debug_data.complete_type(&lvalue.entity_type);
+        if !matches!(entity_type.kind, TypeKind::Struct(_)) {
+            return Err(format!(
+                "Attempt to extract a component of a value that is not a structure{}.",
+                if lvalue.entity_type.name.is_empty() {
+                    String::new()
+                } else {
+                    format!(" (type `{}')", lvalue.entity_type.name)
+                }
+            ));
+        }
+        let (offset, member) = entity_type
+            .member(name)
+            .ok_or(format!("There is no member named {}.", name))?;
+        let mut value = Lvalue {
+            entity_type: member.member_type.clone(),
+            place: Place::OptimizedOut,
+            bit_field: member.bit_field,
+        };
+        value.place = lvalue
+            .place
+            .offset((offset + member.offset) as isize, value.size())?;
+        Ok(value)
+    }
+
+    /// Print the value of an expression as seen from the selected frame. Like in gdb, an
+    /// assignment in the expression changes the inferior.
+    fn print_expression(&mut self, expression: &str) -> Result<(), String> {
+        let frame = self.selected_frame()?;
+        let value = self
+            .parse_expression(expression)
+            .and_then(|parsed| self.read_expression(&parsed, &frame));
+        match value {
+            Ok((entity_type, bytes)) => console!(
+                "({}) {} = {}",
+                entity_type.name,
+                expression,
+                format_value(&entity_type, bytes)
+            ),
+            Err(err) => console!("{}", err),
+        }
+        Ok(())
+    }
+
+    /// Evaluate an expression and read the value it yields, along with its type. Returns None
+    /// for the bytes if the value was optimized out.
+    fn read_expression(
+        &mut self,
+        expression: &Expression,
+        frame: &Frame,
+    ) -> Result<(Type, Option<Vec<u8>>), String> {
+        let lvalue = self.evaluate_expression(expression, frame)?;
+        let bytes = self.read_lvalue(&lvalue)?;
+        Ok((lvalue.entity_type, bytes))
+    }
+
+    /// Register an expression to print on every stop, and print it right away if there is
+    /// something to evaluate it in. Like in gdb, an expression that does not evaluate then is
+    /// rejected.
+    fn add_display(&mut self, text: &str) {
+        let expression = match self.parse_expression(text) {
+            Ok(expression) => expression,
+            Err(err) => {
+                console!("{}", err);
+                return;
+            }
+        };
+        if self.process().is_some() {
+            // an assignment is only carried out once the display is registered
+            let checked = match &expression.root {
+                Expr::Assign(target, _) => target.as_ref(),
+                root => root,
+            };
+            let value = self
+                .selected_frame()
+                .and_then(|frame| self.evaluate(checked, &frame).map(|_| ()));
+            if let Err(err) = value {
+                console!("{}", err);
+                return;
+            }
+        }
+        self.displays.push(Display {
+            id: self.next_display_id,
+            expression,
+        });
+        self.next_display_id += 1;
+        if self.process().is_some() {
+            self.print_display(self.displays.len() - 1);
+        }
+    }
+
+    /// Print the display at the given index of the list, as seen from the selected frame
+    fn print_display(&mut self, index: usize) {
+        let frame = match self.selected_frame() {
+            Ok(frame) => frame,
+            Err(err) => {
+                console!("Fail to read registers: {}", err);
+                return;
+            }
+        };
+        let expression = self.displays[index].expression.clone();
+        let value = match self.read_expression(&expression, &frame) {
+            Ok((entity_type, bytes)) => format_value(&entity_type, bytes),
+            Err(err) => format!("<error: {}>", err),
+        };
+        console!("{}: {} = {}", self.displays[index].id, expression, value);
+    }
+
+    /// Print every display, as done whenever the inferior stops
+    fn print_displays(&mut self) {
+        if self.process().is_none() {
+            return;
+        }
+        for index in 0..self.displays.len() {
+            self.print_display(index);
+        }
+    }
+
+    /// Delete a display, or all of them
+    fn delete_display(&mut self, id: Option<usize>) {
+        match id {
+            Some(id) => match self.displays.iter().position(|display| display.id == id) {
+                Some(index) => {
+                    self.displays.remove(index);
+                }
+                None => console!("No display number {}.", id),
+            },
+            None => self.displays.clear(),
+        }
+    }
+
+    /// List the displays, like info display
+    fn print_display_list(&self) {
+        if self.displays.is_empty() {
+            console!("There are no auto-display expressions now.");
+            return;
+        }
+        console!("Auto-display expressions now in effect:");
+        console!("Num Enb Expression");
+        for display in &self.displays {
+            console!("{}:   y  {}", display.id, display.expression);
+        }
+    }
+
+    /// The name, type and value of the local variables, or the arguments, of the function of the
+    /// selected frame. Returns None if there is no debugging information for the function.
+    pub fn frame_variables(&self, parameters: bool) -> Result<Option<Vec<Value>>, String> {
+        let frame = self.selected_frame()?;
+        let function = match self.debug_data.get_function(frame.lookup_pc()) {
+            Some(function) => function,
+            None => return Ok(None),
+        };
+        let mut variables = Vec::new();
+        for var in function
+            .variables
+            .iter()
+            .filter(|var| var.is_parameter == parameters)
+        {
+            // like gdb, a variable that cannot be read does not hide the others
+            let value = match self.read_variable(var, &frame) {
+                Ok(bytes) => format_value(&var.entity_type, bytes),
+                Err(err) => format!("<error: {}>", err),
+            };
+            variables.push(Value {
+                name: var.name.clone(),
+                type_name: var.entity_type.name.clone(),
+                value,
+            });
+        }
+        Ok(Some(variables))
+    }
+
+    /// Print the local variables, or the arguments, of the function of the selected frame
+    fn print_frame_variables(&self, parameters: bool) -> Result<(), String> {
+        let variables = match self.frame_variables(parameters)? {
+            Some(variables) => variables,
+            None => {
+                console!("No symbol table info available.");
+                return Ok(());
+            }
+        };
+        for var in &variables {
+            console!("{} = {}", var.name, var.value);
+        }
+        if variables.is_empty() {
+            console!(
+                "{}",
+                if parameters {
+                    "No arguments."
+                } else {
+                    "No locals."
+                }
+            );
+        }
+        Ok(())
+    }
+
+    /// Describe the function a code address belongs to, as " <func+offset>"
+    fn symbolize(&self, addr: usize) -> String {
+        match self.debug_data.get_function(addr) {
+            Some(func) if addr == func.address => format!(" <{}>", func.name),
+            Some(func) => format!(" <{}+{}>", func.name, addr - func.address),
+            None => String::new(),
+        }
+    }
+
+    /// Read the code between start and end: from the memory of the inferior, with the original
+    /// bytes in place of breakpoints, from the core dump, or from the executable if there is no
+    /// process
+    fn read_code(&self, start: usize, end: usize) -> Option<Vec<u8>> {
+        match (&self.inferior, &self.core) {
+            (Some(inferior), _) => {
+                let mut bytes = inferior.read_bytes(start, end - start).ok()?;
+                self.hide_breakpoints(start, &mut bytes);
+                Some(bytes)
+            }
+            (None, Some(core)) => core.read_bytes(start, end - start).ok(),
+            (None, None) => self
+                .debug_data
+                .read_text(start, end - start)
+                .map(|bytes| bytes.to_vec()),
+        }
+    }
+
+    /// Disassemble at most count instructions between start and end. Instructions holding a
+    /// breakpoint are marked with "b", and the instruction of the selected frame with "=>".
+    /// Returns the address following the last instruction printed.
+    fn print_instructions(&self, start: usize, end: usize, count: usize) -> usize {
+        let bytes = match self.read_code(start, end) {
+            Some(bytes) => bytes,
+            None => {
+                console!("Cannot access memory at address {:#x}", start);
+                return start;
+            }
+        };
+        let current_pc = match self.process() {
+            Some(_) => self.selected_frame().ok().map(|frame| frame.pc),
+            None => None,
+        };
+        let mut offset = 0;
+        for _ in 0..count {
+            if offset >= bytes.len() {
+                break;
+            }
+            let addr = start + offset;
+            let instruction = disassembler::decode(&bytes[offset..], addr);
+            let breakpoint_marker = if self.breakpoints.contains_key(&addr) {
+                'b'
+            } else {
+                ' '
+            };
+            let current_marker = if current_pc == Some(addr) { "=>" } else { "  " };
+            let target = match instruction.target {
+                Some(target) => self.symbolize(target),
+                None => String::new(),
+            };
+            console!(
+                "{}{} {:#018x}{}:\t{}{}",
+                breakpoint_marker,
+                current_marker,
+                addr,
+                self.symbolize(addr),
+                instruction.text,
+                target
+            );
+            offset += instruction.length;
+        }
+        start + offset
+    }
+
+    /// Evaluate a code address given to disassemble: a number, a register ($pc) or the name of
+    /// a function
+    fn evaluate_code_address(&self, expression: &str) -> Option<usize> {
+        if let Some(value) = expression::parse_constant(expression) {
+            return Some(value as usize);
+        }
+        if let (true, Some(process)) = (expression.starts_with('$'), self.process()) {
+            let name = match &expression[1..] {
+                "pc" => "rip",
+                name => name,
+            };
+            let regs = process.get_registers().ok()?;
+            return inferior::get_register(&regs, name).map(|value| value as usize);
+        }
+        self.debug_data
+            .get_addrs_for_function(None, expression)
+            .first()
+            .copied()
+    }
+
+    /// Disassemble the function containing the pc of the selected frame, a function or an
+    /// address given by name, or a range given as start,end or start,+length
+    fn disassemble(&self, location: Option<&str>) {
+        let evaluate = |expression: &str| {
+            let addr = self.evaluate_code_address(expression);
+            if addr.is_none() {
+                console!("No symbol \"{}\" in current context.", expression);
+            }
+            addr
+        };
+        let (start, end) = match location {
+            Some(location) if location.contains(',') => {
+                let index = location.find(',').unwrap();
+                let start = match evaluate(&location[..index]) {
+                    Some(start) => start,
+                    None => return,
+                };
+                let end = &location[index + 1..];
+                let end = if let Some(length) = end.strip_prefix('+') {
+                    let end = expression::parse_constant(length)
+                        .and_then(|length| usize::try_from(length).ok())
+                        .and_then(|length| start.checked_add(length));
+                    match end {
+                        Some(end) => end,
+                        None => {
+                            console!("Invalid length {}", length);
+                            return;
+                        }
+                    }
+                } else {
+                    match evaluate(end) {
+                        Some(end) => end,
+                        None => return,
+                    }
+                };
+                console!("Dump of assembler code from {:#x} to {:#x}:", start, end);
+                (start, end)
+            }
+            _ => {
+                let addr = match location {
+                    Some(location) => match evaluate(location) {
+                        Some(addr) => addr,
+                        None => return,
+                    },
+                    None if self.process().is_some() => match self.selected_frame() {
+                        Ok(frame) => frame.lookup_pc(),
+                        Err(err) => {
+                            console!("Fail to unwind the stack: {}", err);
+                            return;
+                        }
+                    },
+                    None => {
+                        console!("No frame selected.");
+                        return;
+                    }
+                };
+                match self.debug_data.get_function(addr) {
+                    Some(func) => {
+                        console!("Dump of assembler code for function {}:", func.name);
+                        (func.address, func.address + func.text_length)
+                    }
+                    None => {
+                        console!("No function contains specified address.");
+                        return;
+                    }
+                }
+            }
+        };
+        if start < end {
+            self.print_instructions(start, end, usize::MAX);
+        }
+        console!("End of assembler dump.");
+    }
+
+    /// Print all general purpose registers, or only the given ones, in hex and in their natural
+    /// format
+    fn print_registers(&self, names: &[String]) -> Result<(), nix::Error> {
+        let regs = self.process().unwrap().get_registers()?;
+        let names: Vec<&str> = if names.is_empty() {
+            inferior::REGISTER_NAMES.to_vec()
+        } else {
+            names.iter().map(|name| name.as_str()).collect()
+        };
+        for name in names {
+            let value = match inferior::get_register(&regs, name) {
+                Some(value) => value,
+                None => {
+                    console!("Invalid register `{}'", name);
+                    continue;
+                }
+            };
+            let natural = match name {
+                "rip" | "pc" => format!("{:#x}{}", value, self.symbolize(value as usize)),
+                "rsp" | "rbp" | "fs_base" | "gs_base" => format!("{:#x}", value),
+                "eflags" => {
+                    let flags: Vec<&str> = EFLAGS
+                        .iter()
+                        .filter(|(bit, _)| value & (1 << bit) != 0)
+                        .map(|(_, flag)| *flag)
+                        .collect();
+                    format!("[ {} ]", flags.join(" "))
+                }
+                _ => (value as i64).to_string(),
+            };
+            console!("{:<15}{:<19}{}", name, format!("{:#x}", value), natural);
+        }
+        Ok(())
+    }
+
+    /// Change a general purpose register of the inferior
+    fn set_register(&mut self, name: &str, value: i64) -> Result<(), String> {
+        let inferior = self
+            .inferior
+            .as_mut()
+            .ok_or("Cannot change registers of a core dump")?;
+        let mut regs = inferior.get_registers().map_err(|err| err.to_string())?;
+        if !inferior::set_register(&mut regs, name, value as u64) {
+            return Err(format!("Invalid register `{}'", name));
+        }
+        inferior.set_registers(regs).map_err(|err| err.to_string())
+    }
+
+    /// Evaluate the address operand of x: any expression of integer or pointer value, e.g.
+    /// $rsp, &var or p->next. An array stands for the address of its first element.
+    fn evaluate_address(&self, expression: &str) -> Result<usize, String> {
+        let frame = self.selected_frame()?;
+        let parsed = self.parse_expression(expression)?;
+        match self.number(self.evaluate(&parsed.root, &frame)?)? {
+            (_, Number::Integer(value)) => Ok(value as usize),
+            (_, Number::Float(_)) => {
+                Err(format!("\"{}\" is not an integer or pointer", expression))
+            }
+        }
+    }
+
+    /// Examine the memory of the inferior or core dump, gdb style: x/<count><format><unit> <address>
+    fn examine(&mut self, examine_format: &ExamineFormat, address: Option<&str>) {
+        let (format, unit) = examine_format.resolve(self.examine_format);
+        self.examine_format = (format, unit);
+        let count = examine_format.count.unwrap_or(1);
+        let addr = match (address, self.next_examine_addr) {
+            (Some(address), _) => match self.evaluate_address(address) {
+                Ok(addr) => addr,
+                Err(err) => {
+                    console!("{}", err);
+                    return;
+                }
+            },
+            (None, Some(addr)) => addr,
+            (None, None) => {
+                console!("Argument required (starting display address).");
+                return;
+            }
+        };
+        if count > MAX_EXAMINE_COUNT {
+            console!(
+                "Cannot examine more than {} units at once.",
+                MAX_EXAMINE_COUNT
+            );
+            return;
+        }
+        let length = if format == 'i' {
+            MAX_INSTRUCTION_LENGTH
+        } else {
+            unit
+        };
+        let end = match count
+            .checked_mul(length)
+            .and_then(|length| addr.checked_add(length))
+        {
+            Some(end) => end,
+            None => {
+                console!("Cannot access memory at address {:#x}", addr);
+                return;
+            }
+        };
+        if format == 'i' {
+            self.next_examine_addr = Some(self.print_instructions(addr, end, count));
+            return;
+        }
+        let process = self.process().unwrap();
+        if format == 's' {
+            let mut addr = addr;
+            for _ in 0..count {
+                let mut string = Vec::new();
+                let mut end = addr;
+                while let Ok(byte) = process.read_bytes(end, 1) {
+                    end += 1;
+                    if byte[0] == 0 || string.len() >= MAX_STRING_LENGTH {
+                        break;
+                    }
+                    string.push(byte[0]);
+                }
+                if end == addr {
+                    console!("Cannot access memory at address {:#x}", addr);
+                    return;
+                }
+                let escaped: String = string
+                    .iter()
+                    .map(|byte| (*byte as char).escape_default().to_string())
+                    .collect();
+                console!("{:#x}{}:\t\"{}\"", addr, self.symbolize(addr), escaped);
+                addr = end;
+            }
+            self.next_examine_addr = Some(addr);
+            return;
+        }
+        let mut bytes = match process.read_bytes(addr, end - addr) {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                console!("Cannot access memory at address {:#x}", addr);
+                return;
+            }
+        };
+        self.hide_breakpoints(addr, &mut bytes);
+        let per_line = match (format, unit) {
+            ('a', _) | (_, 8) => 2,
+            (_, 4) => 4,
+            (_, _) => 8,
+        };
+        for (index, line) in bytes.chunks(unit * per_line).enumerate() {
+            let line_addr = addr + index * unit * per_line;
+            let mut text = format!("{:#x}{}:", line_addr, self.symbolize(line_addr));
+            for chunk in line.chunks(unit) {
+                text += &format!(
+                    "\t{}",
+                    format_unit(chunk, format, |addr| self.symbolize(addr))
+                );
+            }
+            console!("{}", text);
+        }
+        self.next_examine_addr = Some(end);
+    }
+
+    /// Read the next line of input: a queued command, else a line typed at the terminal, or a
+    /// line of stdin in batch mode. Returns None at the end of the input.
+    fn next_line(&mut self, prompt: &str) -> Option<String> {
+        if let Some(line) = self.queued_commands.pop_front() {
+            return Some(line);
+        }
+        if self.batch || interpreter::machine_readable() {
+            let mut line = String::new();
+            return match io::stdin().read_line(&mut line) {
+                Ok(0) | Err(_) => None,
+                Ok(_) => Some(line),
+            };
+        }
+        loop {
+            // Print prompt and get next line of user input
+            match self.readline.readline(prompt) {
+                Err(ReadlineError::Interrupted) => {
+                    // User pressed ctrl+c. We're going to ignore it
+                    console!("Type \"quit\" to exit");
+                }
+                Err(ReadlineError::Eof) => return None,
+                Err(err) => {
+                    panic!("Unexpected I/O error: {:?}", err);
+                }
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        self.readline.add_history_entry(line.as_str());
+                        if let Err(err) = self.readline.save_history(&self.history_path) {
+                            console!(
+                                "Warning: failed to save history file at {}: {}",
+                                self.history_path,
+                                err
+                            );
+                        }
+                    }
+                    return Some(line);
+                }
+            }
+        }
+    }
+
+    /// Tell a frontend that the command being run is done
+    fn command_done(&mut self) {
+        if let Some(line) = self.command_line.take() {
+            interpreter::emit(
+                "result",
+                vec![
+                    ("command", Json::String(line)),
+                    ("status", Json::string("done")),
+                ],
+            );
+        }
+    }
+
+    /// Read lines until one parses as a command, with DebuggerCommand::from_line: the commands
+    /// queued from command files and breakpoint command lists come first, then lines of stdin in
+    /// batch mode or lines typed at the prompt. Blank lines and # comments are skipped, and an
+    /// unrecognized command is reported before reading the next line. The end of the input quits.
+    fn get_next_command(&mut self) -> DebuggerCommand {
+        self.command_done();
+        loop {
+            // ctrl+d or the end of the input is the equivalent of "quit" for our purposes
+            let line = match self.next_line("(deet) ") {
+                Some(line) => line,
+                None => return DebuggerCommand::Quit,
+            };
+            // command files may hold blank lines and comments
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if tokens.is_empty() || tokens[0].starts_with('#') {
+                continue;
+            }
+            if let Some(cmd) = DebuggerCommand::from_line(&line) {
+                self.command_line = Some(tokens.join(" "));
+                return cmd;
+            } else {
+                console!("Unrecognized command.");
+                interpreter::emit(
+                    "result",
+                    vec![
+                        ("command", Json::String(tokens.join(" "))),
+                        ("status", Json::string("error")),
+                        ("message", Json::string("Unrecognized command.")),
+                    ],
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_units() {
+        let no_symbol = |_| String::new();
+        assert_eq!(format_unit(&[0xff], 'x', no_symbol), "0xff");
+        assert_eq!(format_unit(&[0xff], 'd', no_symbol), "-1");
+        assert_eq!(format_unit(&[0xff], 'u', no_symbol), "255");
+        assert_eq!(format_unit(&[0x01, 0, 0, 0], 'x', no_symbol), "0x00000001");
+        assert_eq!(
+            format_unit(&[0xef, 0xbe, 0xad, 0xde], 'x', no_symbol),
+            "0xdeadbeef"
+        );
+        assert_eq!(format_unit(&[0xfe, 0xff], 'd', no_symbol), "-2");
+        assert_eq!(format_unit(&[0xff; 8], 'd', no_symbol), "-1");
+        assert_eq!(
+            format_unit(&[0xff; 8], 'u', no_symbol),
+            u64::MAX.to_string()
+        );
+        assert_eq!(format_unit(&[8], 'o', no_symbol), "010");
+        assert_eq!(format_unit(&[0], 'o', no_symbol), "0");
+        assert_eq!(format_unit(&[5], 't', no_symbol), "00000101");
+        assert_eq!(format_unit(&[5, 0], 't', no_symbol), "0000000000000101");
+        assert_eq!(format_unit(&[0x41], 'c', no_symbol), "65 'A'");
+        assert_eq!(format_unit(&[0x0a], 'c', no_symbol), "10 '\\n'");
+        assert_eq!(format_unit(&[0xe9], 'c', no_symbol), "-23 '\\u{e9}'");
+    }
+
+    #[test]
+    fn format_addresses() {
+        let symbolize = |addr| match addr {
+            0x1130 => " <main>".to_string(),
+            _ => String::new(),
+        };
+        assert_eq!(
+            format_unit(&[0x30, 0x11, 0, 0, 0, 0, 0, 0], 'a', symbolize),
+            "0x1130 <main>"
+        );
+        assert_eq!(format_unit(&[0; 8], 'a', symbolize), "0x0");
+    }
 }
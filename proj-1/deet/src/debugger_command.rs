@@ -1,21 +1,265 @@
+use nix::unistd::Pid;
+
+/// The /<count><format><unit> suffix of the x command. Parts that are left out keep their
+/// value from the previous x command.
+pub struct ExamineFormat {
+    pub count: Option<usize>,
+    pub format: Option<char>,
+    /// Size of a unit in bytes
+    pub unit: Option<usize>,
+}
+
+impl ExamineFormat {
+    fn parse(spec: &str) -> Option<ExamineFormat> {
+        let digits = spec.chars().take_while(|c| c.is_ascii_digit()).count();
+        let mut examine_format = ExamineFormat {
+            count: if digits > 0 {
+                Some(spec[..digits].parse().ok()?)
+            } else {
+                None
+            },
+            format: None,
+            unit: None,
+        };
+        for c in spec[digits..].chars() {
+            match c {
+                'x' | 'd' | 'u' | 'o' | 't' | 'c' | 'a' | 's' | 'i' => {
+                    examine_format.format = Some(c)
+                }
+                'b' => examine_format.unit = Some(1),
+                'h' => examine_format.unit = Some(2),
+                'w' => examine_format.unit = Some(4),
+                'g' => examine_format.unit = Some(8),
+                _ => return None,
+            }
+        }
+        Some(examine_format)
+    }
+
+    /// The format and unit size to use, given those of the previous x command
+    pub fn resolve(&self, previous: (char, usize)) -> (char, usize) {
+        let format = self.format.unwrap_or(previous.0);
+        let unit = match (format, self.unit) {
+            ('a', _) => 8,
+            ('c', None) => 1,
+            (_, Some(unit)) => unit,
+            (_, None) => previous.1,
+        };
+        (format, unit)
+    }
+}
+
+/// Which process deet follows when the inferior forks
+#[derive(Clone, Copy, PartialEq)]
+pub enum ForkMode {
+    Parent,
+    Child,
+}
+
 pub enum DebuggerCommand {
     Quit,
     Cont,
     Back,
-    Break(String),
+    Step,
+    Next,
+    StepInstruction(usize),
+    NextInstruction(usize),
+    Finish,
+    Up(usize),
+    Down(usize),
+    Frame(Option<usize>),
+    Break(String, Option<String>),
+    Ignore(usize, usize),
+    Watch(String),
+    Print(String),
+    /// Print an expression on every stop, or with no expression, print them all now
+    Display(Option<String>),
+    /// Stop displaying an expression, or all of them
+    Undisplay(Option<usize>),
+    InfoDisplay,
+    List(Option<String>),
+    InfoBreakpoints,
+    Commands(Option<usize>),
+    Source(String),
+    InfoLocals,
+    InfoArgs,
+    InfoRegisters(Vec<String>),
+    InfoSignals(Option<String>),
+    Handle(String, Vec<String>),
+    /// Evaluate an expression for its side effect, normally an assignment: set var x = 5
+    SetVariable(String),
+    SetFollowForkMode(ForkMode),
+    SetDetachOnFork(bool),
+    InfoInferiors,
+    Inferior(usize),
+    InfoThreads,
+    InfoSharedLibrary,
+    Thread(Option<usize>),
+    Examine(ExamineFormat, Option<String>),
+    Disassemble(Option<String>),
+    GenerateCore(Option<String>),
+    Delete(usize),
+    Disable(usize),
+    Enable(usize),
     Run(Vec<String>),
+    Attach(Pid),
+    Detach,
+}
+
+/// The text of a command line after its first count words, as typed. Expressions are taken
+/// from here rather than from the words, which would lose the spacing inside character literals
+/// and shift the positions syntax errors are reported at.
+fn rest_of_line(line: &str, count: usize) -> &str {
+    let mut rest = line.trim();
+    for _ in 0..count {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        rest = rest[end..].trim_start();
+    }
+    rest
 }
 
 impl DebuggerCommand {
-    pub fn from_tokens(tokens: &Vec<&str>) -> Option<DebuggerCommand> {
-        match tokens[0] {
+    pub fn from_line(line: &str) -> Option<DebuggerCommand> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match *tokens.first()? {
             "q" | "quit" => Some(DebuggerCommand::Quit),
             "c" | "cont" | "continue" => Some(DebuggerCommand::Cont),
             "bt" | "back" | "backtrace" => Some(DebuggerCommand::Back),
+            "s" | "step" => Some(DebuggerCommand::Step),
+            "n" | "next" => Some(DebuggerCommand::Next),
+            "si" | "stepi" => Some(DebuggerCommand::StepInstruction(match tokens.get(1) {
+                Some(count) => count.parse().ok()?,
+                None => 1,
+            })),
+            "ni" | "nexti" => Some(DebuggerCommand::NextInstruction(match tokens.get(1) {
+                Some(count) => count.parse().ok()?,
+                None => 1,
+            })),
+            "fin" | "finish" => Some(DebuggerCommand::Finish),
+            "up" => Some(DebuggerCommand::Up(match tokens.get(1) {
+                Some(count) => count.parse().ok()?,
+                None => 1,
+            })),
+            "down" => Some(DebuggerCommand::Down(match tokens.get(1) {
+                Some(count) => count.parse().ok()?,
+                None => 1,
+            })),
+            "f" | "frame" => Some(DebuggerCommand::Frame(match tokens.get(1) {
+                Some(level) => Some(level.parse().ok()?),
+                None => None,
+            })),
             "b" | "break" => {
-                let breakpoint = tokens[1];
-                Some(DebuggerCommand::Break(breakpoint.to_string()))
+                let breakpoint = *tokens.get(1)?;
+                let condition = match tokens.get(2) {
+                    Some(&"if") if tokens.len() > 3 => Some(rest_of_line(line, 3).to_string()),
+                    Some(_) => return None,
+                    None => None,
+                };
+                Some(DebuggerCommand::Break(breakpoint.to_string(), condition))
+            }
+            "commands" => Some(DebuggerCommand::Commands(match tokens.get(1) {
+                Some(id) => Some(id.parse().ok()?),
+                None => None,
+            })),
+            "source" => Some(DebuggerCommand::Source(tokens.get(1)?.to_string())),
+            "ignore" => Some(DebuggerCommand::Ignore(
+                tokens.get(1)?.parse().ok()?,
+                tokens.get(2)?.parse().ok()?,
+            )),
+            "p" | "print" if tokens.len() > 1 => {
+                Some(DebuggerCommand::Print(rest_of_line(line, 1).to_string()))
+            }
+            "l" | "list" => Some(DebuggerCommand::List(
+                tokens.get(1).map(|location| location.to_string()),
+            )),
+            "display" => Some(DebuggerCommand::Display(if tokens.len() > 1 {
+                Some(rest_of_line(line, 1).to_string())
+            } else {
+                None
+            })),
+            "undisplay" => Some(DebuggerCommand::Undisplay(match tokens.get(1) {
+                Some(id) => Some(id.parse().ok()?),
+                None => None,
+            })),
+            "watch" => Some(DebuggerCommand::Watch(tokens.get(1)?.to_string())),
+            "i" | "info" => match *tokens.get(1)? {
+                "b" | "break" | "breakpoints" => Some(DebuggerCommand::InfoBreakpoints),
+                "locals" => Some(DebuggerCommand::InfoLocals),
+                "display" => Some(DebuggerCommand::InfoDisplay),
+                "args" => Some(DebuggerCommand::InfoArgs),
+                "inferiors" => Some(DebuggerCommand::InfoInferiors),
+                "threads" => Some(DebuggerCommand::InfoThreads),
+                "sharedlibrary" | "dll" => Some(DebuggerCommand::InfoSharedLibrary),
+                "signals" | "handle" => Some(DebuggerCommand::InfoSignals(
+                    tokens.get(2).map(|signal| signal.to_string()),
+                )),
+                "r" | "registers" => Some(DebuggerCommand::InfoRegisters(
+                    tokens[2..]
+                        .iter()
+                        .map(|name| name.trim_start_matches('$').to_string())
+                        .collect(),
+                )),
+                _ => None,
+            },
+            "handle" => Some(DebuggerCommand::Handle(
+                tokens.get(1)?.to_string(),
+                tokens[2..]
+                    .iter()
+                    .map(|keyword| keyword.to_string())
+                    .collect(),
+            )),
+            "inferiors" => Some(DebuggerCommand::InfoInferiors),
+            "inferior" => Some(DebuggerCommand::Inferior(tokens.get(1)?.parse().ok()?)),
+            "thread" => Some(DebuggerCommand::Thread(match tokens.get(1) {
+                Some(id) => Some(id.parse().ok()?),
+                None => None,
+            })),
+            "set" if tokens.get(1) == Some(&"follow-fork-mode") => Some(
+                DebuggerCommand::SetFollowForkMode(match *tokens.get(2)? {
+                    "parent" => ForkMode::Parent,
+                    "child" => ForkMode::Child,
+                    _ => return None,
+                }),
+            ),
+            "set" if tokens.get(1) == Some(&"detach-on-fork") => {
+                Some(DebuggerCommand::SetDetachOnFork(match *tokens.get(2)? {
+                    "on" => true,
+                    "off" => false,
+                    _ => return None,
+                }))
             }
+            "set" if tokens.get(1) == Some(&"var") || tokens.get(1) == Some(&"variable") => Some(
+                DebuggerCommand::SetVariable(rest_of_line(line, 2).to_string()),
+            ),
+            // like gdb, set $rax = 0 or set x = 5 work without var too
+            "set" if tokens.len() > 1 => Some(DebuggerCommand::SetVariable(
+                rest_of_line(line, 1).to_string(),
+            )),
+            command if command == "x" || command.starts_with("x/") => {
+                Some(DebuggerCommand::Examine(
+                    ExamineFormat::parse(command.get(2..).unwrap_or(""))?,
+                    if tokens.len() > 1 {
+                        Some(rest_of_line(line, 1).to_string())
+                    } else {
+                        None
+                    },
+                ))
+            }
+            "disas" | "disassemble" => Some(DebuggerCommand::Disassemble(if tokens.len() > 1 {
+                Some(tokens[1..].join(""))
+            } else {
+                None
+            })),
+            "gcore" | "generate-core-file" => Some(DebuggerCommand::GenerateCore(
+                tokens.get(1).map(|path| path.to_string()),
+            )),
+            "d" | "delete" => Some(DebuggerCommand::Delete(tokens.get(1)?.parse().ok()?)),
+            "disable" => Some(DebuggerCommand::Disable(tokens.get(1)?.parse().ok()?)),
+            "enable" => Some(DebuggerCommand::Enable(tokens.get(1)?.parse().ok()?)),
+            "attach" => Some(DebuggerCommand::Attach(Pid::from_raw(
+                tokens.get(1)?.parse().ok()?,
+            ))),
+            "detach" => Some(DebuggerCommand::Detach),
             "r" | "run" => {
                 let args = tokens[1..].to_vec();
                 Some(DebuggerCommand::Run(
@@ -27,3 +271,107 @@ impl DebuggerCommand {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expressions_keep_their_spacing() {
+        assert!(matches!(
+            DebuggerCommand::from_line("print  c == ' ' "),
+            Some(DebuggerCommand::Print(text)) if text == "c == ' '"
+        ));
+        assert!(matches!(
+            DebuggerCommand::from_line("display buf[0]  !=  '\t'"),
+            Some(DebuggerCommand::Display(Some(text))) if text == "buf[0]  !=  '\t'"
+        ));
+        assert!(matches!(
+            DebuggerCommand::from_line("set var c = ' '"),
+            Some(DebuggerCommand::SetVariable(text)) if text == "c = ' '"
+        ));
+        assert!(matches!(
+            DebuggerCommand::from_line("set c = ' '"),
+            Some(DebuggerCommand::SetVariable(text)) if text == "c = ' '"
+        ));
+        assert!(matches!(
+            DebuggerCommand::from_line("b main if c  ==  ' '"),
+            Some(DebuggerCommand::Break(location, Some(condition)))
+                if location == "main" && condition == "c  ==  ' '"
+        ));
+        assert!(matches!(
+            DebuggerCommand::from_line("x/4xb  &buf[1 + 2]"),
+            Some(DebuggerCommand::Examine(_, Some(address))) if address == "&buf[1 + 2]"
+        ));
+    }
+
+    fn examine_format(spec: &str) -> (Option<usize>, Option<char>, Option<usize>) {
+        let format = ExamineFormat::parse(spec).unwrap();
+        (format.count, format.format, format.unit)
+    }
+
+    #[test]
+    fn examine_formats() {
+        assert_eq!(examine_format(""), (None, None, None));
+        assert_eq!(examine_format("4"), (Some(4), None, None));
+        assert_eq!(examine_format("12xg"), (Some(12), Some('x'), Some(8)));
+        assert_eq!(examine_format("hd"), (None, Some('d'), Some(2)));
+        assert_eq!(examine_format("s"), (None, Some('s'), None));
+        assert_eq!(examine_format("w"), (None, None, Some(4)));
+        assert!(ExamineFormat::parse("3q").is_none());
+        assert!(ExamineFormat::parse("x3").is_none());
+        assert!(ExamineFormat::parse("99999999999999999999x").is_none());
+        assert!(DebuggerCommand::from_line("x/3q $sp").is_none());
+        assert!(matches!(
+            DebuggerCommand::from_line("x $sp"),
+            Some(DebuggerCommand::Examine(
+                ExamineFormat {
+                    count: None,
+                    format: None,
+                    unit: None
+                },
+                _
+            ))
+        ));
+    }
+
+    #[test]
+    fn examine_format_is_sticky() {
+        let resolve = |spec, previous| ExamineFormat::parse(spec).unwrap().resolve(previous);
+        // the first x shows words in hex
+        assert_eq!(resolve("", ('x', 4)), ('x', 4));
+        assert_eq!(resolve("d", ('x', 8)), ('d', 8));
+        assert_eq!(resolve("b", ('d', 4)), ('d', 1));
+        assert_eq!(resolve("3", ('t', 2)), ('t', 2));
+        // characters default to bytes and addresses are always giant words
+        assert_eq!(resolve("c", ('x', 4)), ('c', 1));
+        assert_eq!(resolve("ch", ('x', 4)), ('c', 2));
+        assert_eq!(resolve("", ('c', 1)), ('c', 1));
+        assert_eq!(resolve("a", ('x', 1)), ('a', 8));
+        assert_eq!(resolve("ab", ('x', 4)), ('a', 8));
+    }
+
+    #[test]
+    fn breakpoints() {
+        assert!(matches!(
+            DebuggerCommand::from_line("break main"),
+            Some(DebuggerCommand::Break(location, None)) if location == "main"
+        ));
+        assert!(matches!(
+            DebuggerCommand::from_line("b types.c:39 if i > 2"),
+            Some(DebuggerCommand::Break(location, Some(condition)))
+                if location == "types.c:39" && condition == "i > 2"
+        ));
+        assert!(DebuggerCommand::from_line("break").is_none());
+        assert!(DebuggerCommand::from_line("b ").is_none());
+        assert!(DebuggerCommand::from_line("break main if").is_none());
+        assert!(DebuggerCommand::from_line("break main if  ").is_none());
+        assert!(DebuggerCommand::from_line("break main when x").is_none());
+    }
+
+    #[test]
+    fn blank_line() {
+        assert!(DebuggerCommand::from_line("").is_none());
+        assert!(DebuggerCommand::from_line("  \t").is_none());
+    }
+}
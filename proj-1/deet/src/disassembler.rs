@@ -0,0 +1,1241 @@
+//! A small x86-64 disassembler covering the general purpose and SSE/SSE2 instructions emitted by
+//! C compilers, printed in Intel syntax the way objdump -M intel does. VEX and EVEX encoded
+//! (AVX) instructions are not decoded.
+
+/// A decoded instruction
+pub struct Instruction {
+    pub length: usize,
+    /// Mnemonic and operands, e.g. "mov    rbp,rsp"
+    pub text: String,
+    /// The address a branch goes to, or a rip-relative operand refers to
+    pub target: Option<usize>,
+}
+
+const REGISTERS_64: [&str; 16] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+const REGISTERS_32: [&str; 16] = [
+    "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d", "r10d", "r11d", "r12d",
+    "r13d", "r14d", "r15d",
+];
+const REGISTERS_16: [&str; 16] = [
+    "ax", "cx", "dx", "bx", "sp", "bp", "si", "di", "r8w", "r9w", "r10w", "r11w", "r12w", "r13w",
+    "r14w", "r15w",
+];
+const REGISTERS_8: [&str; 16] = [
+    "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b", "r12b",
+    "r13b", "r14b", "r15b",
+];
+/// Byte registers 4-7 when there is no REX prefix
+const HIGH_BYTE_REGISTERS: [&str; 4] = ["ah", "ch", "dh", "bh"];
+const SEGMENT_REGISTERS: [&str; 6] = ["es", "cs", "ss", "ds", "fs", "gs"];
+const CONDITIONS: [&str; 16] = [
+    "o", "no", "b", "ae", "e", "ne", "be", "a", "s", "ns", "p", "np", "l", "ge", "le", "g",
+];
+const ARITHMETIC: [&str; 8] = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+const SHIFTS: [&str; 8] = ["rol", "ror", "rcl", "rcr", "shl", "shr", "sal", "sar"];
+
+/// Operand that the ModRM byte encodes in its r/m field
+enum Rm {
+    Register(usize),
+    Memory(String),
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    addr: usize,
+    pos: usize,
+    rex: u8,
+    operand_size_prefix: bool,
+    lock_prefix: bool,
+    /// 0xf2 (repne) or 0xf3 (rep)
+    repeat_prefix: Option<u8>,
+    segment: Option<&'static str>,
+    /// Displacement of a rip-relative memory operand, resolved once the length is known
+    rip_displacement: Option<i64>,
+    target: Option<usize>,
+}
+
+fn format_signed(value: i64) -> String {
+    if value < 0 {
+        format!("-{:#x}", -(value as i128))
+    } else {
+        format!("+{:#x}", value)
+    }
+}
+
+fn size_name(size: usize) -> &'static str {
+    match size {
+        1 => "BYTE",
+        2 => "WORD",
+        4 => "DWORD",
+        8 => "QWORD",
+        10 => "TBYTE",
+        16 => "XMMWORD",
+        _ => "",
+    }
+}
+
+impl<'a> Decoder<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.pos)?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn immediate(&mut self, size: usize) -> Option<i64> {
+        let bytes = self.bytes.get(self.pos..self.pos + size)?;
+        self.pos += size;
+        let mut raw = [0u8; 8];
+        raw[..size].copy_from_slice(bytes);
+        let shift = 64 - 8 * size as u32;
+        Some(((u64::from_le_bytes(raw) << shift) as i64) >> shift)
+    }
+
+    fn rex_w(&self) -> bool {
+        self.rex & 0x8 != 0
+    }
+
+    /// Size of the operands of instructions that default to 32 bits
+    fn operand_size(&self) -> usize {
+        if self.rex_w() {
+            8
+        } else if self.operand_size_prefix {
+            2
+        } else {
+            4
+        }
+    }
+
+    fn register(&self, index: usize, size: usize) -> String {
+        match size {
+            1 if self.rex == 0 && (4..8).contains(&index) => HIGH_BYTE_REGISTERS[index - 4],
+            1 => REGISTERS_8[index],
+            2 => REGISTERS_16[index],
+            4 => REGISTERS_32[index],
+            _ => REGISTERS_64[index],
+        }
+        .to_string()
+    }
+
+    /// Format an immediate operand as an unsigned value of the operand size
+    fn format_immediate(value: i64, size: usize) -> String {
+        let mask = if size >= 8 {
+            u64::MAX
+        } else {
+            (1u64 << (8 * size)) - 1
+        };
+        format!("{:#x}", value as u64 & mask)
+    }
+
+    /// Decode a ModRM byte and the SIB byte and displacement following it. Returns the reg field
+    /// and the r/m operand.
+    fn modrm(&mut self) -> Option<(usize, Rm)> {
+        let modrm = self.byte()?;
+        let mode = modrm >> 6;
+        let reg = ((modrm >> 3) & 7) as usize | if self.rex & 0x4 != 0 { 8 } else { 0 };
+        let rm = (modrm & 7) as usize;
+        let rex_b = if self.rex & 0x1 != 0 { 8 } else { 0 };
+        if mode == 3 {
+            return Some((reg, Rm::Register(rm | rex_b)));
+        }
+        let mut address = if rm == 4 {
+            let sib = self.byte()?;
+            let scale = 1 << (sib >> 6);
+            let index = ((sib >> 3) & 7) as usize | if self.rex & 0x2 != 0 { 8 } else { 0 };
+            let base = (sib & 7) as usize;
+            if base == 5 && mode == 0 {
+                let displacement = self.immediate(4)?;
+                if index == 4 {
+                    // absolute address
+                    let segment = self.segment.unwrap_or("ds");
+                    return Some((
+                        reg,
+                        Rm::Memory(format!("{}:{:#x}", segment, displacement as u64)),
+                    ));
+                }
+                format!(
+                    "{}*{}{}",
+                    REGISTERS_64[index],
+                    scale,
+                    format_signed(displacement)
+                )
+            } else if index == 4 {
+                REGISTERS_64[base | rex_b].to_string()
+            } else {
+                format!(
+                    "{}+{}*{}",
+                    REGISTERS_64[base | rex_b],
+                    REGISTERS_64[index],
+                    scale
+                )
+            }
+        } else if rm == 5 && mode == 0 {
+            let displacement = self.immediate(4)?;
+            self.rip_displacement = Some(displacement);
+            format!("rip{}", format_signed(displacement))
+        } else {
+            REGISTERS_64[rm | rex_b].to_string()
+        };
+        match mode {
+            1 => address += &format_signed(self.immediate(1)?),
+            2 => address += &format_signed(self.immediate(4)?),
+            _ => (),
+        }
+        let segment = match self.segment {
+            Some(segment) => format!("{}:", segment),
+            None => String::new(),
+        };
+        Some((reg, Rm::Memory(format!("{}[{}]", segment, address))))
+    }
+
+    /// Format an r/m operand; memory operands are annotated with their size
+    fn rm_operand(&self, rm: &Rm, size: usize) -> String {
+        match rm {
+            Rm::Register(index) => self.register(*index, size),
+            Rm::Memory(address) => format!("{} PTR {}", size_name(size), address),
+        }
+    }
+
+    /// Format an r/m operand of an SSE instruction
+    fn xmm_operand(&self, rm: &Rm, size: usize) -> String {
+        match rm {
+            Rm::Register(index) => format!("xmm{}", index),
+            Rm::Memory(address) => format!("{} PTR {}", size_name(size), address),
+        }
+    }
+
+    fn relative_target(&mut self, size: usize) -> Option<String> {
+        let offset = self.immediate(size)?;
+        let target = self
+            .addr
+            .wrapping_add(self.pos)
+            .wrapping_add(offset as usize);
+        self.target = Some(target);
+        Some(format!("{:#x}", target))
+    }
+
+    /// Decode an instruction given as a mnemonic and its operands
+    fn decode(&mut self) -> Option<(String, String)> {
+        // prefixes
+        let mut opcode = loop {
+            match self.byte()? {
+                0x66 => self.operand_size_prefix = true,
+                byte @ 0xf2 | byte @ 0xf3 => self.repeat_prefix = Some(byte),
+                0x64 => self.segment = Some("fs"),
+                0x65 => self.segment = Some("gs"),
+                0xf0 => self.lock_prefix = true,
+                0x2e | 0x36 | 0x3e | 0x26 | 0x67 => (),
+                byte => break byte,
+            }
+        };
+        if opcode & 0xf0 == 0x40 {
+            self.rex = opcode;
+            opcode = self.byte()?;
+        }
+        let size = self.operand_size();
+        let op = |mnemonic: &str| mnemonic.to_string();
+        Some(match opcode {
+            0x00..=0x3f if opcode & 7 < 6 => {
+                let mnemonic = op(ARITHMETIC[(opcode >> 3) as usize]);
+                match opcode & 7 {
+                    0..=3 => {
+                        let size = if opcode & 1 == 0 { 1 } else { size };
+                        let (reg, rm) = self.modrm()?;
+                        let reg = self.register(reg, size);
+                        let rm = self.rm_operand(&rm, size);
+                        if opcode & 2 == 0 {
+                            (mnemonic, format!("{},{}", rm, reg))
+                        } else {
+                            (mnemonic, format!("{},{}", reg, rm))
+                        }
+                    }
+                    4 => {
+                        let immediate = self.immediate(1)?;
+                        (
+                            mnemonic,
+                            format!("al,{}", Self::format_immediate(immediate, 1)),
+                        )
+                    }
+                    _ => {
+                        let immediate = self.immediate(size.min(4))?;
+                        let reg = self.register(0, size);
+                        (
+                            mnemonic,
+                            format!("{},{}", reg, Self::format_immediate(immediate, size)),
+                        )
+                    }
+                }
+            }
+            0x50..=0x57 => (
+                op("push"),
+                REGISTERS_64[self.opcode_register(opcode)].to_string(),
+            ),
+            0x58..=0x5f => (
+                op("pop"),
+                REGISTERS_64[self.opcode_register(opcode)].to_string(),
+            ),
+            0x63 => {
+                let (reg, rm) = self.modrm()?;
+                (
+                    op("movsxd"),
+                    format!("{},{}", self.register(reg, size), self.rm_operand(&rm, 4)),
+                )
+            }
+            0x68 => {
+                let immediate = self.immediate(4)?;
+                (op("push"), Self::format_immediate(immediate, 8))
+            }
+            0x6a => {
+                let immediate = self.immediate(1)?;
+                (op("push"), Self::format_immediate(immediate, 8))
+            }
+            0x69 | 0x6b => {
+                let (reg, rm) = self.modrm()?;
+                let immediate = self.immediate(if opcode == 0x69 { size.min(4) } else { 1 })?;
+                (
+                    op("imul"),
+                    format!(
+                        "{},{},{}",
+                        self.register(reg, size),
+                        self.rm_operand(&rm, size),
+                        Self::format_immediate(immediate, size)
+                    ),
+                )
+            }
+            0x70..=0x7f => (
+                format!("j{}", CONDITIONS[(opcode & 0xf) as usize]),
+                self.relative_target(1)?,
+            ),
+            0x80 | 0x81 | 0x83 => {
+                let size = if opcode == 0x80 { 1 } else { size };
+                let (reg, rm) = self.modrm()?;
+                let immediate = self.immediate(if opcode == 0x81 { size.min(4) } else { 1 })?;
+                (
+                    op(ARITHMETIC[reg & 7]),
+                    format!(
+                        "{},{}",
+                        self.rm_operand(&rm, size),
+                        Self::format_immediate(immediate, size)
+                    ),
+                )
+            }
+            0x84..=0x8b => {
+                let mnemonic = match opcode {
+                    0x84 | 0x85 => "test",
+                    0x86 | 0x87 => "xchg",
+                    _ => "mov",
+                };
+                let size = if opcode & 1 == 0 { 1 } else { size };
+                let (reg, rm) = self.modrm()?;
+                let reg = self.register(reg, size);
+                let rm = self.rm_operand(&rm, size);
+                if opcode >= 0x8a {
+                    (op(mnemonic), format!("{},{}", reg, rm))
+                } else {
+                    (op(mnemonic), format!("{},{}", rm, reg))
+                }
+            }
+            0x8c | 0x8e => {
+                let (reg, rm) = self.modrm()?;
+                let segment = SEGMENT_REGISTERS.get(reg & 7)?;
+                let rm = self.rm_operand(&rm, 2);
+                if opcode == 0x8c {
+                    (op("mov"), format!("{},{}", rm, segment))
+                } else {
+                    (op("mov"), format!("{},{}", segment, rm))
+                }
+            }
+            0x8d => {
+                let (reg, rm) = self.modrm()?;
+                let address = match rm {
+                    Rm::Memory(address) => address,
+                    Rm::Register(_) => return None,
+                };
+                (
+                    op("lea"),
+                    format!("{},{}", self.register(reg, size), address),
+                )
+            }
+            0x8f => {
+                let (_, rm) = self.modrm()?;
+                (op("pop"), self.rm_operand(&rm, 8))
+            }
+            0x90 if self.repeat_prefix == Some(0xf3) => (op("pause"), String::new()),
+            0x90 if self.rex & 0x1 == 0 && !self.operand_size_prefix => (op("nop"), String::new()),
+            0x90..=0x97 => {
+                let reg = self.register(self.opcode_register(opcode), size);
+                (op("xchg"), format!("{},{}", reg, self.register(0, size)))
+            }
+            0x98 => (
+                op(match size {
+                    8 => "cdqe",
+                    2 => "cbw",
+                    _ => "cwde",
+                }),
+                String::new(),
+            ),
+            0x99 => (
+                op(match size {
+                    8 => "cqo",
+                    2 => "cwd",
+                    _ => "cdq",
+                }),
+                String::new(),
+            ),
+            0x9c => (op("pushf"), String::new()),
+            0x9d => (op("popf"), String::new()),
+            0xa8 => {
+                let immediate = self.immediate(1)?;
+                (
+                    op("test"),
+                    format!("al,{}", Self::format_immediate(immediate, 1)),
+                )
+            }
+            0xa9 => {
+                let immediate = self.immediate(size.min(4))?;
+                let reg = self.register(0, size);
+                (
+                    op("test"),
+                    format!("{},{}", reg, Self::format_immediate(immediate, size)),
+                )
+            }
+            0xa4..=0xa7 | 0xaa..=0xaf => {
+                let size = if opcode & 1 == 0 { 1 } else { size };
+                let destination = format!("{} PTR es:[rdi]", size_name(size));
+                let source = format!("{} PTR ds:[rsi]", size_name(size));
+                let accumulator = self.register(0, size);
+                let (mnemonic, operands) = match opcode & 0xfe {
+                    0xa4 => ("movs", format!("{},{}", destination, source)),
+                    0xa6 => ("cmps", format!("{},{}", source, destination)),
+                    0xaa => ("stos", format!("{},{}", destination, accumulator)),
+                    0xac => ("lods", format!("{},{}", accumulator, source)),
+                    _ => ("scas", format!("{},{}", accumulator, destination)),
+                };
+                let prefix = match (self.repeat_prefix, opcode & 0xfe) {
+                    (Some(0xf3), 0xa6) | (Some(0xf3), 0xae) => "repz ",
+                    (Some(0xf3), _) => "rep ",
+                    (Some(_), _) => "repnz ",
+                    (None, _) => "",
+                };
+                (format!("{}{}", prefix, mnemonic), operands)
+            }
+            0xb0..=0xb7 => {
+                let immediate = self.immediate(1)?;
+                let reg = self.register(self.opcode_register(opcode), 1);
+                (
+                    op("mov"),
+                    format!("{},{}", reg, Self::format_immediate(immediate, 1)),
+                )
+            }
+            0xb8..=0xbf => {
+                let reg = self.register(self.opcode_register(opcode), size);
+                let immediate = self.immediate(size)?;
+                let mnemonic = if size == 8 { "movabs" } else { "mov" };
+                (
+                    op(mnemonic),
+                    format!("{},{}", reg, Self::format_immediate(immediate, size)),
+                )
+            }
+            0xc0 | 0xc1 | 0xd0..=0xd3 => {
+                let size = if opcode & 1 == 0 { 1 } else { size };
+                let (reg, rm) = self.modrm()?;
+                let count = match opcode {
+                    0xc0 | 0xc1 => Self::format_immediate(self.immediate(1)?, 1),
+                    0xd0 | 0xd1 => "1".to_string(),
+                    _ => "cl".to_string(),
+                };
+                (
+                    op(SHIFTS[reg & 7]),
+                    format!("{},{}", self.rm_operand(&rm, size), count),
+                )
+            }
+            0xc2 => {
+                let immediate = self.immediate(2)?;
+                (op("ret"), Self::format_immediate(immediate, 2))
+            }
+            0xc3 if self.repeat_prefix == Some(0xf3) => (op("repz ret"), String::new()),
+            0xc3 => (op("ret"), String::new()),
+            0xc6 | 0xc7 => {
+                let size = if opcode == 0xc6 { 1 } else { size };
+                let (_, rm) = self.modrm()?;
+                let immediate = self.immediate(size.min(4))?;
+                (
+                    op("mov"),
+                    format!(
+                        "{},{}",
+                        self.rm_operand(&rm, size),
+                        Self::format_immediate(immediate, size)
+                    ),
+                )
+            }
+            0xc9 => (op("leave"), String::new()),
+            0xcc => (op("int3"), String::new()),
+            0xcd => {
+                let immediate = self.immediate(1)?;
+                (op("int"), Self::format_immediate(immediate, 1))
+            }
+            0xe3 => (op("jrcxz"), self.relative_target(1)?),
+            0xe8 => (op("call"), self.relative_target(4)?),
+            0xe9 => (op("jmp"), self.relative_target(4)?),
+            0xeb => (op("jmp"), self.relative_target(1)?),
+            0xf4 => (op("hlt"), String::new()),
+            0xf5 => (op("cmc"), String::new()),
+            0xf8 => (op("clc"), String::new()),
+            0xf9 => (op("stc"), String::new()),
+            0xfc => (op("cld"), String::new()),
+            0xfd => (op("std"), String::new()),
+            0xf6 | 0xf7 => {
+                let size = if opcode == 0xf6 { 1 } else { size };
+                let (reg, rm) = self.modrm()?;
+                let operand = self.rm_operand(&rm, size);
+                match reg & 7 {
+                    0 | 1 => {
+                        let immediate = self.immediate(size.min(4))?;
+                        (
+                            op("test"),
+                            format!("{},{}", operand, Self::format_immediate(immediate, size)),
+                        )
+                    }
+                    reg => (
+                        op(["", "", "not", "neg", "mul", "imul", "div", "idiv"][reg]),
+                        operand,
+                    ),
+                }
+            }
+            0xfe => {
+                let (reg, rm) = self.modrm()?;
+                let mnemonic = match reg & 7 {
+                    0 => "inc",
+                    1 => "dec",
+                    _ => return None,
+                };
+                (op(mnemonic), self.rm_operand(&rm, 1))
+            }
+            0xff => {
+                let (reg, rm) = self.modrm()?;
+                match reg & 7 {
+                    0 => (op("inc"), self.rm_operand(&rm, size)),
+                    1 => (op("dec"), self.rm_operand(&rm, size)),
+                    2 => (op("call"), self.rm_operand(&rm, 8)),
+                    4 => (op("jmp"), self.rm_operand(&rm, 8)),
+                    6 => (op("push"), self.rm_operand(&rm, 8)),
+                    _ => return None,
+                }
+            }
+            0x0f => self.decode_two_byte()?,
+            _ => return None,
+        })
+    }
+
+    /// The register encoded in the low three bits of an opcode, extended by REX.B
+    fn opcode_register(&self, opcode: u8) -> usize {
+        (opcode & 7) as usize | if self.rex & 0x1 != 0 { 8 } else { 0 }
+    }
+
+    /// Suffix of scalar and packed SSE instructions selected by the mandatory prefix
+    fn sse_suffix(&self) -> (&'static str, usize) {
+        match (self.repeat_prefix, self.operand_size_prefix) {
+            (Some(0xf3), _) => ("ss", 4),
+            (Some(_), _) => ("sd", 8),
+            (None, true) => ("pd", 16),
+            (None, false) => ("ps", 16),
+        }
+    }
+
+    fn decode_two_byte(&mut self) -> Option<(String, String)> {
+        let opcode = self.byte()?;
+        let size = self.operand_size();
+        let op = |mnemonic: &str| mnemonic.to_string();
+        Some(match opcode {
+            0x05 => (op("syscall"), String::new()),
+            0x0b => (op("ud2"), String::new()),
+            0x1e if self.repeat_prefix == Some(0xf3) => match self.byte()? {
+                0xfa => (op("endbr64"), String::new()),
+                0xfb => (op("endbr32"), String::new()),
+                _ => return None,
+            },
+            0x18 => {
+                let (reg, rm) = self.modrm()?;
+                let mnemonic =
+                    ["prefetchnta", "prefetcht0", "prefetcht1", "prefetcht2"].get(reg & 7)?;
+                (op(mnemonic), self.rm_operand(&rm, 1))
+            }
+            0x1f => {
+                let (_, rm) = self.modrm()?;
+                (op("nop"), self.rm_operand(&rm, size))
+            }
+            0x10 | 0x11 => {
+                let (suffix, operand_size) = self.sse_suffix();
+                let mnemonic = match suffix {
+                    "ss" | "sd" => format!("mov{}", suffix),
+                    _ => format!("movu{}", suffix),
+                };
+                let (reg, rm) = self.modrm()?;
+                let rm = self.xmm_operand(&rm, operand_size);
+                if opcode == 0x10 {
+                    (mnemonic, format!("xmm{},{}", reg, rm))
+                } else {
+                    (mnemonic, format!("{},xmm{}", rm, reg))
+                }
+            }
+            0x14 | 0x15 | 0x28 | 0x29 | 0x2e | 0x2f | 0x51..=0x5f => {
+                let (suffix, operand_size) = self.sse_suffix();
+                let name = match opcode {
+                    0x14 => "unpckl",
+                    0x15 => "unpckh",
+                    0x28 | 0x29 => "mova",
+                    0x2e => "ucomi",
+                    0x2f => "comi",
+                    0x51 => "sqrt",
+                    0x52 => "rsqrt",
+                    0x53 => "rcp",
+                    0x54 => "and",
+                    0x55 => "andn",
+                    0x56 => "or",
+                    0x57 => "xor",
+                    0x58 => "add",
+                    0x59 => "mul",
+                    0x5c => "sub",
+                    0x5d => "min",
+                    0x5e => "div",
+                    0x5f => "max",
+                    _ => "",
+                };
+                let mnemonic = match (opcode, suffix) {
+                    (0x5a, "ss") => op("cvtss2sd"),
+                    (0x5a, "sd") => op("cvtsd2ss"),
+                    (0x5a, "pd") => op("cvtpd2ps"),
+                    (0x5a, _) => op("cvtps2pd"),
+                    (0x5b, "ss") => op("cvttps2dq"),
+                    (0x5b, "pd") => op("cvtps2dq"),
+                    (0x5b, _) => op("cvtdq2ps"),
+                    // comparisons only come in scalar forms
+                    (0x2e, "pd") | (0x2f, "pd") => format!("{}sd", name),
+                    (0x2e, _) | (0x2f, _) => format!("{}ss", name),
+                    _ => format!("{}{}", name, suffix),
+                };
+                let operand_size = match (opcode, suffix) {
+                    (0x2e, "pd") | (0x2f, "pd") => 8,
+                    (0x2e, _) | (0x2f, _) => 4,
+                    _ => operand_size,
+                };
+                let (reg, rm) = self.modrm()?;
+                let rm = self.xmm_operand(&rm, operand_size);
+                if opcode == 0x29 {
+                    (mnemonic, format!("{},xmm{}", rm, reg))
+                } else {
+                    (mnemonic, format!("xmm{},{}", reg, rm))
+                }
+            }
+            0x2a => {
+                let (suffix, _) = self.sse_suffix();
+                let (reg, rm) = self.modrm()?;
+                let size = if self.rex_w() { 8 } else { 4 };
+                (
+                    format!("cvtsi2{}", suffix),
+                    format!("xmm{},{}", reg, self.rm_operand(&rm, size)),
+                )
+            }
+            0x2c | 0x2d => {
+                let (suffix, operand_size) = self.sse_suffix();
+                let (reg, rm) = self.modrm()?;
+                let truncate = if opcode == 0x2c { "t" } else { "" };
+                (
+                    format!("cvt{}{}2si", truncate, suffix),
+                    format!(
+                        "{},{}",
+                        self.register(reg, if self.rex_w() { 8 } else { 4 }),
+                        self.xmm_operand(&rm, operand_size)
+                    ),
+                )
+            }
+            0x31 => (op("rdtsc"), String::new()),
+            0x40..=0x4f => {
+                let (reg, rm) = self.modrm()?;
+                (
+                    format!("cmov{}", CONDITIONS[(opcode & 0xf) as usize]),
+                    format!(
+                        "{},{}",
+                        self.register(reg, size),
+                        self.rm_operand(&rm, size)
+                    ),
+                )
+            }
+            0xd7 if self.operand_size_prefix => {
+                let (reg, rm) = self.modrm()?;
+                (
+                    op("pmovmskb"),
+                    format!("{},{}", self.register(reg, 4), self.xmm_operand(&rm, 16)),
+                )
+            }
+            _ if self.operand_size_prefix && packed_integer_mnemonic(opcode).is_some() => {
+                let (reg, rm) = self.modrm()?;
+                (
+                    op(packed_integer_mnemonic(opcode).unwrap()),
+                    format!("xmm{},{}", reg, self.xmm_operand(&rm, 16)),
+                )
+            }
+            0x70 if self.operand_size_prefix || self.repeat_prefix.is_some() => {
+                let mnemonic = match self.repeat_prefix {
+                    Some(0xf3) => "pshufhw",
+                    Some(_) => "pshuflw",
+                    None => "pshufd",
+                };
+                let (reg, rm) = self.modrm()?;
+                let immediate = self.immediate(1)?;
+                (
+                    op(mnemonic),
+                    format!(
+                        "xmm{},{},{}",
+                        reg,
+                        self.xmm_operand(&rm, 16),
+                        Self::format_immediate(immediate, 1)
+                    ),
+                )
+            }
+            0x71..=0x73 if self.operand_size_prefix => {
+                let (reg, rm) = self.modrm()?;
+                let mnemonic = match (opcode, reg & 7) {
+                    (0x71, 2) => "psrlw",
+                    (0x71, 4) => "psraw",
+                    (0x71, 6) => "psllw",
+                    (0x72, 2) => "psrld",
+                    (0x72, 4) => "psrad",
+                    (0x72, 6) => "pslld",
+                    (0x73, 2) => "psrlq",
+                    (0x73, 3) => "psrldq",
+                    (0x73, 6) => "psllq",
+                    (0x73, 7) => "pslldq",
+                    _ => return None,
+                };
+                let immediate = self.immediate(1)?;
+                (
+                    op(mnemonic),
+                    format!(
+                        "{},{}",
+                        self.xmm_operand(&rm, 16),
+                        Self::format_immediate(immediate, 1)
+                    ),
+                )
+            }
+            0x12 | 0x13 | 0x16 | 0x17 if self.repeat_prefix.is_none() => {
+                let (reg, rm) = self.modrm()?;
+                let high = opcode >= 0x16;
+                let mnemonic = match (high, &rm, self.operand_size_prefix) {
+                    (false, Rm::Register(_), _) => "movhlps",
+                    (true, Rm::Register(_), _) => "movlhps",
+                    (false, _, false) => "movlps",
+                    (false, _, true) => "movlpd",
+                    (true, _, false) => "movhps",
+                    (true, _, true) => "movhpd",
+                };
+                let rm = self.xmm_operand(&rm, 8);
+                if opcode & 1 == 0 {
+                    (op(mnemonic), format!("xmm{},{}", reg, rm))
+                } else {
+                    (op(mnemonic), format!("{},xmm{}", rm, reg))
+                }
+            }
+            0x2b | 0xe7 if self.repeat_prefix.is_none() => {
+                let mnemonic = match (opcode, self.operand_size_prefix) {
+                    (0x2b, false) => "movntps",
+                    (0x2b, true) => "movntpd",
+                    (_, true) => "movntdq",
+                    (_, false) => return None,
+                };
+                let (reg, rm) = self.modrm()?;
+                (
+                    op(mnemonic),
+                    format!("{},xmm{}", self.xmm_operand(&rm, 16), reg),
+                )
+            }
+            0x6e | 0x7e if self.repeat_prefix.is_none() => {
+                let (reg, rm) = self.modrm()?;
+                let size = if self.rex_w() { 8 } else { 4 };
+                let mnemonic = if self.rex_w() { "movq" } else { "movd" };
+                let vector = if self.operand_size_prefix {
+                    format!("xmm{}", reg)
+                } else {
+                    format!("mm{}", reg & 7)
+                };
+                let rm = self.rm_operand(&rm, size);
+                if opcode == 0x6e {
+                    (op(mnemonic), format!("{},{}", vector, rm))
+                } else {
+                    (op(mnemonic), format!("{},{}", rm, vector))
+                }
+            }
+            0x7e => {
+                let (reg, rm) = self.modrm()?;
+                (
+                    op("movq"),
+                    format!("xmm{},{}", reg, self.xmm_operand(&rm, 8)),
+                )
+            }
+            0xd6 if self.operand_size_prefix => {
+                let (reg, rm) = self.modrm()?;
+                (
+                    op("movq"),
+                    format!("{},xmm{}", self.xmm_operand(&rm, 8), reg),
+                )
+            }
+            0x6f | 0x7f if self.operand_size_prefix || self.repeat_prefix == Some(0xf3) => {
+                let mnemonic = if self.operand_size_prefix {
+                    "movdqa"
+                } else {
+                    "movdqu"
+                };
+                let (reg, rm) = self.modrm()?;
+                let rm = self.xmm_operand(&rm, 16);
+                if opcode == 0x6f {
+                    (op(mnemonic), format!("xmm{},{}", reg, rm))
+                } else {
+                    (op(mnemonic), format!("{},xmm{}", rm, reg))
+                }
+            }
+            0x80..=0x8f => (
+                format!("j{}", CONDITIONS[(opcode & 0xf) as usize]),
+                self.relative_target(4)?,
+            ),
+            0x90..=0x9f => {
+                let (_, rm) = self.modrm()?;
+                (
+                    format!("set{}", CONDITIONS[(opcode & 0xf) as usize]),
+                    self.rm_operand(&rm, 1),
+                )
+            }
+            0xa2 => (op("cpuid"), String::new()),
+            0xa3 | 0xab | 0xb3 | 0xbb => {
+                let mnemonic = match opcode {
+                    0xa3 => "bt",
+                    0xab => "bts",
+                    0xb3 => "btr",
+                    _ => "btc",
+                };
+                let (reg, rm) = self.modrm()?;
+                (
+                    op(mnemonic),
+                    format!(
+                        "{},{}",
+                        self.rm_operand(&rm, size),
+                        self.register(reg, size)
+                    ),
+                )
+            }
+            0xa4 | 0xa5 | 0xac | 0xad => {
+                let mnemonic = if opcode < 0xac { "shld" } else { "shrd" };
+                let (reg, rm) = self.modrm()?;
+                let count = if opcode & 1 == 0 {
+                    Self::format_immediate(self.immediate(1)?, 1)
+                } else {
+                    "cl".to_string()
+                };
+                (
+                    op(mnemonic),
+                    format!(
+                        "{},{},{}",
+                        self.rm_operand(&rm, size),
+                        self.register(reg, size),
+                        count
+                    ),
+                )
+            }
+            0xae => {
+                let modrm = *self.bytes.get(self.pos)?;
+                match modrm {
+                    0xe8 | 0xf0 | 0xf8 => {
+                        self.pos += 1;
+                        let mnemonic = match modrm {
+                            0xe8 => "lfence",
+                            0xf0 => "mfence",
+                            _ => "sfence",
+                        };
+                        (op(mnemonic), String::new())
+                    }
+                    _ => {
+                        let (reg, rm) = self.modrm()?;
+                        let mnemonic = match reg & 7 {
+                            2 => "ldmxcsr",
+                            3 => "stmxcsr",
+                            _ => return None,
+                        };
+                        (op(mnemonic), self.rm_operand(&rm, 4))
+                    }
+                }
+            }
+            0xaf => {
+                let (reg, rm) = self.modrm()?;
+                (
+                    op("imul"),
+                    format!(
+                        "{},{}",
+                        self.register(reg, size),
+                        self.rm_operand(&rm, size)
+                    ),
+                )
+            }
+            0xb0 | 0xb1 | 0xc0 | 0xc1 => {
+                let mnemonic = if opcode < 0xc0 { "cmpxchg" } else { "xadd" };
+                let size = if opcode & 1 == 0 { 1 } else { size };
+                let (reg, rm) = self.modrm()?;
+                (
+                    op(mnemonic),
+                    format!(
+                        "{},{}",
+                        self.rm_operand(&rm, size),
+                        self.register(reg, size)
+                    ),
+                )
+            }
+            0xb6 | 0xb7 | 0xbe | 0xbf => {
+                let mnemonic = if opcode < 0xbe { "movzx" } else { "movsx" };
+                let source_size = if opcode & 1 == 0 { 1 } else { 2 };
+                let (reg, rm) = self.modrm()?;
+                (
+                    op(mnemonic),
+                    format!(
+                        "{},{}",
+                        self.register(reg, size),
+                        self.rm_operand(&rm, source_size)
+                    ),
+                )
+            }
+            0xb8 if self.repeat_prefix == Some(0xf3) => {
+                let (reg, rm) = self.modrm()?;
+                (
+                    op("popcnt"),
+                    format!(
+                        "{},{}",
+                        self.register(reg, size),
+                        self.rm_operand(&rm, size)
+                    ),
+                )
+            }
+            0xba => {
+                let (reg, rm) = self.modrm()?;
+                let mnemonic = match reg & 7 {
+                    4 => "bt",
+                    5 => "bts",
+                    6 => "btr",
+                    7 => "btc",
+                    _ => return None,
+                };
+                let immediate = self.immediate(1)?;
+                (
+                    op(mnemonic),
+                    format!(
+                        "{},{}",
+                        self.rm_operand(&rm, size),
+                        Self::format_immediate(immediate, 1)
+                    ),
+                )
+            }
+            0xbc | 0xbd => {
+                let mnemonic = match (opcode, self.repeat_prefix) {
+                    (0xbc, Some(0xf3)) => "tzcnt",
+                    (0xbc, _) => "bsf",
+                    (_, Some(0xf3)) => "lzcnt",
+                    (_, _) => "bsr",
+                };
+                let (reg, rm) = self.modrm()?;
+                (
+                    op(mnemonic),
+                    format!(
+                        "{},{}",
+                        self.register(reg, size),
+                        self.rm_operand(&rm, size)
+                    ),
+                )
+            }
+            0xc8..=0xcf => (
+                op("bswap"),
+                self.register(self.opcode_register(opcode), size),
+            ),
+            _ => return None,
+        })
+    }
+}
+
+/// Mnemonic of the SSE2 integer instructions of the form `op xmm, xmm/m128` with a 0x66 prefix
+fn packed_integer_mnemonic(opcode: u8) -> Option<&'static str> {
+    Some(match opcode {
+        0x60 => "punpcklbw",
+        0x61 => "punpcklwd",
+        0x62 => "punpckldq",
+        0x63 => "packsswb",
+        0x64 => "pcmpgtb",
+        0x65 => "pcmpgtw",
+        0x66 => "pcmpgtd",
+        0x67 => "packuswb",
+        0x68 => "punpckhbw",
+        0x69 => "punpckhwd",
+        0x6a => "punpckhdq",
+        0x6b => "packssdw",
+        0x6c => "punpcklqdq",
+        0x6d => "punpckhqdq",
+        0x74 => "pcmpeqb",
+        0x75 => "pcmpeqw",
+        0x76 => "pcmpeqd",
+        0xd4 => "paddq",
+        0xd5 => "pmullw",
+        0xd8 => "psubusb",
+        0xd9 => "psubusw",
+        0xda => "pminub",
+        0xdb => "pand",
+        0xdc => "paddusb",
+        0xdd => "paddusw",
+        0xde => "pmaxub",
+        0xdf => "pandn",
+        0xe0 => "pavgb",
+        0xe3 => "pavgw",
+        0xe4 => "pmulhuw",
+        0xe5 => "pmulhw",
+        0xe8 => "psubsb",
+        0xe9 => "psubsw",
+        0xea => "pminsw",
+        0xeb => "por",
+        0xec => "paddsb",
+        0xed => "paddsw",
+        0xee => "pmaxsw",
+        0xef => "pxor",
+        0xf4 => "pmuludq",
+        0xf5 => "pmaddwd",
+        0xf6 => "psadbw",
+        0xf8 => "psubb",
+        0xf9 => "psubw",
+        0xfa => "psubd",
+        0xfb => "psubq",
+        0xfc => "paddb",
+        0xfd => "paddw",
+        0xfe => "paddd",
+        _ => return None,
+    })
+}
+
+/// Decode the instruction at the start of bytes, which are located at addr. Bytes that do not
+/// form a known instruction are shown as "(bad)" one at a time.
+pub fn decode(bytes: &[u8], addr: usize) -> Instruction {
+    let mut decoder = Decoder {
+        bytes,
+        addr,
+        pos: 0,
+        rex: 0,
+        operand_size_prefix: false,
+        lock_prefix: false,
+        repeat_prefix: None,
+        segment: None,
+        rip_displacement: None,
+        target: None,
+    };
+    match decoder.decode() {
+        Some((mnemonic, operands)) => {
+            let mnemonic = if decoder.lock_prefix {
+                format!("lock {}", mnemonic)
+            } else {
+                mnemonic
+            };
+            let mut text = if operands.is_empty() {
+                mnemonic
+            } else {
+                format!("{:<6} {}", mnemonic, operands)
+            };
+            let mut target = decoder.target;
+            if let Some(displacement) = decoder.rip_displacement {
+                let address = addr
+                    .wrapping_add(decoder.pos)
+                    .wrapping_add(displacement as usize);
+                text += &format!("        # {:#x}", address);
+                target = Some(address);
+            }
+            Instruction {
+                length: decoder.pos,
+                text,
+                target,
+            }
+        }
+        None => Instruction {
+            length: 1,
+            text: "(bad)".to_string(),
+            target: None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decode a whole instruction located at 0x1000
+    fn text(bytes: &[u8]) -> String {
+        let instruction = decode(bytes, 0x1000);
+        assert_eq!(instruction.length, bytes.len(), "{:02x?}", bytes);
+        instruction.text
+    }
+
+    #[test]
+    fn instructions() {
+        // the text objdump -M intel shows for the same bytes
+        let cases: &[(&[u8], &str)] = &[
+            // plain opcode
+            (&[0x55], "push   rbp"),
+            // REX.W
+            (&[0x48, 0x89, 0xe5], "mov    rbp,rsp"),
+            // REX.B extends the register in the opcode
+            (&[0x41, 0x57], "push   r15"),
+            // REX.W and REX.R
+            (&[0x4c, 0x89, 0xc7], "mov    rdi,r8"),
+            // ModRM with an 8-bit displacement
+            (&[0x89, 0x7d, 0xfc], "mov    DWORD PTR [rbp-0x4],edi"),
+            // SIB without an index
+            (&[0x8b, 0x04, 0x24], "mov    eax,DWORD PTR [rsp]"),
+            // SIB with a scaled index
+            (
+                &[0x8b, 0x44, 0x8d, 0x10],
+                "mov    eax,DWORD PTR [rbp+rcx*4+0x10]",
+            ),
+            // SIB without a base, REX.X extends the index
+            (
+                &[0x42, 0x8b, 0x04, 0xc5, 0x00, 0x10, 0x00, 0x00],
+                "mov    eax,DWORD PTR [r8*8+0x1000]",
+            ),
+            // RIP-relative, resolved from the end of the instruction
+            (
+                &[0x48, 0x8b, 0x05, 0x10, 0x00, 0x00, 0x00],
+                "mov    rax,QWORD PTR [rip+0x10]        # 0x1017",
+            ),
+            // objdump writes rip+0xfffffffffffffff9; deet keeps the sign
+            (
+                &[0x48, 0x8d, 0x3d, 0xf9, 0xff, 0xff, 0xff],
+                "lea    rdi,[rip-0x7]        # 0x1000",
+            ),
+            // sign-extended imm8
+            (&[0x83, 0xc0, 0x01], "add    eax,0x1"),
+            (&[0x48, 0x83, 0xec, 0x10], "sub    rsp,0x10"),
+            // imm32
+            (&[0x81, 0xec, 0x00, 0x01, 0x00, 0x00], "sub    esp,0x100"),
+            // imm32 sign-extended to 64 bits
+            (
+                &[0x48, 0xc7, 0xc0, 0xff, 0xff, 0xff, 0xff],
+                "mov    rax,0xffffffffffffffff",
+            ),
+            (&[0xb8, 0x2a, 0x00, 0x00, 0x00], "mov    eax,0x2a"),
+            // imm64
+            (
+                &[0x48, 0xb8, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11],
+                "movabs rax,0x1122334455667788",
+            ),
+            // rel32
+            (&[0xe8, 0x00, 0x00, 0x00, 0x00], "call   0x1005"),
+            // rel8
+            (&[0xeb, 0xfe], "jmp    0x1000"),
+            (&[0x74, 0x05], "je     0x1007"),
+            // two-byte opcodes
+            (&[0x0f, 0x84, 0x00, 0x01, 0x00, 0x00], "je     0x1106"),
+            (&[0x0f, 0xaf, 0xc1], "imul   eax,ecx"),
+            (&[0x0f, 0xb6, 0xc0], "movzx  eax,al"),
+            (
+                &[0x0f, 0x1f, 0x44, 0x00, 0x00],
+                "nop    DWORD PTR [rax+rax*1+0x0]",
+            ),
+            (&[0x0f, 0x94, 0xc0], "sete   al"),
+            // SSE with a mandatory prefix
+            (
+                &[0xf3, 0x0f, 0x10, 0x45, 0xfc],
+                "movss  xmm0,DWORD PTR [rbp-0x4]",
+            ),
+            (&[0x66, 0x0f, 0xef, 0xc0], "pxor   xmm0,xmm0"),
+            // an empty REX prefix selects sil rather than dh
+            (&[0x40, 0x88, 0xf0], "mov    al,sil"),
+            (&[0x88, 0xf0], "mov    al,dh"),
+            // operand size prefix
+            (&[0x66, 0x89, 0xc8], "mov    ax,cx"),
+            (&[0xc3], "ret"),
+            // segment override and an absolute address
+            (
+                &[0x64, 0x48, 0x8b, 0x04, 0x25, 0x28, 0x00, 0x00, 0x00],
+                "mov    rax,QWORD PTR fs:0x28",
+            ),
+            // lock prefix
+            (
+                &[0xf0, 0x0f, 0xb1, 0x0a],
+                "lock cmpxchg DWORD PTR [rdx],ecx",
+            ),
+            (&[0x41, 0xff, 0xd4], "call   r12"),
+            (
+                &[0xff, 0x24, 0xc5, 0x00, 0x20, 0x00, 0x00],
+                "jmp    QWORD PTR [rax*8+0x2000]",
+            ),
+            (&[0xc1, 0xe0, 0x03], "shl    eax,0x3"),
+            (&[0xf2, 0x0f, 0x58, 0xc1], "addsd  xmm0,xmm1"),
+            (&[0x0f, 0x05], "syscall"),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(text(bytes), *expected, "{:02x?}", bytes);
+        }
+    }
+
+    #[test]
+    fn targets() {
+        assert_eq!(
+            decode(&[0xe8, 0x00, 0x00, 0x00, 0x00], 0x1000).target,
+            Some(0x1005)
+        );
+        assert_eq!(decode(&[0x74, 0xfe], 0x1000).target, Some(0x1000));
+        assert_eq!(
+            decode(&[0x48, 0x8b, 0x05, 0x10, 0x00, 0x00, 0x00], 0x1000).target,
+            Some(0x1017)
+        );
+        assert_eq!(decode(&[0x48, 0x89, 0xe5], 0x1000).target, None);
+    }
+
+    #[test]
+    fn only_the_first_instruction() {
+        let instruction = decode(&[0x55, 0x48, 0x89, 0xe5], 0x1000);
+        assert_eq!(instruction.length, 1);
+        assert_eq!(instruction.text, "push   rbp");
+    }
+
+    #[test]
+    fn truncated() {
+        let instructions: &[&[u8]] = &[
+            &[0x48, 0x8b, 0x05, 0x10, 0x00, 0x00, 0x00],
+            &[0x42, 0x8b, 0x04, 0xc5, 0x00, 0x10, 0x00, 0x00],
+            &[0x8b, 0x44, 0x8d, 0x10],
+            &[0x48, 0xb8, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11],
+            &[0x81, 0xec, 0x00, 0x01, 0x00, 0x00],
+            &[0x0f, 0x84, 0x00, 0x01, 0x00, 0x00],
+            &[0xf3, 0x0f, 0x10, 0x45, 0xfc],
+            &[0x64, 0x48, 0x8b, 0x04, 0x25, 0x28, 0x00, 0x00, 0x00],
+        ];
+        for bytes in instructions {
+            for end in 0..bytes.len() {
+                let instruction = decode(&bytes[..end], 0x1000);
+                assert_eq!(instruction.text, "(bad)", "{:02x?}", &bytes[..end]);
+                assert_eq!(instruction.length, 1);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown() {
+        // invalid in 64-bit mode
+        for bytes in &[&[0x06][..], &[0xd6], &[0x62, 0x00], &[0xfe, 0xd0]] {
+            assert_eq!(decode(bytes, 0x1000).text, "(bad)", "{:02x?}", bytes);
+        }
+    }
+
+    #[test]
+    fn every_byte_pair() {
+        // whatever the bytes, decoding neither panics nor reads past them
+        for first in 0..=255u8 {
+            for second in 0..=255u8 {
+                for bytes in &[&[first, second][..], &[first, second, 0xff, 0xff, 0xff]] {
+                    let instruction = decode(bytes, usize::MAX - 2);
+                    assert!(instruction.length <= bytes.len().max(1));
+                }
+            }
+        }
+    }
+}
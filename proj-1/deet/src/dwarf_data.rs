@@ -1,6 +1,6 @@
 use crate::gimli_wrapper;
 use addr2line::Context;
-use object::Object;
+use object::{Object, ObjectSection, ObjectSegment};
 use std::convert::TryInto;
 use std::{fmt, fs};
 
@@ -10,14 +10,38 @@ pub enum Error {
     DwarfFormatError(gimli_wrapper::Error),
 }
 
-pub struct DwarfData {
+/// The debugging information of one ELF file, i.e. the program or a shared library. Addresses
+/// in files are where the code and data are in the process, i.e. shifted by bias from the
+/// addresses the object was linked at.
+struct ObjectData {
+    path: String,
+    /// How far the object is loaded from the addresses it was linked at: 0 for an executable
+    /// linked with -no-pie, and the load address of a position-independent executable or a
+    /// shared library
+    bias: usize,
+    /// Addresses the loadable segments span, as linked
+    start: usize,
+    end: usize,
     files: Vec<File>,
     addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    /// Address and contents of the .text section, as linked, for disassembling when there is no
+    /// process
+    text_address: usize,
+    text: Vec<u8>,
+    /// The entry point and the address of the .dynamic section, as linked
+    entry: usize,
+    dynamic: Option<usize>,
+}
+
+/// The debugging information of the program, then of the shared libraries it loaded
+pub struct DwarfData {
+    objects: Vec<ObjectData>,
 }
 
 impl fmt::Debug for DwarfData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "DwarfData {{files: {:?}}}", self.files)
+        let files: Vec<&File> = self.files().collect();
+        write!(f, "DwarfData {{files: {:?}}}", files)
     }
 }
 
@@ -27,71 +51,285 @@ impl From<gimli_wrapper::Error> for Error {
     }
 }
 
-impl DwarfData {
-    pub fn from_file(path: &str) -> Result<DwarfData, Error> {
+/// Move the addresses of the functions, lines and static variables of files by delta
+fn relocate_files(files: &mut [File], delta: usize) {
+    let relocate_location = |location: &mut Location| match location {
+        Location::Address(addr) => *addr = addr.wrapping_add(delta),
+        Location::Expression(expression) => expression.bias = expression.bias.wrapping_add(delta),
+        Location::List(entries) => {
+            for (start, end, expression) in entries {
+                *start = start.wrapping_add(delta);
+                *end = end.wrapping_add(delta);
+                expression.bias = expression.bias.wrapping_add(delta);
+            }
+        }
+        _ => (),
+    };
+    let relocate_variable = |var: &mut Variable| relocate_location(&mut var.location);
+    for file in files {
+        file.global_variables.iter_mut().for_each(relocate_variable);
+        for func in &mut file.functions {
+            func.address = func.address.wrapping_add(delta);
+            func.frame_base.iter_mut().for_each(relocate_location);
+            func.variables.iter_mut().for_each(relocate_variable);
+        }
+        for line in &mut file.lines {
+            line.address = line.address.wrapping_add(delta);
+        }
+    }
+}
+
+impl ObjectData {
+    fn from_file(path: &str, bias: usize) -> Result<ObjectData, Error> {
         let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
         let mmap = unsafe { memmap::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? };
-        let object = object::File::parse(&*mmap)
-            .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?;
+        let object =
+            object::File::parse(&mmap).map_err(|e| gimli_wrapper::Error::Object(e.to_string()))?;
         let endian = if object.is_little_endian() {
             gimli::RunTimeEndian::Little
         } else {
             gimli::RunTimeEndian::Big
         };
+        let text_address = object
+            .section_by_name(".text")
+            .map_or(0, |text| text.address() as usize);
+        let text = object
+            .section_data_by_name(".text")
+            .map_or_else(Vec::new, |data| data.to_vec());
+        let start = object
+            .segments()
+            .map(|segment| segment.address() as usize)
+            .min()
+            .unwrap_or(0);
+        let end = object
+            .segments()
+            .map(|segment| (segment.address() + segment.size()) as usize)
+            .max()
+            .unwrap_or(0);
+        let mut files = gimli_wrapper::load_file(&object, endian)?;
+        relocate_files(&mut files, bias);
+        Ok(ObjectData {
+            path: path.to_string(),
+            bias,
+            start,
+            end,
+            files,
+            addr2line: Context::new(&object).map_err(gimli_wrapper::Error::from)?,
+            text_address,
+            text,
+            entry: object.entry() as usize,
+            dynamic: object
+                .section_by_name(".dynamic")
+                .map(|dynamic| dynamic.address() as usize),
+        })
+    }
+
+    /// Whether addr is in the memory the object is loaded at
+    fn contains(&self, addr: usize) -> bool {
+        let linked = addr.wrapping_sub(self.bias);
+        self.start <= linked && linked < self.end
+    }
+}
+
+/// A shared library loaded into the process
+pub struct Library<'a> {
+    pub path: &'a str,
+    /// Where the .text section of the library is
+    pub text_start: usize,
+    pub text_end: usize,
+    /// Whether the library has debugging information
+    pub has_debug_info: bool,
+}
+
+impl DwarfData {
+    pub fn from_file(path: &str) -> Result<DwarfData, Error> {
         Ok(DwarfData {
-            files: gimli_wrapper::load_file(&object, endian)?,
-            addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            objects: vec![ObjectData::from_file(path, 0)?],
         })
     }
 
-    #[allow(dead_code)]
-    fn get_target_file(&self, file: &str) -> Option<&File> {
-        self.files.iter().find(|f| {
-            f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
+    /// The compilation units of the program and of the loaded libraries
+    fn files(&self) -> impl Iterator<Item = &File> {
+        self.objects.iter().flat_map(|object| object.files.iter())
+    }
+
+    /// The object that is loaded at addr
+    fn object_at(&self, addr: usize) -> Option<&ObjectData> {
+        self.objects.iter().find(|object| object.contains(addr))
+    }
+
+    /// Move the program to where the process loaded it, given the entry point of the process.
+    /// Returns how far the program moved, which is 0 unless it is position independent, as a
+    /// wrapping offset to add to addresses.
+    pub fn relocate_program(&mut self, entry: usize) -> usize {
+        let program = &mut self.objects[0];
+        let delta = entry.wrapping_sub(program.entry.wrapping_add(program.bias));
+        program.bias = program.bias.wrapping_add(delta);
+        relocate_files(&mut program.files, delta);
+        delta
+    }
+
+    /// Where the .dynamic section of the program is, or None if it is statically linked
+    pub fn dynamic_section(&self) -> Option<usize> {
+        let program = &self.objects[0];
+        program
+            .dynamic
+            .map(|dynamic| dynamic.wrapping_add(program.bias))
+    }
+
+    /// Whether addr is in the program rather than in a shared library
+    pub fn in_program(&self, addr: usize) -> bool {
+        self.objects[0].contains(addr)
+    }
+
+    /// Whether addr is in a shared library rather than in the program
+    pub fn in_library(&self, addr: usize) -> bool {
+        self.objects[1..].iter().any(|object| object.contains(addr))
+    }
+
+    /// The shared libraries that are loaded, in the order they were loaded
+    pub fn libraries(&self) -> Vec<Library<'_>> {
+        self.objects[1..]
+            .iter()
+            .map(|object| Library {
+                path: &object.path,
+                text_start: object.text_address.wrapping_add(object.bias),
+                text_end: (object.text_address + object.text.len()).wrapping_add(object.bias),
+                has_debug_info: !object.files.is_empty(),
+            })
+            .collect()
+    }
+
+    /// Load the debugging information of a shared library loaded bias away from the addresses
+    /// it was linked at
+    pub fn load_library(&mut self, path: &str, bias: usize) -> Result<(), Error> {
+        let library = ObjectData::from_file(path, bias)?;
+        self.objects.push(library);
+        Ok(())
+    }
+
+    /// Forget the shared libraries that are not in the given list of paths and load biases
+    pub fn keep_libraries(&mut self, libraries: &[(String, usize)]) {
+        let program = self.objects.remove(0);
+        self.objects.retain(|object| {
+            libraries
+                .iter()
+                .any(|(path, bias)| *path == object.path && *bias == object.bias)
+        });
+        self.objects.insert(0, program);
+    }
+
+    /// Whether a shared library is loaded
+    pub fn has_library(&self, path: &str, bias: usize) -> bool {
+        self.objects[1..]
+            .iter()
+            .any(|object| object.path == path && object.bias == bias)
+    }
+
+    /// Find the compilation units matching a file name, which may be given as a bare name
+    /// ("foo.c"), a path relative to the compilation directory or an absolute path
+    fn get_target_files<'a>(&'a self, file: &'a str) -> impl Iterator<Item = &'a File> + 'a {
+        self.files().filter(move |f| {
+            f.name == file
+                || f.name.ends_with(&format!("/{}", file))
+                || file.ends_with(&format!("/{}", f.name))
         })
     }
 
     #[allow(dead_code)]
     pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
-        let target_file = match file {
-            Some(filename) => self.get_target_file(filename)?,
-            None => self.files.get(0)?,
+        self.get_addrs_for_line(file, line_number).first().copied()
+    }
+
+    /// Find every address a breakpoint on a line should be set at, in the given file or in all
+    /// files. A line without code resolves to the next line that has some, and a line whose code
+    /// is spread over several functions (e.g. in a macro) gets one address per function.
+    pub fn get_addrs_for_line(&self, file: Option<&str>, line_number: usize) -> Vec<usize> {
+        let target_files: Vec<&File> = match file {
+            Some(filename) => self.get_target_files(filename).collect(),
+            None => self.files().collect(),
         };
-        Some(
-            target_file
+        let mut addrs: Vec<usize> = Vec::new();
+        for target_file in target_files {
+            let number = match target_file
                 .lines
                 .iter()
-                .find(|line| line.number >= line_number)?
-                .address,
-        )
+                .map(|line| line.number)
+                .filter(|number| *number >= line_number)
+                .min()
+            {
+                Some(number) => number,
+                None => continue,
+            };
+            let mut line_addrs: Vec<usize> = target_file
+                .lines
+                .iter()
+                .filter(|line| line.number == number)
+                .map(|line| line.address)
+                .collect();
+            line_addrs.sort();
+            let mut functions = Vec::new();
+            for addr in line_addrs {
+                let function = self.get_function(addr).map(|func| func.address);
+                if !functions.contains(&function) {
+                    functions.push(function);
+                    addrs.push(addr);
+                }
+            }
+        }
+        addrs
     }
 
     #[allow(dead_code)]
     pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
-        match file {
-            Some(filename) => Some(
-                self.get_target_file(filename)?
-                    .functions
+        self.get_addrs_for_function(file, func_name)
+            .first()
+            .copied()
+    }
+
+    /// Find the addresses of every function with the given name, in the given file or in all
+    /// files. There may be several, e.g. static functions of different files.
+    pub fn get_addrs_for_function(&self, file: Option<&str>, func_name: &str) -> Vec<usize> {
+        let target_files: Vec<&File> = match file {
+            Some(filename) => self.get_target_files(filename).collect(),
+            None => self.files().collect(),
+        };
+        let mut addrs: Vec<usize> = target_files
+            .iter()
+            .flat_map(|file| file.functions.iter())
+            // declarations of external functions have no code
+            .filter(|func| func.name == func_name && func.text_length > 0)
+            .map(|func| func.address)
+            .collect();
+        addrs.sort();
+        addrs.dedup();
+        addrs
+    }
+
+    /// Find where the body of the function at addr starts, past the prologue that sets up its
+    /// frame and stores its arguments. Like gdb, this is the address of the second row of the
+    /// line table in the function; a function with a single row has no prologue to skip.
+    pub fn skip_prologue(&self, addr: usize) -> usize {
+        for file in self.files() {
+            if let Some(func) = file.functions.iter().find(|func| func.address == addr) {
+                return file
+                    .lines
                     .iter()
-                    .find(|func| func.name == func_name)?
-                    .address,
-            ),
-            None => {
-                for file in &self.files {
-                    if let Some(func) = file.functions.iter().find(|func| func.name == func_name) {
-                        return Some(func.address);
-                    }
-                }
-                None
+                    .map(|line| line.address)
+                    .filter(|line_addr| addr < *line_addr && *line_addr < addr + func.text_length)
+                    .min()
+                    .unwrap_or(addr);
             }
         }
+        addr
     }
 
     #[allow(dead_code)]
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
-        let location = self
+        let object = self.object_at(curr_addr)?;
+        let location = object
             .addr2line
-            .find_location(curr_addr.try_into().unwrap())
+            .find_location((curr_addr - object.bias).try_into().unwrap())
             .ok()??;
         Some(Line {
             file: location.file?.to_string(),
@@ -102,18 +340,96 @@ impl DwarfData {
 
     #[allow(dead_code)]
     pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
-        let frame = self
+        let object = self.object_at(curr_addr)?;
+        let frame = object
             .addr2line
-            .find_frames(curr_addr.try_into().unwrap())
+            .find_frames((curr_addr - object.bias).try_into().unwrap())
             .ok()?
             .next()
             .ok()??;
         Some(frame.function?.raw_name().ok()?.to_string())
     }
 
+    /// Read up to len bytes of the .text section of the executable or of a library, starting at
+    /// addr
+    pub fn read_text(&self, addr: usize, len: usize) -> Option<&[u8]> {
+        let object = self.object_at(addr)?;
+        let start = (addr - object.bias).checked_sub(object.text_address)?;
+        let end = (start + len).min(object.text.len());
+        object
+            .text
+            .get(start..end)
+            .filter(|bytes| !bytes.is_empty())
+    }
+
+    /// Find the function whose text contains curr_addr
+    pub fn get_function(&self, curr_addr: usize) -> Option<&Function> {
+        self.files()
+            .flat_map(|file| file.functions.iter())
+            .find(|func| func.address <= curr_addr && curr_addr < func.address + func.text_length)
+    }
+
+    /// Look up a variable visible at curr_addr: a local variable or parameter of the enclosing
+    /// function, a global variable of its file, or a global variable of any other file
+    pub fn get_variable(&self, curr_addr: usize, name: &str) -> Option<&Variable> {
+        for file in self.files() {
+            let func = file.functions.iter().find(|func| {
+                func.address <= curr_addr && curr_addr < func.address + func.text_length
+            });
+            if let Some(func) = func {
+                if let Some(var) = func.variables.iter().find(|var| var.name == name) {
+                    return Some(var);
+                }
+                if let Some(var) = file.global_variables.iter().find(|var| var.name == name) {
+                    return Some(var);
+                }
+            }
+        }
+        self.files()
+            .flat_map(|file| file.global_variables.iter())
+            .find(|var| var.name == name)
+    }
+
+    /// Look up a type defined at the top level of a file by name, e.g. "struct point" or the
+    /// name of a typedef
+    pub fn get_type(&self, name: &str) -> Option<&Type> {
+        self.files()
+            .flat_map(|file| file.types.iter())
+            .find(|defined| defined.name == name)
+    }
+
+    /// Look up an enumerator by name, returning its enumeration type and its value
+    pub fn get_enumerator(&self, name: &str) -> Option<(&Type, i64)> {
+        self.files()
+            .flat_map(|file| file.types.iter())
+            .find_map(|defined| match &defined.kind {
+                TypeKind::Enum(enumerators) => enumerators
+                    .iter()
+                    .find(|(enumerator, _)| enumerator == name)
+                    .map(|(_, value)| (defined, *value)),
+                _ => None,
+            })
+    }
+
+    /// The definition of a structure or union that the given type only declares, looked up by
+    /// name. Other types are complete already.
+    pub fn complete_type<'a>(&'a self, entity_type: &'a Type) -> &'a Type {
+        match &entity_type.kind {
+            TypeKind::Struct(members) if members.is_empty() => self
+                .files()
+                .flat_map(|file| file.types.iter())
+                .find(|defined| {
+                    defined.name == entity_type.name
+                        && matches!(&defined.kind, TypeKind::Struct(members) if !members.is_empty())
+                })
+                .unwrap_or(entity_type),
+            _ => entity_type,
+        }
+    }
+
     #[allow(dead_code)]
     pub fn print(&self) {
-        for file in &self.files {
+        for file in self.files() {
             println!("------");
             println!("{}", file.name);
             println!("------");
@@ -148,18 +464,319 @@ impl DwarfData {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+/// Most elements of an array, or characters of a string, that are shown
+const MAX_ELEMENTS: usize = 200;
+
+/// Repetitions of an element beyond which they are shown as "<repeats n times>"
+const REPEAT_THRESHOLD: usize = 10;
+
+/// How the bits of a base type are interpreted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Signed,
+    Unsigned,
+    SignedChar,
+    UnsignedChar,
+    Boolean,
+    Float,
+}
+
+/// A member of a structure or union
+#[derive(Debug, Clone)]
+pub struct Member {
+    /// Empty for an anonymous structure or union nested in another one
+    pub name: String,
+    /// Offset of the first byte holding the member
+    pub offset: usize,
+    /// Offset in bits from that byte, and width in bits, of a bit field
+    pub bit_field: Option<(usize, usize)>,
+    pub member_type: Type,
+}
+
+#[derive(Debug, Clone)]
+pub enum TypeKind {
+    Base(Encoding),
+    /// A pointer to a type, or to void
+    Pointer(Option<Box<Type>>),
+    /// A structure or union. One that is only declared, or that is reached through a pointer,
+    /// has no members: DwarfData::complete_type finds its definition by name.
+    Struct(Vec<Member>),
+    /// An array of elements, of the given length unless it is flexible
+    Array(Box<Type>, Option<usize>),
+    /// An enumeration, with the names and values of its enumerators
+    Enum(Vec<(String, i64)>),
+    Function,
+}
+
+#[derive(Debug, Clone)]
 pub struct Type {
     pub name: String,
     pub size: usize,
+    pub kind: TypeKind,
+}
+
+/// Escape a byte of a string or character literal, delimited by quote, the way C source would
+/// spell it
+fn escape_byte(byte: u8, quote: u8) -> String {
+    match byte {
+        b'\\' => "\\\\".to_string(),
+        _ if byte == quote => format!("\\{}", quote as char),
+        b'\n' => "\\n".to_string(),
+        b'\r' => "\\r".to_string(),
+        b'\t' => "\\t".to_string(),
+        0x20..=0x7e => (byte as char).to_string(),
+        _ => format!("\\{:03o}", byte),
+    }
+}
+
+/// Format the bytes of a char array as a string. The NUL bytes padding it are counted rather
+/// than shown, like gdb does.
+fn format_string(bytes: &[u8]) -> String {
+    let end = bytes
+        .iter()
+        .rposition(|byte| *byte != 0)
+        .map_or(0, |last| last + 1);
+    let text: String = bytes[..end.min(MAX_ELEMENTS)]
+        .iter()
+        .map(|byte| escape_byte(*byte, b'"'))
+        .collect();
+    let ellipsis = if end > MAX_ELEMENTS { "..." } else { "" };
+    // the first NUL terminates the string
+    let padding = (bytes.len() - end).saturating_sub(1);
+    if padding <= REPEAT_THRESHOLD {
+        format!("\"{}{}\"{}", text, "\\000".repeat(padding), ellipsis)
+    } else if text.is_empty() {
+        format!("'\\000' <repeats {} times>", padding)
+    } else {
+        format!(
+            "\"{}\"{}, '\\000' <repeats {} times>",
+            text, ellipsis, padding
+        )
+    }
+}
+
+impl Member {
+    /// The bytes of the member, given the bytes of the structure holding it
+    pub fn value(&self, bytes: &[u8]) -> Vec<u8> {
+        let bytes = bytes.get(self.offset..).unwrap_or(&[]);
+        match self.bit_field {
+            Some((bit_offset, bit_size)) => {
+                self.member_type.extract_bits(bytes, bit_offset, bit_size)
+            }
+            None => bytes[..self.member_type.size.min(bytes.len())].to_vec(),
+        }
+    }
 }
 
 impl Type {
-    pub fn new(name: String, size: usize) -> Self {
-        Type {
-            name: name,
-            size: size,
+    pub fn new(name: String, size: usize, kind: TypeKind) -> Self {
+        Type { name, size, kind }
+    }
+
+    /// A pointer to a type, or to void, named the way C spells it
+    pub fn pointer_to(pointee: Option<Type>) -> Self {
+        let name = match &pointee {
+            None => "void *".to_string(),
+            Some(pointee) => match pointee.kind {
+                TypeKind::Function => pointee.name.replacen('(', "(*)(", 1),
+                TypeKind::Array(..) => pointee.name.replacen(" [", " (*)[", 1),
+                _ if pointee.name.ends_with('*') => format!("{}*", pointee.name),
+                _ => format!("{} *", pointee.name),
+            },
+        };
+        Type::new(
+            name,
+            std::mem::size_of::<usize>(),
+            TypeKind::Pointer(pointee.map(Box::new)),
+        )
+    }
+
+    /// Whether the integers of this type are signed
+    pub fn is_signed(&self) -> bool {
+        match &self.kind {
+            TypeKind::Base(encoding) => {
+                *encoding == Encoding::Signed || *encoding == Encoding::SignedChar
+            }
+            TypeKind::Enum(enumerators) => enumerators.iter().any(|(_, value)| *value < 0),
+            _ => false,
+        }
+    }
+
+    /// Interpret the little-endian bytes of a value of this type as an integer
+    pub fn integer_value(&self, bytes: &[u8]) -> Option<i64> {
+        match &self.kind {
+            TypeKind::Base(Encoding::Float) => return None,
+            TypeKind::Base(_) | TypeKind::Pointer(_) | TypeKind::Enum(_) => (),
+            _ => return None,
+        }
+        if self.size == 0 || self.size > 8 || bytes.len() < self.size {
+            return None;
         }
+        let mut raw = [0u8; 8];
+        raw[..self.size].copy_from_slice(&bytes[..self.size]);
+        let unsigned = u64::from_le_bytes(raw);
+        let shift = 64 - 8 * self.size as u32;
+        if self.is_signed() {
+            Some(((unsigned << shift) as i64) >> shift)
+        } else {
+            Some(unsigned as i64)
+        }
+    }
+
+    /// Extract a bit field of this type from the bytes starting at its first byte, as the bytes
+    /// of a value of this type
+    pub fn extract_bits(&self, bytes: &[u8], bit_offset: usize, bit_size: usize) -> Vec<u8> {
+        let mut raw = [0u8; 8];
+        let len = bytes.len().min(8);
+        raw[..len].copy_from_slice(&bytes[..len]);
+        let mut value = u64::from_le_bytes(raw) >> bit_offset;
+        if bit_size < 64 {
+            value &= (1 << bit_size) - 1;
+            if bit_size > 0 && self.is_signed() && value >> (bit_size - 1) & 1 == 1 {
+                value |= !0 << bit_size;
+            }
+        }
+        value.to_le_bytes()[..self.size.min(8)].to_vec()
+    }
+
+    /// Find a member of a structure or union, looking into its anonymous members as well.
+    /// Returns the member and the offset of the member holding it.
+    pub fn member(&self, name: &str) -> Option<(usize, &Member)> {
+        let members = match &self.kind {
+            TypeKind::Struct(members) => members,
+            _ => return None,
+        };
+        for member in members {
+            if member.name == name {
+                return Some((0, member));
+            }
+            if member.name.is_empty() {
+                if let Some((offset, nested)) = member.member_type.member(name) {
+                    return Some((member.offset + offset, nested));
+                }
+            }
+        }
+        None
+    }
+
+    /// Format the little-endian bytes of a value of this type
+    pub fn format_value(&self, bytes: &[u8]) -> String {
+        if bytes.len() < self.size {
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            return format!("0x{}", hex.join(""));
+        }
+        match &self.kind {
+            TypeKind::Base(encoding) => self.format_base(*encoding, bytes),
+            TypeKind::Pointer(_) => format!("{:#x}", self.integer_value(bytes).unwrap_or(0)),
+            TypeKind::Enum(enumerators) => {
+                let value = self.integer_value(bytes).unwrap_or(0);
+                match enumerators
+                    .iter()
+                    .find(|(_, enumerator)| *enumerator == value)
+                {
+                    Some((name, _)) => name.clone(),
+                    None => value.to_string(),
+                }
+            }
+            TypeKind::Struct(members) if members.is_empty() => "<incomplete type>".to_string(),
+            TypeKind::Struct(members) => {
+                let fields: Vec<String> = members
+                    .iter()
+                    .map(|member| {
+                        let value = member.member_type.format_value(&member.value(bytes));
+                        if member.name.is_empty() {
+                            value
+                        } else {
+                            format!("{} = {}", member.name, value)
+                        }
+                    })
+                    .collect();
+                format!("{{{}}}", fields.join(", "))
+            }
+            TypeKind::Array(element, length) => {
+                let count = length.unwrap_or(0);
+                if let TypeKind::Base(Encoding::SignedChar)
+                | TypeKind::Base(Encoding::UnsignedChar) = element.kind
+                {
+                    if element.size == 1 {
+                        return format_string(&bytes[..count]);
+                    }
+                }
+                let values: Vec<String> = (0..count.min(MAX_ELEMENTS))
+                    .map(|index| element.format_value(&bytes[index * element.size..]))
+                    .collect();
+                // runs of the same value are shown once
+                let mut elements = Vec::new();
+                let mut index = 0;
+                while index < values.len() {
+                    let run = values[index..]
+                        .iter()
+                        .take_while(|value| **value == values[index])
+                        .count();
+                    if run > REPEAT_THRESHOLD {
+                        elements.push(format!("{} <repeats {} times>", values[index], run));
+                    } else {
+                        elements.extend(values[index..index + run].iter().cloned());
+                    }
+                    index += run;
+                }
+                if count > MAX_ELEMENTS {
+                    elements.push("...".to_string());
+                }
+                format!("{{{}}}", elements.join(", "))
+            }
+            TypeKind::Function => format!("{{{}}}", self.name),
+        }
+    }
+
+    /// Format the little-endian bytes of a number, character or boolean
+    fn format_base(&self, encoding: Encoding, bytes: &[u8]) -> String {
+        if self.size == 0 || self.size > 8 {
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            return format!("0x{}", hex.join(""));
+        }
+        let mut raw = [0u8; 8];
+        raw[..self.size].copy_from_slice(&bytes[..self.size]);
+        let unsigned = u64::from_le_bytes(raw);
+        let shift = 64 - 8 * self.size as u32;
+        let signed = ((unsigned << shift) as i64) >> shift;
+        match encoding {
+            Encoding::Boolean => (unsigned != 0).to_string(),
+            Encoding::Float if self.size == 4 => f32::from_bits(unsigned as u32).to_string(),
+            Encoding::Float if self.size == 8 => f64::from_bits(unsigned).to_string(),
+            Encoding::Float => format!("{:#x}", unsigned),
+            Encoding::SignedChar | Encoding::UnsignedChar if self.size == 1 => {
+                let value = if encoding == Encoding::UnsignedChar {
+                    unsigned as i64
+                } else {
+                    signed
+                };
+                format!("{} '{}'", value, escape_byte(unsigned as u8, b'\''))
+            }
+            Encoding::Unsigned | Encoding::UnsignedChar => unsigned.to_string(),
+            Encoding::Signed | Encoding::SignedChar => signed.to_string(),
+        }
+    }
+}
+
+/// A DWARF expression that computes the location of a variable, see location.rs
+#[derive(Clone)]
+pub struct Expression {
+    pub bytes: Vec<u8>,
+    pub encoding: gimli::Encoding,
+    /// How far the object is loaded from the addresses it was linked at, to add to the operands
+    /// of DW_OP_addr
+    pub bias: usize,
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex: Vec<String> = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        write!(f, "Expression({})", hex.join(" "))
     }
 }
 
@@ -167,13 +784,34 @@ impl Type {
 pub enum Location {
     Address(usize),
     FramePointerOffset(isize),
+    /// Anything more involved than the two above, e.g. a register or a value split in pieces
+    Expression(Expression),
+    /// A location list: each expression applies to the pc range [start, end), and the variable
+    /// is optimized out at any other pc
+    List(Vec<(usize, usize, Expression)>),
+    /// The little-endian bytes of a variable that the compiler replaced by a constant
+    Value(Vec<u8>),
+    OptimizedOut,
 }
 
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
+        match self {
             Location::Address(addr) => write!(f, "Address({:#x})", addr),
             Location::FramePointerOffset(offset) => write!(f, "FramePointerOffset({})", offset),
+            Location::Expression(expression) => write!(f, "{}", expression),
+            Location::List(entries) => {
+                write!(f, "List(")?;
+                for (index, (start, end, expression)) in entries.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "[{:#x}, {:#x}): {}", start, end, expression)?;
+                }
+                write!(f, ")")
+            }
+            Location::Value(bytes) => write!(f, "Value({:?})", bytes),
+            Location::OptimizedOut => write!(f, "OptimizedOut"),
         }
     }
 }
@@ -191,6 +829,7 @@ pub struct Variable {
     pub entity_type: Type,
     pub location: Location,
     pub line_number: usize, // Line number in source file
+    pub is_parameter: bool,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -199,12 +838,16 @@ pub struct Function {
     pub address: usize,
     pub text_length: usize,
     pub line_number: usize, // Line number in source file
+    /// DW_AT_frame_base, which DW_OP_fbreg offsets are relative to
+    pub frame_base: Option<Location>,
     pub variables: Vec<Variable>,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct File {
     pub name: String,
+    /// The named types defined at the top level of the file
+    pub types: Vec<Type>,
     pub global_variables: Vec<Variable>,
     pub functions: Vec<Function>,
     pub lines: Vec<Line>,
@@ -223,4 +866,42 @@ impl fmt::Display for Line {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::samples::build_sample;
+
+    #[test]
+    fn prototypes_before_functions() {
+        // signal.h declares signal and raise, and their parameters come before any function
+        let (program, _) = build_sample("signals");
+        let debug_data = DwarfData::from_file(&program).unwrap();
+        let variables = |function: &str| -> Vec<String> {
+            let addr = debug_data.get_addr_for_function(None, function).unwrap();
+            let function = debug_data.get_function(addr).unwrap();
+            function
+                .variables
+                .iter()
+                .map(|var| var.name.clone())
+                .collect()
+        };
+        assert_eq!(variables("handler"), ["sig"]);
+        assert_eq!(variables("main"), ["p", "c", "values"]);
+        let main = debug_data.get_addr_for_function(None, "main").unwrap();
+        assert!(debug_data.get_variable(main, "handled").is_some());
+    }
 
+    #[test]
+    fn prologues() {
+        let (program, _) = build_sample("signals");
+        let debug_data = DwarfData::from_file(&program).unwrap();
+        let body = |function: &str| {
+            let addr = debug_data.get_addr_for_function(None, function).unwrap();
+            let body = debug_data.skip_prologue(addr);
+            assert!(body > addr);
+            debug_data.get_line_from_addr(body).unwrap().number
+        };
+        assert_eq!(body("handler"), 14);
+        assert_eq!(body("main"), 18);
+    }
+}
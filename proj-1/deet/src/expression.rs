@@ -0,0 +1,959 @@
+//! The C expressions that print, set var, x and breakpoint conditions take, e.g.
+//! `list->next->value * 2`, `(long)&arr[i + 1]`, `x = 5` or `$rax == 0x10`. This file turns the
+//! text of an expression into a tree; Debugger evaluates the tree against the inferior.
+
+use crate::dwarf_data::{Encoding, Type, TypeKind};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOperator {
+    Negate,
+    Plus,
+    Not,
+    BitNot,
+    Dereference,
+    AddressOf,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOperator {
+    Mul,
+    Div,
+    Mod,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+/// A type written in a cast or in sizeof: base type keywords (unsigned long), a structure,
+/// union or enumeration (struct point) or a typedef name, followed by any number of *
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeName {
+    pub base: String,
+    pub pointers: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    /// An integer literal, with its u and l suffixes
+    Integer {
+        value: i64,
+        unsigned: bool,
+        long: bool,
+    },
+    Float(f64),
+    Char(u8),
+    /// A variable, or an enumerator
+    Variable(String),
+    /// A register of the inferior, written as $name
+    Register(String),
+    Unary(UnaryOperator, Box<Expr>),
+    Binary(BinaryOperator, Box<Expr>, Box<Expr>),
+    /// condition ? value : otherwise
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// An assignment; x += 1 is kept as x = x + 1
+    Assign(Box<Expr>, Box<Expr>),
+    /// A member of a structure or union; p->x is kept as (*p).x
+    Member(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Cast(TypeName, Box<Expr>),
+    SizeofType(TypeName),
+    SizeofValue(Box<Expr>),
+}
+
+/// A parsed expression, along with its text for showing it back to the user
+#[derive(Clone, PartialEq)]
+pub struct Expression {
+    pub text: String,
+    pub root: Expr,
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// Parse a decimal or 0x-prefixed hexadecimal integer, possibly negative
+pub fn parse_constant(token: &str) -> Option<i64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+    let value = if digits.to_lowercase().starts_with("0x") {
+        i64::from_str_radix(&digits[2..], 16).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// Keywords that make up the names of the base types of C
+const BASE_KEYWORDS: [&str; 10] = [
+    "void", "char", "short", "int", "long", "float", "double", "signed", "unsigned", "_Bool",
+];
+
+/// The base type named by keywords such as "unsigned long" or "char", or None if the name is
+/// not made of base type keywords. void is not a type that values can have, so it is None too.
+pub fn base_type(name: &str) -> Option<Type> {
+    let words: Vec<&str> = name.split_whitespace().collect();
+    if words.is_empty() || words.iter().any(|word| !BASE_KEYWORDS.contains(word)) {
+        return None;
+    }
+    let has = |keyword: &str| words.contains(&keyword);
+    let unsigned = has("unsigned");
+    let integer = |size| {
+        let encoding = if unsigned {
+            Encoding::Unsigned
+        } else {
+            Encoding::Signed
+        };
+        (size, encoding)
+    };
+    let (size, encoding) = if has("void") {
+        return None;
+    } else if has("_Bool") {
+        (1, Encoding::Boolean)
+    } else if has("float") {
+        (4, Encoding::Float)
+    } else if has("double") {
+        (if has("long") { 16 } else { 8 }, Encoding::Float)
+    } else if has("char") {
+        let encoding = if unsigned {
+            Encoding::UnsignedChar
+        } else {
+            Encoding::SignedChar
+        };
+        (1, encoding)
+    } else if has("short") {
+        integer(2)
+    } else if has("long") {
+        integer(8)
+    } else {
+        integer(4)
+    };
+    Some(Type::new(words.join(" "), size, TypeKind::Base(encoding)))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Integer(u64, bool, bool),
+    Float(f64),
+    Char(u8),
+    Identifier(String),
+    Register(String),
+    Symbol(&'static str),
+    End,
+}
+
+/// Operators and punctuation, longest first so that "<=" is not taken for "<"
+const SYMBOLS: [&str; 39] = [
+    "<<=", ">>=", "->", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=", "*=", "/=",
+    "%=", "&=", "|=", "^=", "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "|", "^", "(",
+    ")", "[", "]", ".", "?", ":",
+];
+
+/// The binary operators, with their precedence: the higher, the tighter they bind
+const BINARY_OPERATORS: [(&str, BinaryOperator, usize); 18] = [
+    ("||", BinaryOperator::Or, 1),
+    ("&&", BinaryOperator::And, 2),
+    ("|", BinaryOperator::BitOr, 3),
+    ("^", BinaryOperator::BitXor, 4),
+    ("&", BinaryOperator::BitAnd, 5),
+    ("==", BinaryOperator::Eq, 6),
+    ("!=", BinaryOperator::Ne, 6),
+    ("<", BinaryOperator::Lt, 7),
+    ("<=", BinaryOperator::Le, 7),
+    (">", BinaryOperator::Gt, 7),
+    (">=", BinaryOperator::Ge, 7),
+    ("<<", BinaryOperator::Shl, 8),
+    (">>", BinaryOperator::Shr, 8),
+    ("+", BinaryOperator::Add, 9),
+    ("-", BinaryOperator::Sub, 9),
+    ("*", BinaryOperator::Mul, 10),
+    ("/", BinaryOperator::Div, 10),
+    ("%", BinaryOperator::Mod, 10),
+];
+
+/// Split the text of an expression into tokens, each with the offset it starts at
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, String> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let c = bytes[pos];
+        let start = pos;
+        if c.is_ascii_whitespace() {
+            pos += 1;
+            continue;
+        }
+        let is_word = |byte: u8| byte.is_ascii_alphanumeric() || byte == b'_';
+        let token = if c.is_ascii_digit()
+            || (c == b'.' && matches!(bytes.get(pos + 1), Some(next) if next.is_ascii_digit()))
+        {
+            // a number runs until the first character that is not part of it, including the
+            // sign of an exponent
+            pos += 1;
+            while pos < bytes.len() {
+                let previous = bytes[pos - 1].to_ascii_lowercase();
+                let exponent_sign = (bytes[pos] == b'+' || bytes[pos] == b'-')
+                    && previous == b'e'
+                    && !text[start..].to_lowercase().starts_with("0x");
+                if is_word(bytes[pos]) || bytes[pos] == b'.' || exponent_sign {
+                    pos += 1;
+                } else {
+                    break;
+                }
+            }
+            parse_number(&text[start..pos])?
+        } else if is_word(c) {
+            while pos < bytes.len() && is_word(bytes[pos]) {
+                pos += 1;
+            }
+            Token::Identifier(text[start..pos].to_string())
+        } else if c == b'$' {
+            pos += 1;
+            while pos < bytes.len() && is_word(bytes[pos]) {
+                pos += 1;
+            }
+            Token::Register(text[start + 1..pos].to_string())
+        } else if c == b'\'' {
+            let (value, len) = parse_char(&bytes[pos + 1..])?;
+            pos += 1 + len;
+            if bytes.get(pos) != Some(&b'\'') {
+                return Err("Unmatched single quote.".to_string());
+            }
+            pos += 1;
+            Token::Char(value)
+        } else {
+            match SYMBOLS
+                .iter()
+                .find(|symbol| text[pos..].starts_with(*symbol))
+            {
+                Some(symbol) => {
+                    pos += symbol.len();
+                    Token::Symbol(symbol)
+                }
+                None => {
+                    let c = text[pos..].chars().next().unwrap();
+                    return Err(format!("Invalid character '{}' in expression.", c));
+                }
+            }
+        };
+        tokens.push((token, start));
+    }
+    tokens.push((Token::End, text.len()));
+    Ok(tokens)
+}
+
+/// Parse an integer or floating point literal, with its suffixes
+fn parse_number(text: &str) -> Result<Token, String> {
+    let invalid = || format!("Invalid number \"{}\".", text);
+    let lower = text.to_lowercase();
+    let hex = lower.starts_with("0x");
+    if !hex && (lower.contains('.') || lower.contains('e')) {
+        let digits = lower.trim_end_matches(&['f', 'l'][..]);
+        return digits.parse().map(Token::Float).map_err(|_| invalid());
+    }
+    let digits = lower.trim_end_matches(&['u', 'l'][..]);
+    let suffix = &lower[digits.len()..];
+    let value = if hex {
+        u64::from_str_radix(&digits[2..], 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse()
+    }
+    .map_err(|_| invalid())?;
+    Ok(Token::Integer(
+        value,
+        suffix.contains('u'),
+        suffix.contains('l'),
+    ))
+}
+
+/// Parse the character of a character literal, after the opening quote. Returns the character
+/// and the number of bytes it is spelled with.
+fn parse_char(bytes: &[u8]) -> Result<(u8, usize), String> {
+    let unmatched = || "Unmatched single quote.".to_string();
+    match bytes.first() {
+        None | Some(b'\'') => Err(unmatched()),
+        Some(b'\\') => {
+            let escape = *bytes.get(1).ok_or_else(unmatched)?;
+            let simple = match escape {
+                b'n' => Some(b'\n'),
+                b't' => Some(b'\t'),
+                b'r' => Some(b'\r'),
+                b'a' => Some(7),
+                b'b' => Some(8),
+                b'f' => Some(12),
+                b'v' => Some(11),
+                b'e' => Some(27),
+                b'0'..=b'7' | b'x' => None,
+                other => Some(other),
+            };
+            if let Some(value) = simple {
+                return Ok((value, 2));
+            }
+            // \ followed by up to three octal digits, or x followed by hexadecimal digits
+            let (radix, start, max) = if escape == b'x' {
+                (16, 2, usize::MAX)
+            } else {
+                (8, 1, 3)
+            };
+            let digits = bytes
+                .get(start..)
+                .ok_or_else(unmatched)?
+                .iter()
+                .take(max)
+                .take_while(|byte| (**byte as char).is_digit(radix))
+                .count();
+            let text = bytes
+                .get(start..start + digits)
+                .and_then(|digits| std::str::from_utf8(digits).ok())
+                .ok_or_else(unmatched)?;
+            let value = u32::from_str_radix(text, radix).map_err(|_| unmatched())?;
+            Ok((value as u8, start + digits))
+        }
+        Some(byte) => Ok((*byte, 1)),
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    /// Whether an identifier is the name of a type, which makes (name) a cast
+    is_type: &'a dyn Fn(&str) -> bool,
+}
+
+impl Expression {
+    /// Parse an expression. is_type tells typedef names from variable names, as the C grammar
+    /// depends on it: (t) * x is a cast if t is a type, and a product otherwise.
+    pub fn parse(text: &str, is_type: &dyn Fn(&str) -> bool) -> Result<Expression, String> {
+        let mut parser = Parser {
+            text,
+            tokens: tokenize(text)?,
+            pos: 0,
+            is_type,
+        };
+        let root = parser.expression()?;
+        if parser.peek() != &Token::End {
+            return Err(parser.error());
+        }
+        Ok(Expression {
+            text: text.trim().to_string(),
+            root,
+        })
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn peek_at(&self, offset: usize) -> &Token {
+        let index = (self.pos + offset).min(self.tokens.len() - 1);
+        &self.tokens[index].0
+    }
+
+    /// Consume the given symbol if it comes next
+    fn eat(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Token::Symbol(next) if *next == symbol) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, String> {
+        match self.peek().clone() {
+            Token::Identifier(name) => {
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(self.error()),
+        }
+    }
+
+    /// The error for the token at the current position, worded like gdb's
+    fn error(&self) -> String {
+        format!(
+            "A syntax error in expression, near `{}'.",
+            &self.text[self.tokens[self.pos].1..]
+        )
+    }
+
+    /// Whether the token at the given offset from the current position starts a type name
+    fn at_type(&self, offset: usize) -> bool {
+        match self.peek_at(offset) {
+            Token::Identifier(name) => {
+                BASE_KEYWORDS.contains(&name.as_str())
+                    || ["struct", "union", "enum", "const", "volatile"].contains(&name.as_str())
+                    || (self.is_type)(name)
+            }
+            _ => false,
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expr, String> {
+        let target = self.conditional()?;
+        let operator = match self.peek() {
+            Token::Symbol("=") => None,
+            Token::Symbol(symbol) if symbol.len() > 1 && symbol.ends_with('=') => {
+                let symbol = &symbol[..symbol.len() - 1];
+                match BINARY_OPERATORS.iter().find(|(name, ..)| *name == symbol) {
+                    Some((_, operator, _)) => Some(*operator),
+                    None => return Ok(target),
+                }
+            }
+            _ => return Ok(target),
+        };
+        self.pos += 1;
+        // assignments associate to the right
+        let value = self.expression()?;
+        let value = match operator {
+            Some(operator) => Expr::Binary(operator, Box::new(target.clone()), Box::new(value)),
+            None => value,
+        };
+        Ok(Expr::Assign(Box::new(target), Box::new(value)))
+    }
+
+    fn conditional(&mut self) -> Result<Expr, String> {
+        let condition = self.binary(1)?;
+        if !self.eat("?") {
+            return Ok(condition);
+        }
+        let value = self.expression()?;
+        self.expect(":")?;
+        let otherwise = self.conditional()?;
+        Ok(Expr::Conditional(
+            Box::new(condition),
+            Box::new(value),
+            Box::new(otherwise),
+        ))
+    }
+
+    /// Parse a sequence of binary operations whose operators bind at least as tight as
+    /// min_precedence, by precedence climbing
+    fn binary(&mut self, min_precedence: usize) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        loop {
+            let (operator, precedence) = match self.peek() {
+                Token::Symbol(symbol) => {
+                    match BINARY_OPERATORS.iter().find(|(name, ..)| name == symbol) {
+                        Some((_, operator, precedence)) if *precedence >= min_precedence => {
+                            (*operator, *precedence)
+                        }
+                        _ => return Ok(left),
+                    }
+                }
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.binary(precedence + 1)?;
+            left = Expr::Binary(operator, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let operator = match self.peek() {
+            Token::Symbol("-") => Some(UnaryOperator::Negate),
+            Token::Symbol("+") => Some(UnaryOperator::Plus),
+            Token::Symbol("!") => Some(UnaryOperator::Not),
+            Token::Symbol("~") => Some(UnaryOperator::BitNot),
+            Token::Symbol("*") => Some(UnaryOperator::Dereference),
+            Token::Symbol("&") => Some(UnaryOperator::AddressOf),
+            _ => None,
+        };
+        if let Some(operator) = operator {
+            self.pos += 1;
+            return Ok(Expr::Unary(operator, Box::new(self.unary()?)));
+        }
+        if matches!(self.peek(), Token::Identifier(word) if word == "sizeof") {
+            self.pos += 1;
+            if matches!(self.peek(), Token::Symbol("(")) && self.at_type(1) {
+                self.pos += 1;
+                let type_name = self.type_name()?;
+                self.expect(")")?;
+                return Ok(Expr::SizeofType(type_name));
+            }
+            return Ok(Expr::SizeofValue(Box::new(self.unary()?)));
+        }
+        if matches!(self.peek(), Token::Symbol("(")) && self.at_type(1) {
+            self.pos += 1;
+            let type_name = self.type_name()?;
+            self.expect(")")?;
+            return Ok(Expr::Cast(type_name, Box::new(self.unary()?)));
+        }
+        self.postfix()
+    }
+
+    fn type_name(&mut self) -> Result<TypeName, String> {
+        let mut words: Vec<String> = Vec::new();
+        while let Token::Identifier(word) = self.peek() {
+            let word = word.clone();
+            match word.as_str() {
+                "const" | "volatile" => self.pos += 1,
+                "struct" | "union" | "enum" if words.is_empty() => {
+                    self.pos += 1;
+                    words.push(word);
+                    words.push(self.identifier()?);
+                }
+                keyword if BASE_KEYWORDS.contains(&keyword) => {
+                    self.pos += 1;
+                    words.push(word);
+                }
+                name if words.is_empty() && (self.is_type)(name) => {
+                    self.pos += 1;
+                    words.push(word);
+                }
+                _ => break,
+            }
+        }
+        if words.is_empty() {
+            return Err(self.error());
+        }
+        let mut pointers = 0;
+        while self.eat("*") {
+            pointers += 1;
+            while matches!(self.peek(), Token::Identifier(word) if word == "const" || word == "volatile")
+            {
+                self.pos += 1;
+            }
+        }
+        Ok(TypeName {
+            base: words.join(" "),
+            pointers,
+        })
+    }
+
+    fn postfix(&mut self) -> Result<Expr, String> {
+        let mut value = self.primary()?;
+        loop {
+            if self.eat("[") {
+                let index = self.expression()?;
+                self.expect("]")?;
+                value = Expr::Index(Box::new(value), Box::new(index));
+            } else if self.eat(".") {
+                value = Expr::Member(Box::new(value), self.identifier()?);
+            } else if self.eat("->") {
+                let pointee = Expr::Unary(UnaryOperator::Dereference, Box::new(value));
+                value = Expr::Member(Box::new(pointee), self.identifier()?);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let token = self.peek().clone();
+        if token == Token::End {
+            return Err(self.error());
+        }
+        self.pos += 1;
+        Ok(match token {
+            Token::Integer(value, unsigned, long) => Expr::Integer {
+                value: value as i64,
+                unsigned,
+                long,
+            },
+            Token::Float(value) => Expr::Float(value),
+            Token::Char(value) => Expr::Char(value),
+            Token::Identifier(name) => Expr::Variable(name),
+            Token::Register(name) => Expr::Register(name),
+            Token::Symbol("(") => {
+                let value = self.expression()?;
+                self.expect(")")?;
+                value
+            }
+            _ => {
+                self.pos -= 1;
+                return Err(self.error());
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse with t and node_t as the only typedef names
+    fn parse(text: &str) -> Result<Expr, String> {
+        Expression::parse(text, &|name| name == "t" || name == "node_t")
+            .map(|expression| expression.root)
+    }
+
+    fn var(name: &str) -> Box<Expr> {
+        Box::new(Expr::Variable(name.to_string()))
+    }
+
+    fn int(value: i64) -> Box<Expr> {
+        Box::new(Expr::Integer {
+            value,
+            unsigned: false,
+            long: false,
+        })
+    }
+
+    fn binary(operator: BinaryOperator, left: Box<Expr>, right: Box<Expr>) -> Box<Expr> {
+        Box::new(Expr::Binary(operator, left, right))
+    }
+
+    fn unary(operator: UnaryOperator, operand: Box<Expr>) -> Box<Expr> {
+        Box::new(Expr::Unary(operator, operand))
+    }
+
+    fn type_name(base: &str, pointers: usize) -> TypeName {
+        TypeName {
+            base: base.to_string(),
+            pointers,
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(
+            parse("1 + 2 * 3"),
+            Ok(*binary(
+                BinaryOperator::Add,
+                int(1),
+                binary(BinaryOperator::Mul, int(2), int(3))
+            ))
+        );
+        assert_eq!(
+            parse("(1 + 2) * 3"),
+            Ok(*binary(
+                BinaryOperator::Mul,
+                binary(BinaryOperator::Add, int(1), int(2)),
+                int(3)
+            ))
+        );
+        assert_eq!(
+            parse("a < b == c & d"),
+            Ok(*binary(
+                BinaryOperator::BitAnd,
+                binary(
+                    BinaryOperator::Eq,
+                    binary(BinaryOperator::Lt, var("a"), var("b")),
+                    var("c")
+                ),
+                var("d")
+            ))
+        );
+        assert_eq!(
+            parse("!a && b || c"),
+            Ok(*binary(
+                BinaryOperator::Or,
+                binary(
+                    BinaryOperator::And,
+                    unary(UnaryOperator::Not, var("a")),
+                    var("b")
+                ),
+                var("c")
+            ))
+        );
+        assert_eq!(
+            parse("1 << 2 + 3"),
+            Ok(*binary(
+                BinaryOperator::Shl,
+                int(1),
+                binary(BinaryOperator::Add, int(2), int(3))
+            ))
+        );
+    }
+
+    #[test]
+    fn associativity() {
+        assert_eq!(
+            parse("a - b - c"),
+            Ok(*binary(
+                BinaryOperator::Sub,
+                binary(BinaryOperator::Sub, var("a"), var("b")),
+                var("c")
+            ))
+        );
+        assert_eq!(
+            parse("a = b = c"),
+            Ok(Expr::Assign(
+                var("a"),
+                Box::new(Expr::Assign(var("b"), var("c")))
+            ))
+        );
+        assert_eq!(
+            parse("a ? b : c ? d : e"),
+            Ok(Expr::Conditional(
+                var("a"),
+                var("b"),
+                Box::new(Expr::Conditional(var("c"), var("d"), var("e")))
+            ))
+        );
+        assert_eq!(
+            parse("x += 1"),
+            Ok(Expr::Assign(
+                var("x"),
+                binary(BinaryOperator::Add, var("x"), int(1))
+            ))
+        );
+        assert_eq!(
+            parse("x <<= y"),
+            Ok(Expr::Assign(
+                var("x"),
+                binary(BinaryOperator::Shl, var("x"), var("y"))
+            ))
+        );
+    }
+
+    #[test]
+    fn casts() {
+        // a cast if the name in parentheses is a type, a product otherwise
+        assert_eq!(
+            parse("(t) * x"),
+            Ok(Expr::Cast(
+                type_name("t", 0),
+                unary(UnaryOperator::Dereference, var("x"))
+            ))
+        );
+        assert_eq!(
+            parse("(v) * x"),
+            Ok(*binary(BinaryOperator::Mul, var("v"), var("x")))
+        );
+        assert_eq!(
+            parse("(unsigned long *)p"),
+            Ok(Expr::Cast(type_name("unsigned long", 1), var("p")))
+        );
+        assert_eq!(
+            parse("(const struct point * const *)p"),
+            Ok(Expr::Cast(type_name("struct point", 2), var("p")))
+        );
+        assert_eq!(
+            parse("(node_t *)(long)p"),
+            Ok(Expr::Cast(
+                type_name("node_t", 1),
+                Box::new(Expr::Cast(type_name("long", 0), var("p")))
+            ))
+        );
+        assert_eq!(
+            parse("sizeof(int)"),
+            Ok(Expr::SizeofType(type_name("int", 0)))
+        );
+        assert_eq!(parse("sizeof x"), Ok(Expr::SizeofValue(var("x"))));
+        assert_eq!(parse("sizeof (x)"), Ok(Expr::SizeofValue(var("x"))));
+    }
+
+    #[test]
+    fn postfix() {
+        assert_eq!(
+            parse("p->x"),
+            Ok(Expr::Member(
+                unary(UnaryOperator::Dereference, var("p")),
+                "x".to_string()
+            ))
+        );
+        assert_eq!(parse("a[i]"), Ok(Expr::Index(var("a"), var("i"))));
+        assert_eq!(
+            parse("-a[i + 1].b"),
+            Ok(*unary(
+                UnaryOperator::Negate,
+                Box::new(Expr::Member(
+                    Box::new(Expr::Index(
+                        var("a"),
+                        binary(BinaryOperator::Add, var("i"), int(1))
+                    )),
+                    "b".to_string()
+                ))
+            ))
+        );
+        assert_eq!(
+            parse("&*p"),
+            Ok(*unary(
+                UnaryOperator::AddressOf,
+                unary(UnaryOperator::Dereference, var("p"))
+            ))
+        );
+        assert_eq!(
+            parse("$rip + 1"),
+            Ok(*binary(
+                BinaryOperator::Add,
+                Box::new(Expr::Register("rip".to_string())),
+                int(1)
+            ))
+        );
+    }
+
+    #[test]
+    fn characters() {
+        for (text, value) in &[
+            ("'a'", b'a'),
+            ("' '", b' '),
+            ("'\\n'", b'\n'),
+            ("'\\t'", b'\t'),
+            ("'\\e'", 27),
+            ("'\\\\'", b'\\'),
+            ("'\\''", b'\''),
+            ("'\\0'", 0),
+            ("'\\101'", b'A'),
+            ("'\\x41'", b'A'),
+            ("'\\x7f'", 0x7f),
+        ] {
+            assert_eq!(parse(text), Ok(Expr::Char(*value)), "{}", text);
+        }
+        // an octal escape has at most three digits, so '\1012' holds two characters
+        for text in &[
+            "'", "''", "'ab'", "'\\", "'\\x", "'\\x'", "'\\xg'", "'a", "'\\1012'",
+        ] {
+            assert_eq!(
+                parse(text),
+                Err("Unmatched single quote.".to_string()),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn numbers() {
+        let integer = |value, unsigned, long| {
+            Ok(Expr::Integer {
+                value,
+                unsigned,
+                long,
+            })
+        };
+        assert_eq!(parse("42"), integer(42, false, false));
+        assert_eq!(parse("42u"), integer(42, true, false));
+        assert_eq!(parse("42L"), integer(42, false, true));
+        assert_eq!(parse("42ul"), integer(42, true, true));
+        assert_eq!(parse("42LU"), integer(42, true, true));
+        assert_eq!(parse("0x1F"), integer(31, false, false));
+        assert_eq!(parse("010"), integer(8, false, false));
+        assert_eq!(parse("0"), integer(0, false, false));
+        assert_eq!(parse("0xffffffffffffffff"), integer(-1, false, false));
+        assert_eq!(parse("1.5"), Ok(Expr::Float(1.5)));
+        assert_eq!(parse(".5"), Ok(Expr::Float(0.5)));
+        assert_eq!(parse("2.5f"), Ok(Expr::Float(2.5)));
+        assert_eq!(parse("1e3"), Ok(Expr::Float(1000.0)));
+        assert_eq!(parse("1e-3"), Ok(Expr::Float(0.001)));
+        assert_eq!(
+            parse("1e3+1"),
+            Ok(*binary(
+                BinaryOperator::Add,
+                Box::new(Expr::Float(1000.0)),
+                int(1)
+            ))
+        );
+        assert_eq!(
+            parse("0x1e+1"),
+            Ok(*binary(BinaryOperator::Add, int(30), int(1)))
+        );
+        for text in &["09", "0x", "12abc", "1.2.3", "99999999999999999999"] {
+            assert_eq!(
+                parse(text),
+                Err(format!("Invalid number \"{}\".", text)),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let near = |rest: &str| Err(format!("A syntax error in expression, near `{}'.", rest));
+        assert_eq!(parse(""), near(""));
+        assert_eq!(parse("1 +"), near(""));
+        assert_eq!(parse("1 2"), near("2"));
+        assert_eq!(parse("(1 + 2"), near(""));
+        assert_eq!(parse("a[1"), near(""));
+        assert_eq!(parse("a + ) * b"), near(") * b"));
+        assert_eq!(parse("p->  5"), near("5"));
+        assert_eq!(parse("a ? b"), near(""));
+        assert_eq!(parse("(struct) x"), near(") x"));
+        assert_eq!(parse("x = = 1"), near("= 1"));
+        assert_eq!(
+            parse("a @ b"),
+            Err("Invalid character '@' in expression.".to_string())
+        );
+        assert_eq!(
+            parse("é"),
+            Err("Invalid character 'é' in expression.".to_string())
+        );
+    }
+
+    #[test]
+    fn truncated_input() {
+        // whatever is typed at the prompt is an error at worst
+        for text in &[
+            "'\\x41' + (unsigned long *)p->next[3] ? $rip : 1e-3",
+            "'\\101' <<= sizeof(struct point) % 0x1fUL",
+            "é + 'é'",
+        ] {
+            for end in (0..=text.len()).filter(|end| text.is_char_boundary(*end)) {
+                let _ = parse(&text[..end]);
+            }
+        }
+    }
+
+    #[test]
+    fn text() {
+        let expression = Expression::parse("  a  +  ' ' ", &|_| false).unwrap();
+        assert_eq!(expression.to_string(), "a  +  ' '");
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(parse_constant("42"), Some(42));
+        assert_eq!(parse_constant("-42"), Some(-42));
+        assert_eq!(parse_constant("0x10"), Some(16));
+        assert_eq!(parse_constant("0X10"), Some(16));
+        assert_eq!(parse_constant("x"), None);
+        assert_eq!(parse_constant(""), None);
+    }
+
+    #[test]
+    fn base_types() {
+        let check = |name: &str, size, encoding| {
+            let base = base_type(name).unwrap();
+            assert_eq!(base.size, size, "{}", name);
+            assert!(
+                matches!(&base.kind, TypeKind::Base(e) if *e == encoding),
+                "{}",
+                name
+            );
+        };
+        check("int", 4, Encoding::Signed);
+        check("unsigned", 4, Encoding::Unsigned);
+        check("unsigned long", 8, Encoding::Unsigned);
+        check("long long", 8, Encoding::Signed);
+        check("short", 2, Encoding::Signed);
+        check("unsigned char", 1, Encoding::UnsignedChar);
+        check("char", 1, Encoding::SignedChar);
+        check("_Bool", 1, Encoding::Boolean);
+        check("float", 4, Encoding::Float);
+        check("long double", 16, Encoding::Float);
+        assert!(base_type("void").is_none());
+        assert!(base_type("node_t").is_none());
+    }
+}
@@ -5,12 +5,13 @@
 //!
 //! This code is a huge mess. Please don't read it unless you're trying to do an extension :)
 
-use gimli;
 use gimli::{UnitOffset, UnitSectionOffset};
 use object::Object;
 use std::borrow;
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::dwarf_data::{
+    Encoding, Expression, File, Function, Line, Location, Member, Type, TypeKind, Variable,
+};
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Write;
@@ -34,7 +35,7 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     let borrow_section: &dyn for<'a> Fn(
         &'a borrow::Cow<[u8]>,
     ) -> gimli::EndianSlice<'a, gimli::RunTimeEndian> =
-        &|section| gimli::EndianSlice::new(&*section, endian);
+        &|section| gimli::EndianSlice::new(section, endian);
 
     // Create `EndianSlice`s for all of the sections.
     let dwarf = dwarf_cow.borrow(&borrow_section);
@@ -49,12 +50,20 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     while let Some(header) = iter.next()? {
         let unit = dwarf.unit(header)?;
 
+        // Types may be referenced before they are declared, so collect them first
+        let mut unit_types = load_types(&unit, &dwarf, &mut offset_to_type)?;
+
         // Iterate over the Debugging Information Entries (DIEs) in the unit.
         let mut depth = 0;
+        // what the variables among the children of the entries enclosing the current entry
+        // belong to, by depth
+        let mut scopes: Vec<Scope> = Vec::new();
         let mut entries = unit.entries();
         while let Some((delta_depth, entry)) = entries.next_dfs()? {
             depth += delta_depth;
-            // Update the offset_to_type mapping for types
+            scopes.truncate(depth as usize);
+            let scope = scopes.last().copied().unwrap_or(Scope::Other);
+            let mut children = Scope::Other;
             // Update the variable list for formal params/variables
             match entry.tag() {
                 gimli::DW_TAG_compile_unit => {
@@ -69,40 +78,16 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                     };
                     compilation_units.push(File {
                         name,
+                        types: std::mem::take(&mut unit_types),
                         global_variables: Vec::new(),
                         functions: Vec::new(),
                         lines: Vec::new(),
                     });
-                }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
-                        }
-                    } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
+                    children = Scope::Unit;
                 }
                 gimli::DW_TAG_subprogram => {
                     let mut func: Function = Default::default();
+                    let mut declaration = false;
                     let mut attrs = entry.attrs();
                     while let Some(attr) = attrs.next()? {
                         let val = get_attr_value(&attr, &unit, &dwarf);
@@ -129,16 +114,30 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                                     func.line_number = line_number.try_into().unwrap();
                                 }
                             }
+                            gimli::DW_AT_frame_base => {
+                                func.frame_base = get_location(&attr, &unit, &dwarf);
+                            }
+                            gimli::DW_AT_declaration => {
+                                declaration = true;
+                            }
                             _ => {}
                         }
                     }
                     compilation_units.last_mut().unwrap().functions.push(func);
+                    // the parameters of a prototype are not variables of the program
+                    if !declaration {
+                        children = Scope::Function;
+                    }
+                }
+                gimli::DW_TAG_lexical_block | gimli::DW_TAG_inlined_subroutine => {
+                    children = scope;
                 }
                 gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
                     let mut name = String::new();
                     let mut entity_type: Option<Type> = None;
                     let mut location: Option<Location> = None;
                     let mut line_number = 0;
+                    let mut declaration = false;
                     let mut attrs = entry.attrs();
                     while let Some(attr) = attrs.next()? {
                         let val = get_attr_value(&attr, &unit, &dwarf);
@@ -151,16 +150,24 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                             }
                             gimli::DW_AT_type => {
                                 if let Ok(DebugValue::Size(offset)) = val {
-                                    if let Some(dtype) = offset_to_type.get(&offset).clone() {
+                                    if let Some(dtype) = offset_to_type.get(&offset) {
                                         entity_type = Some(dtype.clone());
                                     }
                                 }
                             }
                             gimli::DW_AT_location => {
-                                if let Some(loc) = get_location(&attr, &unit) {
+                                if let Some(loc) = get_location(&attr, &unit, &dwarf) {
                                     location = Some(loc);
                                 }
                             }
+                            gimli::DW_AT_const_value => {
+                                if let Some(bytes) = constant_bytes(attr.value()) {
+                                    location = Some(Location::Value(bytes));
+                                }
+                            }
+                            gimli::DW_AT_declaration => {
+                                declaration = true;
+                            }
                             gimli::DW_AT_decl_line => {
                                 if let Ok(DebugValue::Uint(num)) = val {
                                     line_number = num;
@@ -169,28 +176,33 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                             _ => {}
                         }
                     }
-                    if entity_type.is_some() && location.is_some() {
+                    // a variable the compiler optimized away entirely has no location
+                    if location.is_none() && !declaration {
+                        location = Some(Location::OptimizedOut);
+                    }
+                    if let (Some(entity_type), Some(location)) = (entity_type, location) {
                         let var = Variable {
                             name,
-                            entity_type: entity_type.unwrap(),
-                            location: location.unwrap(),
+                            entity_type,
+                            location,
                             line_number: line_number.try_into().unwrap(),
+                            is_parameter: entry.tag() == gimli::DW_TAG_formal_parameter,
                         };
-                        if depth == 1 {
-                            compilation_units
+                        match scope {
+                            Scope::Unit => compilation_units
                                 .last_mut()
                                 .unwrap()
                                 .global_variables
-                                .push(var);
-                        } else if depth > 1 {
-                            compilation_units
+                                .push(var),
+                            Scope::Function => compilation_units
                                 .last_mut()
                                 .unwrap()
                                 .functions
                                 .last_mut()
                                 .unwrap()
                                 .variables
-                                .push(var);
+                                .push(var),
+                            Scope::Other => {}
                         }
                     }
                 }
@@ -198,8 +210,15 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                 // match statement
                 _ => {}
             }
+            scopes.push(children);
         }
 
+        // Paths in the line program and the name of the unit are relative to this directory
+        let comp_dir = match &unit.comp_dir {
+            Some(dir) => path::PathBuf::from(gimli::Reader::to_string_lossy(dir)?.as_ref()),
+            None => path::PathBuf::new(),
+        };
+
         // Get line numbers
         if let Some(program) = unit.line_program.clone() {
             // Iterate over the line program rows.
@@ -207,7 +226,7 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
             while let Some((header, row)) = rows.next_row()? {
                 if !row.end_sequence() {
                     // Determine the path. Real applications should cache this for performance.
-                    let mut path = path::PathBuf::new();
+                    let mut path = comp_dir.clone();
                     if let Some(file) = row.file(header) {
                         if let Some(dir) = file.directory(header) {
                             path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy().as_ref());
@@ -223,7 +242,7 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                     // Get the File
                     let file = compilation_units
                         .iter_mut()
-                        .find(|f| f.name == path.as_os_str().to_str().unwrap());
+                        .find(|f| comp_dir.join(&f.name) == path);
 
                     // Determine line/column. DWARF line/column is never 0, so we use that
                     // but other applications may want to display this differently.
@@ -243,10 +262,346 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     Ok(compilation_units)
 }
 
+/// Get the offset of an entry relative to the start of .debug_info, which is how DW_AT_type
+/// references are resolved by get_attr_value
+fn get_section_offset<R: Reader>(offset: UnitOffset, unit: &gimli::Unit<R>) -> usize {
+    match offset.to_unit_section_offset(unit) {
+        UnitSectionOffset::DebugInfoOffset(goff) => goff.0,
+        UnitSectionOffset::DebugTypesOffset(goff) => goff.0,
+    }
+}
+
+/// A type entry of a unit, along with what its children say about it
+struct RawType {
+    tag: gimli::DwTag,
+    name: Option<String>,
+    byte_size: usize,
+    encoding: Option<gimli::DwAte>,
+    /// Offset of the type this one points to, qualifies, names or holds
+    target: Option<usize>,
+    members: Vec<RawMember>,
+    enumerators: Vec<(String, i64)>,
+    /// Lengths of the dimensions of an array, None for a flexible one
+    dimensions: Vec<Option<usize>>,
+    /// Whether the type is defined at the top level of the unit
+    top_level: bool,
+}
+
+struct RawMember {
+    name: String,
+    offset: usize,
+    bit_field: Option<(usize, usize)>,
+    member_type: Option<usize>,
+}
+
+/// What the variables among the children of an entry belong to
+#[derive(Clone, Copy)]
+enum Scope {
+    /// The compilation unit, whose variables are globals
+    Unit,
+    /// The function last added to the unit, or one of its blocks
+    Function,
+    /// Anything else, e.g. a prototype or a function type, whose parameters are not variables
+    Other,
+}
+
+/// Whether an entry describes a type
+fn is_type_tag(tag: gimli::DwTag) -> bool {
+    matches!(
+        tag,
+        gimli::DW_TAG_base_type
+            | gimli::DW_TAG_pointer_type
+            | gimli::DW_TAG_structure_type
+            | gimli::DW_TAG_union_type
+            | gimli::DW_TAG_array_type
+            | gimli::DW_TAG_enumeration_type
+            | gimli::DW_TAG_typedef
+            | gimli::DW_TAG_const_type
+            | gimli::DW_TAG_volatile_type
+            | gimli::DW_TAG_restrict_type
+            | gimli::DW_TAG_subroutine_type
+    )
+}
+
+/// The value of a constant attribute, sign extended only if it is encoded as signed
+fn constant_value<R: Reader>(value: gimli::AttributeValue<R>) -> Option<i64> {
+    match value {
+        gimli::AttributeValue::Sdata(data) => Some(data),
+        value => value.udata_value().map(|data| data as i64),
+    }
+}
+
+/// Fill the offset_to_type mapping with the types of a unit. Returns the named types defined at
+/// the top level of the unit.
+fn load_types<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    offset_to_type: &mut HashMap<usize, Type>,
+) -> Result<Vec<Type>, Error> {
+    let mut raw_types: HashMap<usize, RawType> = HashMap::new();
+    // offsets of the type entries enclosing the current entry, by depth
+    let mut parents: Vec<Option<usize>> = Vec::new();
+    let mut depth = 0;
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        let depth = depth as usize;
+        parents.truncate(depth);
+        let parent = parents.last().copied().flatten();
+        let offset = get_section_offset(entry.offset(), unit);
+        let mut name = None;
+        let mut byte_size = 0;
+        let mut encoding = None;
+        let mut target = None;
+        let mut member_offset = 0;
+        let mut bit_size = None;
+        let mut bit_offset = None;
+        let mut data_bit_offset = None;
+        let mut value = None;
+        let mut upper_bound = None;
+        let mut count = None;
+        let mut attrs = entry.attrs();
+        while let Some(attr) = attrs.next()? {
+            match (attr.name(), get_attr_value(&attr, unit, dwarf)) {
+                (gimli::DW_AT_name, Ok(DebugValue::Str(attr_name))) => name = Some(attr_name),
+                (gimli::DW_AT_byte_size, Ok(DebugValue::Uint(size))) => {
+                    byte_size = size.try_into().unwrap()
+                }
+                (gimli::DW_AT_type, Ok(DebugValue::Size(type_offset))) => {
+                    target = Some(type_offset)
+                }
+                (gimli::DW_AT_data_member_location, _) => {
+                    member_offset = match attr.value() {
+                        // DWARF 2 spells the offset as DW_OP_plus_uconst
+                        gimli::AttributeValue::Exprloc(expression) => {
+                            let mut data = expression.0;
+                            match gimli::Operation::parse(&mut data, unit.encoding()) {
+                                Ok(gimli::Operation::PlusConstant { value }) => value as usize,
+                                _ => 0,
+                            }
+                        }
+                        value => constant_value(value).unwrap_or(0) as usize,
+                    }
+                }
+                (gimli::DW_AT_bit_size, _) => bit_size = constant_value(attr.value()),
+                (gimli::DW_AT_bit_offset, _) => bit_offset = constant_value(attr.value()),
+                (gimli::DW_AT_data_bit_offset, _) => data_bit_offset = constant_value(attr.value()),
+                (gimli::DW_AT_const_value, _) => value = constant_value(attr.value()),
+                (gimli::DW_AT_upper_bound, _) => upper_bound = constant_value(attr.value()),
+                (gimli::DW_AT_count, _) => count = constant_value(attr.value()),
+                (gimli::DW_AT_encoding, _) => {
+                    if let gimli::AttributeValue::Encoding(ate) = attr.value() {
+                        encoding = Some(ate);
+                    }
+                }
+                _ => {}
+            }
+        }
+        match (entry.tag(), parent) {
+            (tag, _) if is_type_tag(tag) => {
+                raw_types.insert(
+                    offset,
+                    RawType {
+                        tag,
+                        name,
+                        byte_size,
+                        encoding,
+                        target,
+                        members: Vec::new(),
+                        enumerators: Vec::new(),
+                        dimensions: Vec::new(),
+                        top_level: depth == 1,
+                    },
+                );
+            }
+            (gimli::DW_TAG_member, Some(parent)) => {
+                let parent = raw_types.get_mut(&parent).unwrap();
+                let bits = match (data_bit_offset, bit_offset, bit_size) {
+                    (Some(data_bit_offset), _, Some(size)) => {
+                        Some((data_bit_offset as usize, size as usize))
+                    }
+                    // DWARF 3 counts the offset from the most significant bit of the storage
+                    // unit, which is byte_size bytes long
+                    (None, Some(bit_offset), Some(size)) => {
+                        let storage_end = (member_offset + byte_size) as i64 * 8;
+                        Some((
+                            (storage_end - bit_offset - size).max(0) as usize,
+                            size as usize,
+                        ))
+                    }
+                    _ => None,
+                };
+                let (offset, bit_field) = match bits {
+                    Some((bit_offset, size)) => (bit_offset / 8, Some((bit_offset % 8, size))),
+                    None => (member_offset, None),
+                };
+                parent.members.push(RawMember {
+                    name: name.unwrap_or_default(),
+                    offset,
+                    bit_field,
+                    member_type: target,
+                });
+            }
+            (gimli::DW_TAG_enumerator, Some(parent)) => {
+                raw_types
+                    .get_mut(&parent)
+                    .unwrap()
+                    .enumerators
+                    .push((name.unwrap_or_default(), value.unwrap_or(0)));
+            }
+            (gimli::DW_TAG_subrange_type, Some(parent)) => {
+                let length = match (count, upper_bound) {
+                    (Some(count), _) => Some(count.max(0) as usize),
+                    (None, Some(upper_bound)) => Some((upper_bound + 1).max(0) as usize),
+                    (None, None) => None,
+                };
+                raw_types.get_mut(&parent).unwrap().dimensions.push(length);
+            }
+            _ => {}
+        }
+        parents.push(Some(offset).filter(|offset| raw_types.contains_key(offset)));
+    }
+    let mut named_types = Vec::new();
+    for (&offset, raw_type) in &raw_types {
+        let built = build_type(offset, &raw_types, false);
+        if raw_type.top_level && raw_type.name.is_some() {
+            named_types.push(built.clone());
+        }
+        offset_to_type.insert(offset, built);
+    }
+    Ok(named_types)
+}
+
+/// Build the type at the given offset from the type entries of its unit. A named structure or
+/// union reached through a pointer is left without members, since it may point back to itself;
+/// its definition is found by name when the pointer is dereferenced.
+fn build_type(offset: usize, raw_types: &HashMap<usize, RawType>, through_pointer: bool) -> Type {
+    let raw_type = match raw_types.get(&offset) {
+        Some(raw_type) => raw_type,
+        None => return Type::new("<unknown>".to_string(), 0, TypeKind::Base(Encoding::Signed)),
+    };
+    let target = |through_pointer| {
+        raw_type
+            .target
+            .map(|target| build_type(target, raw_types, through_pointer))
+    };
+    let void = || Type::new("void".to_string(), 0, TypeKind::Base(Encoding::Signed));
+    let name = raw_type.name.clone();
+    match raw_type.tag {
+        gimli::DW_TAG_base_type => {
+            let name = name.unwrap_or_else(|| "<unknown>".to_string());
+            let encoding = match raw_type.encoding {
+                Some(gimli::DW_ATE_boolean) => Encoding::Boolean,
+                Some(gimli::DW_ATE_float) => Encoding::Float,
+                Some(gimli::DW_ATE_signed_char) => Encoding::SignedChar,
+                Some(gimli::DW_ATE_unsigned_char) => Encoding::UnsignedChar,
+                Some(gimli::DW_ATE_unsigned) | Some(gimli::DW_ATE_UTF) => Encoding::Unsigned,
+                _ if name.contains("unsigned") => Encoding::Unsigned,
+                _ => Encoding::Signed,
+            };
+            Type::new(name, raw_type.byte_size, TypeKind::Base(encoding))
+        }
+        gimli::DW_TAG_pointer_type => {
+            let mut pointer = Type::pointer_to(target(true));
+            if raw_type.byte_size != 0 {
+                pointer.size = raw_type.byte_size;
+            }
+            pointer
+        }
+        gimli::DW_TAG_typedef => {
+            let mut named = target(through_pointer).unwrap_or_else(void);
+            named.name = name.unwrap_or_default();
+            named
+        }
+        gimli::DW_TAG_const_type | gimli::DW_TAG_volatile_type | gimli::DW_TAG_restrict_type => {
+            let qualifier = match raw_type.tag {
+                gimli::DW_TAG_const_type => "const",
+                gimli::DW_TAG_volatile_type => "volatile",
+                _ => "restrict",
+            };
+            let mut qualified = target(through_pointer).unwrap_or_else(void);
+            // a qualified pointer is spelled with the qualifier after the star
+            qualified.name = match qualified.kind {
+                TypeKind::Pointer(_) => format!("{} {}", qualified.name, qualifier),
+                _ => format!("{} {}", qualifier, qualified.name),
+            };
+            qualified
+        }
+        gimli::DW_TAG_structure_type | gimli::DW_TAG_union_type => {
+            let keyword = if raw_type.tag == gimli::DW_TAG_union_type {
+                "union"
+            } else {
+                "struct"
+            };
+            let members = if through_pointer && name.is_some() {
+                Vec::new()
+            } else {
+                raw_type
+                    .members
+                    .iter()
+                    .map(|member| Member {
+                        name: member.name.clone(),
+                        offset: member.offset,
+                        bit_field: member.bit_field,
+                        member_type: match member.member_type {
+                            Some(member_type) => build_type(member_type, raw_types, false),
+                            None => void(),
+                        },
+                    })
+                    .collect()
+            };
+            let name = match name {
+                Some(name) => format!("{} {}", keyword, name),
+                None => format!("{} {{...}}", keyword),
+            };
+            Type::new(name, raw_type.byte_size, TypeKind::Struct(members))
+        }
+        gimli::DW_TAG_array_type => {
+            let element = target(through_pointer).unwrap_or_else(void);
+            let dimensions = if raw_type.dimensions.is_empty() {
+                vec![None]
+            } else {
+                raw_type.dimensions.clone()
+            };
+            let lengths: Vec<String> = dimensions
+                .iter()
+                .map(|length| length.map_or_else(String::new, |length| length.to_string()))
+                .collect();
+            // an array of arrays, built from the innermost dimension
+            let element_name = element.name.clone();
+            let mut array = element;
+            for (dimension, length) in dimensions.into_iter().enumerate().rev() {
+                let name = format!("{} [{}]", element_name, lengths[dimension..].join("]["));
+                let size = array.size * length.unwrap_or(0);
+                array = Type::new(name, size, TypeKind::Array(Box::new(array), length));
+            }
+            array
+        }
+        gimli::DW_TAG_enumeration_type => {
+            let name = match name {
+                Some(name) => format!("enum {}", name),
+                None => "enum {...}".to_string(),
+            };
+            let size = if raw_type.byte_size == 0 {
+                4
+            } else {
+                raw_type.byte_size
+            };
+            Type::new(name, size, TypeKind::Enum(raw_type.enumerators.clone()))
+        }
+        // a function type, the pointee of a function pointer
+        _ => {
+            let return_type = target(true).map_or_else(|| "void".to_string(), |t| t.name);
+            Type::new(format!("{} ()", return_type), 1, TypeKind::Function)
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum DebugValue {
     Str(String),
     Uint(u64),
+    #[allow(dead_code)]
     Int(i64),
     Size(usize),
     NoVal,
@@ -254,33 +609,33 @@ pub enum DebugValue {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
-    GimliError(gimli::Error),
-    Addr2lineError(addr2line::gimli::Error),
-    ObjectError(String),
-    IoError,
+    Gimli(gimli::Error),
+    Addr2line(addr2line::gimli::Error),
+    Object(String),
+    Io,
 }
 
 impl From<gimli::Error> for Error {
     fn from(err: gimli::Error) -> Self {
-        Error::GimliError(err)
+        Error::Gimli(err)
     }
 }
 
 impl From<addr2line::gimli::Error> for Error {
     fn from(err: addr2line::gimli::Error) -> Self {
-        Error::Addr2lineError(err)
+        Error::Addr2line(err)
     }
 }
 
 impl From<io::Error> for Error {
     fn from(_: io::Error) -> Self {
-        Error::IoError
+        Error::Io
     }
 }
 
 impl From<std::fmt::Error> for Error {
     fn from(_: std::fmt::Error) -> Self {
-        Error::IoError
+        Error::Io
     }
 }
 
@@ -291,25 +646,64 @@ impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
 
 trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
 
-fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
-    if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
-        let encoding = unit.encoding();
-        let mut pc = data.0.clone();
-        if pc.len() > 0 {
-            if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
-                match op {
-                    gimli::Operation::FrameOffset { offset } => {
-                        return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
-                    }
-                    gimli::Operation::Address { address } => {
-                        return Some(Location::Address(address.try_into().unwrap()));
-                    }
-                    _ => {}
+fn get_location<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Location> {
+    match attr.value() {
+        gimli::AttributeValue::Exprloc(ref data) => {
+            let expression = get_expression(data, unit)?;
+            if expression.bytes.is_empty() {
+                return Some(Location::OptimizedOut);
+            }
+            // the common cases of a lone DW_OP_fbreg or DW_OP_addr need no evaluation
+            let mut pc = data.0.clone();
+            let location = match gimli::Operation::parse(&mut pc, unit.encoding()) {
+                Ok(gimli::Operation::FrameOffset { offset }) if pc.is_empty() => {
+                    Location::FramePointerOffset(offset.try_into().unwrap())
+                }
+                Ok(gimli::Operation::Address { address }) if pc.is_empty() => {
+                    Location::Address(address.try_into().unwrap())
                 }
+                _ => Location::Expression(expression),
+            };
+            Some(location)
+        }
+        gimli::AttributeValue::LocationListsRef(offset) => {
+            let mut entries = Vec::new();
+            let mut locations = dwarf.locations(unit, offset).ok()?;
+            while let Ok(Some(entry)) = locations.next() {
+                entries.push((
+                    entry.range.begin as usize,
+                    entry.range.end as usize,
+                    get_expression(&entry.data, unit)?,
+                ));
             }
+            Some(Location::List(entries))
         }
+        _ => None,
+    }
+}
+
+/// Copy a DWARF expression out of the debugging information, for evaluating it later
+fn get_expression<R: Reader>(
+    data: &gimli::Expression<R>,
+    unit: &gimli::Unit<R>,
+) -> Option<Expression> {
+    Some(Expression {
+        bytes: data.0.to_slice().ok()?.to_vec(),
+        encoding: unit.encoding(),
+        bias: 0,
+    })
+}
+
+/// The little-endian bytes of a DW_AT_const_value
+fn constant_bytes<R: Reader>(value: gimli::AttributeValue<R>) -> Option<Vec<u8>> {
+    match value {
+        gimli::AttributeValue::Block(data) => Some(data.to_slice().ok()?.to_vec()),
+        value => constant_value(value).map(|data| data.to_le_bytes().to_vec()),
     }
-    None
 }
 
 // based on dwarf_dump.rs
@@ -344,6 +738,16 @@ fn get_attr_value<R: Reader>(
                 Ok(DebugValue::Str(format!("<.debug_str+0x{:08x}>", offset.0)))
             }
         }
+        gimli::AttributeValue::DebugLineStrRef(offset) => {
+            if let Ok(s) = dwarf.debug_line_str.get_str(offset) {
+                Ok(DebugValue::Str(format!("{}", s.to_string_lossy()?)))
+            } else {
+                Ok(DebugValue::Str(format!(
+                    "<.debug_line_str+0x{:08x}>",
+                    offset.0
+                )))
+            }
+        }
         gimli::AttributeValue::Sdata(data) => Ok(DebugValue::Int(data)),
         gimli::AttributeValue::Addr(data) => Ok(DebugValue::Uint(data)),
         gimli::AttributeValue::Udata(data) => Ok(DebugValue::Uint(data)),
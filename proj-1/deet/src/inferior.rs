@@ -1,13 +1,19 @@
 use nix::sys::ptrace;
 use nix::sys::signal;
+use nix::sys::signal::Signal;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
+use std::cell::{RefCell, RefMut};
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
 use std::os::unix::prelude::CommandExt;
 use std::process::{Child, Command};
 
 use crate::debugger::BreakPoint;
 use crate::dwarf_data::DwarfData;
+use crate::target::Target;
+use crate::unwind::{Frame, Unwinder};
 
 pub enum Status {
     /// Indicates inferior stopped. Contains the signal that stopped the process, as well as the
@@ -20,158 +26,792 @@ pub enum Status {
     /// Indicates the inferior exited due to a signal. Contains the signal that killed the
     /// process.
     Signaled(signal::Signal),
+
+    /// Indicates inferior stopped because a hardware watchpoint triggered. Contains the index of
+    /// the debug register holding the watchpoint, as well as the current instruction pointer.
+    Watchpoint(usize, usize),
+
+    /// Indicates inferior stopped in fork or vfork. Contains the pid of the new child, which is
+    /// traced as well, and whether it was created by vfork.
+    Forked(Pid, bool),
+
+    /// Indicates inferior stopped after replacing its program with exec. Contains the instruction
+    /// pointer in the new program.
+    Execed(usize),
+
+    /// Indicates inferior stopped because the child it created with vfork released its memory by
+    /// exiting or calling exec.
+    VforkDone,
+}
+
+/// Describe a frame by its function and source line, or by its address when there is no debugging
+/// information for it
+pub fn describe_frame(frame: &Frame, debug_data: &DwarfData) -> String {
+    let function = debug_data.get_function_from_addr(frame.lookup_pc());
+    let location = match debug_data.get_line_from_addr(frame.lookup_pc()) {
+        Some(line) => line.to_string(),
+        None => format!("{:#x}", frame.pc),
+    };
+    format!("{} ({})", function.as_deref().unwrap_or("??"), location)
 }
 
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
 /// pre_exec with Command to call this in the child process.
 fn child_traceme() -> Result<(), std::io::Error> {
-    ptrace::traceme().or(Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        "ptrace TRACEME failed",
-    )))
+    ptrace::traceme().or(Err(std::io::Error::other("ptrace TRACEME failed")))
+}
+
+/// Turn off address space layout randomization in the child, like gdb does, so that addresses
+/// are the same from one run to the next. Failing to do so is not fatal.
+fn disable_randomization() -> Result<(), std::io::Error> {
+    unsafe {
+        let persona = libc::personality(0xffff_ffff);
+        if persona != -1 {
+            libc::personality((persona | libc::ADDR_NO_RANDOMIZE) as libc::c_ulong);
+        }
+    }
+    Ok(())
 }
 
+/// Offset of u_debugreg in struct user (see sys/user.h) on x86-64
+const DEBUG_REGISTER_OFFSET: usize = 848;
+
 fn align_addr_to_word(addr: usize) -> usize {
     addr & (-(std::mem::size_of::<usize>() as isize) as usize)
 }
 
+/// Names of the general purpose registers, in the order info registers lists them
+pub const REGISTER_NAMES: [&str; 26] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip", "eflags", "cs", "ss", "ds", "es", "fs", "gs", "fs_base", "gs_base",
+];
+
+/// Find a general purpose register in a register set by its name, e.g. "rip" or "rax"
+fn register_mut<'a>(regs: &'a mut libc::user_regs_struct, name: &str) -> Option<&'a mut u64> {
+    Some(match name {
+        "rax" => &mut regs.rax,
+        "rbx" => &mut regs.rbx,
+        "rcx" => &mut regs.rcx,
+        "rdx" => &mut regs.rdx,
+        "rsi" => &mut regs.rsi,
+        "rdi" => &mut regs.rdi,
+        "rbp" | "fp" => &mut regs.rbp,
+        "rsp" | "sp" => &mut regs.rsp,
+        "r8" => &mut regs.r8,
+        "r9" => &mut regs.r9,
+        "r10" => &mut regs.r10,
+        "r11" => &mut regs.r11,
+        "r12" => &mut regs.r12,
+        "r13" => &mut regs.r13,
+        "r14" => &mut regs.r14,
+        "r15" => &mut regs.r15,
+        "rip" | "pc" => &mut regs.rip,
+        "eflags" => &mut regs.eflags,
+        "cs" => &mut regs.cs,
+        "ss" => &mut regs.ss,
+        "ds" => &mut regs.ds,
+        "es" => &mut regs.es,
+        "fs" => &mut regs.fs,
+        "gs" => &mut regs.gs,
+        "fs_base" => &mut regs.fs_base,
+        "gs_base" => &mut regs.gs_base,
+        _ => return None,
+    })
+}
+
+/// Look up a general purpose register by its name, e.g. "rip" or "rax"
+pub fn get_register(regs: &libc::user_regs_struct, name: &str) -> Option<u64> {
+    let mut regs = *regs;
+    register_mut(&mut regs, name).map(|value| *value)
+}
+
+/// Change a general purpose register in a register set, returning false for an unknown name
+pub fn set_register(regs: &mut libc::user_regs_struct, name: &str, value: u64) -> bool {
+    match register_mut(regs, name) {
+        Some(register) => {
+            *register = value;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Follow the threads the inferior creates, the processes it forks, and the programs it executes
+fn set_trace_options(pid: Pid) -> Result<(), nix::Error> {
+    ptrace::setoptions(
+        pid,
+        ptrace::Options::PTRACE_O_TRACECLONE
+            | ptrace::Options::PTRACE_O_TRACEFORK
+            | ptrace::Options::PTRACE_O_TRACEVFORK
+            | ptrace::Options::PTRACE_O_TRACEVFORKDONE
+            | ptrace::Options::PTRACE_O_TRACEEXEC,
+    )
+}
+
+/// Send a signal to one thread of a process
+fn tgkill(pid: Pid, tid: Pid, signal: Signal) -> Result<(), nix::Error> {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_tgkill,
+            pid.as_raw(),
+            tid.as_raw(),
+            signal as libc::c_int,
+        )
+    };
+    nix::errno::Errno::result(res).map(drop)
+}
+
+/// Read the debug register DR<index> of a thread
+fn read_debug_register(tid: Pid, index: usize) -> Result<usize, nix::Error> {
+    let offset = DEBUG_REGISTER_OFFSET + index * std::mem::size_of::<usize>();
+    // PTRACE_PEEKUSER returns the value, so -1 is only an error if errno is set
+    let value = unsafe {
+        nix::errno::Errno::clear();
+        libc::ptrace(
+            libc::PTRACE_PEEKUSER,
+            tid.as_raw(),
+            offset as *mut libc::c_void,
+            std::ptr::null_mut::<libc::c_void>(),
+        )
+    };
+    match nix::errno::Errno::result(value) {
+        Ok(_) | Err(nix::Error::Sys(nix::errno::Errno::UnknownErrno)) => Ok(value as usize),
+        Err(err) => Err(err),
+    }
+}
+
+/// Write the debug register DR<index> of a thread
+fn write_debug_register(tid: Pid, index: usize, value: usize) -> Result<(), nix::Error> {
+    let offset = DEBUG_REGISTER_OFFSET + index * std::mem::size_of::<usize>();
+    let res = unsafe {
+        libc::ptrace(
+            libc::PTRACE_POKEUSER,
+            tid.as_raw(),
+            offset as *mut libc::c_void,
+            value as *mut libc::c_void,
+        )
+    };
+    nix::errno::Errno::result(res).map(drop)
+}
+
+/// The thread a wait status is about
+fn status_tid(status: &WaitStatus) -> Option<Pid> {
+    match *status {
+        WaitStatus::Exited(tid, _)
+        | WaitStatus::Signaled(tid, _, _)
+        | WaitStatus::Stopped(tid, _)
+        | WaitStatus::PtraceEvent(tid, _, _)
+        | WaitStatus::PtraceSyscall(tid)
+        | WaitStatus::Continued(tid) => Some(tid),
+        WaitStatus::StillAlive => None,
+    }
+}
+
+/// A thread of the inferior
+struct Thread {
+    /// Number of the thread, as listed by info threads
+    id: usize,
+    tid: Pid,
+    running: bool,
+    /// An event the thread reported while the other threads were being stopped, reported the
+    /// next time the inferior is resumed instead of resuming it
+    pending_event: Option<Status>,
+}
+
 pub struct Inferior {
-    child: Child,
+    pid: Pid,
+    /// None if the inferior was attached to or forked by another inferior rather than started by
+    /// us
+    child: Option<Child>,
+    /// Whether the inferior was attached to rather than started by us, and should be detached
+    /// from rather than killed on quit
+    attached: bool,
+    threads: Vec<Thread>,
+    next_thread_id: usize,
+    /// The thread whose registers are read and written, and that is stepped
+    current: Pid,
+    /// Whether only the current thread was resumed, to execute a single instruction
+    stepping: bool,
+    /// Wait statuses of processes that are not threads of this inferior yet, e.g. the first stop
+    /// of a new thread, which may be reported before the clone that created it
+    stashed: Vec<WaitStatus>,
+    /// Built the first time a frame is needed, and kept until the mappings of the process change
+    unwinder: RefCell<Option<Unwinder>>,
 }
 
 impl Inferior {
     /// Hack a byte into original instruction, return the origin byte
-    fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
+    pub fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
         let aligned_addr = align_addr_to_word(addr);
         let byte_offset = addr - aligned_addr;
-        let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
-        let orig_byte = (word >> 8 * byte_offset) & 0xff;
-        let masked_word = word & !(0xff << 8 * byte_offset);
-        let updated_word = masked_word | ((val as u64) << 8 * byte_offset);
+        let word = ptrace::read(self.current, aligned_addr as ptrace::AddressType)? as u64;
+        let orig_byte = (word >> (8 * byte_offset)) & 0xff;
+        let masked_word = word & !(0xff << (8 * byte_offset));
+        let updated_word = masked_word | ((val as u64) << (8 * byte_offset));
         ptrace::write(
-            self.pid(),
+            self.current,
             aligned_addr as ptrace::AddressType,
             updated_word as *mut std::ffi::c_void,
         )?;
         Ok(orig_byte as u8)
     }
 
+    /// Write bytes into the memory of the inferior, e.g. to assign a variable
+    pub fn write_bytes(&mut self, addr: usize, bytes: &[u8]) -> Result<(), nix::Error> {
+        for (offset, byte) in bytes.iter().enumerate() {
+            self.write_byte(addr + offset, *byte)?;
+        }
+        Ok(())
+    }
+
     /// Hack 0xcc into original instruction, turn it into INT
     pub fn write_breakpoint(&mut self, addr: usize) -> Result<u8, nix::Error> {
         self.write_byte(addr, 0xcc)
     }
 
-    /// Check if the previous instruction a breakpoint
-    pub fn get_previous_ins(&self) -> Result<usize, nix::Error> {
-        let regs = ptrace::getregs(self.pid()).unwrap();
-        Ok(regs.rip as usize - 1)
+    /// Overwrite the register set of the current thread
+    pub fn set_registers(&mut self, regs: libc::user_regs_struct) -> Result<(), nix::Error> {
+        ptrace::setregs(self.current, regs)
     }
 
-    /// Restore the orignial instruction and step one, then restore to the breakpoint
-    pub fn step_breakpoint(&mut self, rip: usize, orin_byte: u8) -> bool {
-        // restore instruction
-        self.write_byte(rip, orin_byte).unwrap();
-        // rewind rip to the stopped instruction
-        let mut regs = ptrace::getregs(self.pid()).unwrap();
+    /// Move the instruction pointer of the current thread, e.g. to rewind it onto a breakpoint
+    /// that was just hit
+    pub fn set_rip(&mut self, rip: usize) -> Result<(), nix::Error> {
+        let mut regs = ptrace::getregs(self.current)?;
         regs.rip = rip as u64;
-        ptrace::setregs(self.pid(), regs).unwrap();
+        ptrace::setregs(self.current, regs)
+    }
+
+    /// Write the debug register DR<index> of every thread, since watchpoints apply to all of them
+    pub fn write_debug_register(&mut self, index: usize, value: usize) -> Result<(), nix::Error> {
+        for thread in &self.threads {
+            write_debug_register(thread.tid, index, value)?;
+        }
+        Ok(())
+    }
+
+    /// Restore the orignial instruction and step one, then restore to the breakpoint. The signal,
+    /// if any, is delivered as the instruction is stepped.
+    pub fn step_breakpoint(
+        &mut self,
+        rip: usize,
+        orin_byte: u8,
+        signal: Option<signal::Signal>,
+    ) -> Result<Status, nix::Error> {
+        // restore instruction
+        self.write_byte(rip, orin_byte)?;
         // step one the original instruction
-        ptrace::step(self.pid(), None).unwrap();
+        let status = self.step_current(signal)?;
         // restore the breakpoint and return to resume the normal execution
-        match self.wait(None).unwrap() {
-            Status::Stopped(s, _) if s == signal::Signal::SIGTRAP => {
-                self.write_breakpoint(rip).unwrap();
-                true
+        match status {
+            Status::Exited(_) | Status::Signaled(_) => (),
+            _ => {
+                self.write_breakpoint(rip)?;
             }
-            _ => false,
+        }
+        Ok(status)
+    }
+
+    /// Execute a single instruction, stepping by the breakpoint if there is one at the current
+    /// instruction pointer, and delivering the given signal
+    pub fn step_instruction(
+        &mut self,
+        breakpoints: &HashMap<usize, BreakPoint>,
+        signal: Option<signal::Signal>,
+    ) -> Result<Status, nix::Error> {
+        let rip = self.get_registers()?.rip as usize;
+        let breakpoint = breakpoints.get(&rip).filter(|bp| bp.enabled());
+        match breakpoint {
+            Some(breakpoint) => self.step_breakpoint(rip, breakpoint.orig_byte(), signal),
+            None => self.step_current(signal),
+        }
+    }
+
+    /// An inferior whose only thread is still running, until its first stop is waited for
+    fn traced(pid: Pid, child: Option<Child>, attached: bool) -> Inferior {
+        Inferior {
+            pid,
+            child,
+            attached,
+            threads: vec![Thread {
+                id: 1,
+                tid: pid,
+                running: true,
+                pending_event: None,
+            }],
+            next_thread_id: 2,
+            current: pid,
+            stepping: false,
+            stashed: Vec::new(),
+            unwinder: RefCell::new(None),
         }
     }
 
     /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
     /// an error is encountered.
-    pub fn new(
-        target: &str,
-        args: &Vec<String>,
-        breakpoints: &mut HashMap<usize, BreakPoint>,
-    ) -> Option<Inferior> {
+    pub fn new(target: &str, args: &Vec<String>) -> Option<Inferior> {
         // implement me!
         let mut command = Command::new(target);
         command.args(args);
         unsafe {
+            command.pre_exec(disable_randomization);
             command.pre_exec(child_traceme);
         }
         // call fork and exec, return a SIGTRAP
         let child = command.spawn().ok()?;
         // check if child is successfully created
-        let mut inferior = Inferior { child };
+        let mut inferior = Inferior::traced(Pid::from_raw(child.id() as i32), Some(child), false);
         match inferior.wait(None).unwrap() {
-            Status::Stopped(signal, _) if signal == signal::Signal::SIGTRAP => (),
+            Status::Stopped(signal::Signal::SIGTRAP, _) => (),
             _ => return None,
         }
-        // insert breakpoints before run
-        for breakpoint in breakpoints.values_mut() {
-            match inferior.write_breakpoint(breakpoint.addr()) {
+        set_trace_options(inferior.pid()).ok()?;
+        Some(inferior)
+    }
+
+    /// Attaches to an already running process with PTRACE_ATTACH and waits for it to stop. Each
+    /// of its threads is attached to.
+    pub fn attach(pid: Pid) -> Result<Inferior, nix::Error> {
+        ptrace::attach(pid)?;
+        let mut inferior = Inferior::traced(pid, None, true);
+        // PTRACE_ATTACH stops the process with a SIGSTOP, which is swallowed on the next resume
+        loop {
+            match inferior.wait_thread(pid)? {
+                WaitStatus::Stopped(_, signal::Signal::SIGSTOP) => break,
+                // pass on signals that were pending before the SIGSTOP
+                WaitStatus::Stopped(_, signal) => ptrace::cont(pid, signal)?,
+                _ => return Err(nix::Error::Sys(nix::errno::Errno::ESRCH)),
+            }
+        }
+        inferior.threads[0].running = false;
+        set_trace_options(pid)?;
+        let tasks = fs::read_dir(format!("/proc/{}/task", pid))
+            .map_err(|_| nix::Error::Sys(nix::errno::Errno::ESRCH))?;
+        for task in tasks.filter_map(|task| task.ok()) {
+            let tid = match task.file_name().to_string_lossy().parse() {
+                Ok(tid) if tid != pid.as_raw() => Pid::from_raw(tid),
+                _ => continue,
+            };
+            // the thread may have exited meanwhile
+            if ptrace::attach(tid).is_ok() {
+                inferior.add_thread(tid, None)?;
+                set_trace_options(tid)?;
+            }
+        }
+        Ok(inferior)
+    }
+
+    /// Takes over the child that this inferior forked. The child is traced from its birth and
+    /// starts with a SIGSTOP, which is swallowed here. It inherits the breakpoints of its parent
+    /// along with its memory.
+    pub fn fork_child(&mut self, pid: Pid) -> Result<Inferior, nix::Error> {
+        let mut child = Inferior::traced(pid, None, false);
+        match self.wait_thread(pid)? {
+            WaitStatus::Stopped(..) => {
+                child.threads[0].running = false;
+                Ok(child)
+            }
+            _ => Err(nix::Error::Sys(nix::errno::Errno::ESRCH)),
+        }
+    }
+
+    /// Removes the breakpoints and lets the inferior run on its own
+    pub fn detach(&mut self, breakpoints: &HashMap<usize, BreakPoint>) -> Result<(), nix::Error> {
+        for breakpoint in breakpoints.values().filter(|bp| bp.enabled()) {
+            self.write_byte(breakpoint.addr(), breakpoint.orig_byte())?;
+        }
+        self.write_debug_register(7, 0)?;
+        for thread in &self.threads {
+            ptrace::detach(thread.tid, None)?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if the inferior was attached to rather than started by us
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    /// Insert the enabled breakpoints, recording the original bytes they replace
+    pub fn install_breakpoints(&mut self, breakpoints: &mut HashMap<usize, BreakPoint>) {
+        for breakpoint in breakpoints.values_mut().filter(|bp| bp.enabled()) {
+            match self.write_breakpoint(breakpoint.addr()) {
                 Ok(orig_byte) => breakpoint.set_byte(orig_byte),
-                Err(_) => println!("Fail to insert breakpoint at {:#x}", breakpoint.addr()),
+                Err(_) => console!("Fail to insert breakpoint at {:#x}", breakpoint.addr()),
             }
         }
-        Some(inferior)
     }
 
-    /// Wakes up the inferior and waits until it stops or terminates
+    /// Wakes up every thread of the inferior and waits until one of them stops or the inferior
+    /// terminates. The signal is delivered to the current thread.
     pub fn continue_run(&mut self, signal: Option<signal::Signal>) -> Result<Status, nix::Error> {
-        ptrace::cont(self.pid(), signal)?;
+        // an event a thread had while the others were being stopped comes first
+        if let Some(thread) = self
+            .threads
+            .iter_mut()
+            .find(|thread| thread.pending_event.is_some())
+        {
+            let (tid, status) = (thread.tid, thread.pending_event.take().unwrap());
+            if let Some(signal) = signal {
+                tgkill(self.pid, self.current, signal)?;
+            }
+            self.current = tid;
+            return Ok(status);
+        }
+        self.stepping = false;
+        let tids: Vec<Pid> = self.threads.iter().map(|thread| thread.tid).collect();
+        for tid in tids {
+            let signal = if tid == self.current { signal } else { None };
+            self.resume_thread(tid, false, signal)?;
+        }
+        self.wait(None)
+    }
+
+    /// Execute a single instruction in the current thread, leaving the other threads stopped
+    fn step_current(&mut self, signal: Option<signal::Signal>) -> Result<Status, nix::Error> {
+        self.stepping = true;
+        self.resume_thread(self.current, true, signal)?;
         self.wait(None)
     }
 
+    fn resume_thread(
+        &mut self,
+        tid: Pid,
+        step: bool,
+        signal: Option<signal::Signal>,
+    ) -> Result<(), nix::Error> {
+        if step {
+            ptrace::step(tid, signal)?;
+        } else {
+            ptrace::cont(tid, signal)?;
+        }
+        if let Some(thread) = self.threads.iter_mut().find(|thread| thread.tid == tid) {
+            thread.running = true;
+        }
+        Ok(())
+    }
+
     /// Kill the existing child process and reap it
     pub fn kill(&mut self) -> Result<(), nix::Error> {
-        self.child.kill().expect("Process is not running");
-        self.wait(None).expect("Fail to reap the killed process");
-        println!("Killing running inferior (pid {})", self.pid());
+        match self.child.as_mut() {
+            Some(child) => child.kill().expect("Process is not running"),
+            None => signal::kill(self.pid(), signal::Signal::SIGKILL)?,
+        }
+        // every thread reports its death, the leader last
+        let mut tids: Vec<Pid> = self
+            .threads
+            .iter()
+            .map(|thread| thread.tid)
+            .filter(|tid| *tid != self.pid)
+            .collect();
+        tids.push(self.pid);
+        for tid in tids {
+            while let Ok(status) = waitpid(tid, Some(WaitPidFlag::__WALL)) {
+                if let WaitStatus::Exited(..) | WaitStatus::Signaled(..) = status {
+                    break;
+                }
+            }
+        }
+        console!("Killing running inferior (pid {})", self.pid());
         Ok(())
     }
 
+    /// The threads of the inferior, by number and thread id
+    pub fn threads(&self) -> Vec<(usize, Pid)> {
+        self.threads
+            .iter()
+            .map(|thread| (thread.id, thread.tid))
+            .collect()
+    }
+
+    /// The number and thread id of the current thread
+    pub fn current_thread(&self) -> (usize, Pid) {
+        self.threads
+            .iter()
+            .find(|thread| thread.tid == self.current)
+            .map_or((0, self.current), |thread| (thread.id, thread.tid))
+    }
+
+    /// Make the thread with the given number the current one, returning false if there is none
+    pub fn select_thread(&mut self, id: usize) -> bool {
+        match self.threads.iter().find(|thread| thread.id == id) {
+            Some(thread) => {
+                self.current = thread.tid;
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Returns the pid of this inferior.
     pub fn pid(&self) -> Pid {
-        nix::unistd::Pid::from_raw(self.child.id() as i32)
+        self.pid
+    }
+
+    /// Bring the unwinder up to date after the dynamic linker loaded or unloaded libraries
+    pub fn update_unwinder(&mut self) {
+        if let Some(unwinder) = self.unwinder.get_mut() {
+            unwinder.update(self.pid);
+        }
+    }
+
+    /// The innermost frame of a thread
+    pub fn thread_frame(&self, tid: Pid) -> Result<Frame, nix::Error> {
+        let regs = self.thread_registers(tid)?;
+        Ok(self
+            .unwinder()
+            .innermost_frame(&regs, |addr| self.read_word(addr).ok()))
+    }
+
+    /// The register set of a thread
+    pub fn thread_registers(&self, tid: Pid) -> Result<libc::user_regs_struct, nix::Error> {
+        ptrace::getregs(tid)
+    }
+
+    /// The signal a thread stopped with
+    pub fn thread_signal(&self, tid: Pid) -> Option<Signal> {
+        let info = ptrace::getsiginfo(tid).ok()?;
+        Signal::try_from(info.si_signo).ok()
+    }
+
+    /// Waits for any thread of the inferior, setting aside the statuses of other processes
+    fn wait_any(&mut self, options: Option<WaitPidFlag>) -> Result<WaitStatus, nix::Error> {
+        let threads = &self.threads;
+        let is_thread = |status: &WaitStatus| {
+            status_tid(status).is_some_and(|tid| threads.iter().any(|t| t.tid == tid))
+        };
+        if let Some(index) = self.stashed.iter().position(is_thread) {
+            return Ok(self.stashed.remove(index));
+        }
+        let options = options.unwrap_or_else(WaitPidFlag::empty) | WaitPidFlag::__WALL;
+        loop {
+            let status = waitpid(Pid::from_raw(-1), Some(options))?;
+            if is_thread(&status) || status == WaitStatus::StillAlive {
+                return Ok(status);
+            }
+            self.stashed.push(status);
+        }
+    }
+
+    /// Waits for one thread, which may have reported its status to wait_any already
+    fn wait_thread(&mut self, tid: Pid) -> Result<WaitStatus, nix::Error> {
+        match self
+            .stashed
+            .iter()
+            .position(|status| status_tid(status) == Some(tid))
+        {
+            Some(index) => Ok(self.stashed.remove(index)),
+            None => waitpid(tid, Some(WaitPidFlag::__WALL)),
+        }
+    }
+
+    /// Start tracking a new thread, and wait for the SIGSTOP it starts with. A thread created
+    /// by another one inherits its hardware watchpoints.
+    fn add_thread(&mut self, tid: Pid, creator: Option<Pid>) -> Result<(), nix::Error> {
+        let id = self.next_thread_id;
+        self.next_thread_id += 1;
+        self.threads.push(Thread {
+            id,
+            tid,
+            running: true,
+            pending_event: None,
+        });
+        self.stop_thread(tid, false)?;
+        if let Some(creator) = creator {
+            for index in [0, 1, 2, 3, 7].iter() {
+                write_debug_register(tid, *index, read_debug_register(creator, *index)?)?;
+            }
+            console!("[New Thread {} (LWP {})]", id, tid);
+        }
+        Ok(())
     }
 
-    /// Print backtrace of current status till main
-    pub fn print_backtrace(&mut self, debug_data: &DwarfData) -> Result<(), nix::Error> {
-        let regs = ptrace::getregs(self.pid()).unwrap();
-        let mut instruction_ptr = regs.rip as usize;
-        let mut stackbase_ptr = regs.rbp as usize;
-        let mut backtraces = Vec::new();
+    fn remove_thread(&mut self, tid: Pid) {
+        if let Some(index) = self.threads.iter().position(|thread| thread.tid == tid) {
+            let thread = self.threads.remove(index);
+            console!("[Thread {} (LWP {}) exited]", thread.id, thread.tid);
+        }
+        if self.current == tid {
+            self.current = self.threads.first().map_or(self.pid, |thread| thread.tid);
+        }
+    }
+
+    /// Wait until a running thread stops with SIGSTOP, sending it one if send is set. Events the
+    /// thread reports in the meantime are kept to be reported on the next resume, except for
+    /// breakpoint hits, which are undone so that the thread hits the breakpoint again later.
+    fn stop_thread(&mut self, tid: Pid, send: bool) -> Result<(), nix::Error> {
+        if send && tgkill(self.pid, tid, signal::Signal::SIGSTOP).is_err() {
+            // the thread is exiting, its death is reported below
+        }
         loop {
-            let function = debug_data.get_function_from_addr(instruction_ptr).unwrap();
-            let line = debug_data.get_line_from_addr(instruction_ptr).unwrap();
-            backtraces.push(format!("{} ({})", function, line));
-            if function == String::from("main") {
-                break;
+            match self.wait_thread(tid)? {
+                WaitStatus::Stopped(_, signal::Signal::SIGSTOP) => break,
+                WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                    self.remove_thread(tid);
+                    return Ok(());
+                }
+                WaitStatus::PtraceEvent(_, _, event)
+                    if event == ptrace::Event::PTRACE_EVENT_CLONE as i32 =>
+                {
+                    let new_tid = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                    self.add_thread(new_tid, Some(tid))?;
+                }
+                WaitStatus::Stopped(_, signal::Signal::SIGTRAP)
+                    if read_debug_register(tid, 6)? & 0xf == 0 =>
+                {
+                    let mut regs = ptrace::getregs(tid)?;
+                    let addr = regs.rip as usize - 1;
+                    let word = ptrace::read(tid, addr as ptrace::AddressType)?;
+                    if word & 0xff == 0xcc {
+                        regs.rip -= 1;
+                        ptrace::setregs(tid, regs)?;
+                    }
+                }
+                status => {
+                    let event = self.thread_status(tid, status)?;
+                    if let Some(thread) = self.threads.iter_mut().find(|thread| thread.tid == tid) {
+                        thread.pending_event = Some(event);
+                    }
+                }
             }
-            instruction_ptr = ptrace::read(self.pid(), (stackbase_ptr + 8) as ptrace::AddressType)
-                .unwrap() as usize;
-            stackbase_ptr =
-                ptrace::read(self.pid(), stackbase_ptr as ptrace::AddressType).unwrap() as usize;
+            // let the thread go on to the SIGSTOP still pending for it
+            ptrace::cont(tid, None)?;
         }
-        for backtrace in backtraces.iter().rev() {
-            println!("{}", backtrace);
+        if let Some(thread) = self.threads.iter_mut().find(|thread| thread.tid == tid) {
+            thread.running = false;
         }
         Ok(())
     }
 
-    /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
-    /// after the waitpid call.
-    pub fn wait(&self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
-        Ok(match waitpid(self.pid(), options)? {
-            WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
-            WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
+    /// Waits until a thread of the inferior stops or the inferior terminates, and returns a
+    /// Status to indicate its state. The thread that stopped becomes the current thread, and
+    /// every other thread is stopped as well. New and exiting threads are taken care of without
+    /// stopping.
+    pub fn wait(&mut self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
+        loop {
+            let status = self.wait_any(options)?;
+            let tid = match status_tid(&status) {
+                Some(tid) => tid,
+                None => return Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)),
+            };
+            if let Some(thread) = self.threads.iter_mut().find(|thread| thread.tid == tid) {
+                thread.running = false;
+            }
+            match status {
+                WaitStatus::Exited(..) | WaitStatus::Signaled(..) if tid != self.pid => {
+                    self.remove_thread(tid);
+                    // the stepped thread is gone, let the others go on
+                    if !self.threads.iter().any(|thread| thread.running) {
+                        self.stepping = false;
+                        let tids: Vec<Pid> = self.threads.iter().map(|thread| thread.tid).collect();
+                        for tid in tids {
+                            self.resume_thread(tid, false, None)?;
+                        }
+                    }
+                }
+                WaitStatus::PtraceEvent(_, _, event)
+                    if event == ptrace::Event::PTRACE_EVENT_CLONE as i32 =>
+                {
+                    let new_tid = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                    self.add_thread(new_tid, Some(tid))?;
+                    // keep running the way we were
+                    self.resume_thread(tid, self.stepping, None)?;
+                    if !self.stepping {
+                        self.resume_thread(new_tid, false, None)?;
+                    }
+                }
+                status => {
+                    self.current = tid;
+                    let running: Vec<Pid> = self
+                        .threads
+                        .iter()
+                        .filter(|thread| thread.running)
+                        .map(|thread| thread.tid)
+                        .collect();
+                    for other in running {
+                        self.stop_thread(other, true)?;
+                    }
+                    return self.thread_status(tid, status);
+                }
+            }
+        }
+    }
+
+    /// Translate the wait status of a thread into a Status
+    fn thread_status(&mut self, tid: Pid, status: WaitStatus) -> Result<Status, nix::Error> {
+        Ok(match status {
+            WaitStatus::Exited(_pid, exit_code) => {
+                self.threads.clear();
+                Status::Exited(exit_code)
+            }
+            WaitStatus::Signaled(_pid, signal, _core_dumped) => {
+                self.threads.clear();
+                Status::Signaled(signal)
+            }
+            WaitStatus::PtraceEvent(_pid, _signal, event) => {
+                if event == ptrace::Event::PTRACE_EVENT_FORK as i32
+                    || event == ptrace::Event::PTRACE_EVENT_VFORK as i32
+                {
+                    let child = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                    Status::Forked(child, event == ptrace::Event::PTRACE_EVENT_VFORK as i32)
+                } else if event == ptrace::Event::PTRACE_EVENT_EXEC as i32 {
+                    // exec ends every other thread, and gives its thread id to the leader
+                    let pid = self.pid;
+                    self.threads.retain(|thread| thread.tid == pid);
+                    self.current = pid;
+                    *self.unwinder.get_mut() = None;
+                    Status::Execed(ptrace::getregs(pid)?.rip as usize)
+                } else {
+                    Status::VforkDone
+                }
+            }
             WaitStatus::Stopped(_pid, signal) => {
-                let regs = ptrace::getregs(self.pid())?;
+                let regs = ptrace::getregs(tid)?;
+                // DR6 tells a watchpoint trap apart from a breakpoint or single-step trap
+                let status = read_debug_register(tid, 6)?;
+                if signal == signal::Signal::SIGTRAP && status & 0xf != 0 {
+                    write_debug_register(tid, 6, 0)?;
+                    let index = status.trailing_zeros() as usize;
+                    return Ok(Status::Watchpoint(index, regs.rip as usize));
+                }
                 Status::Stopped(signal, regs.rip as usize)
             }
-            other => panic!("waitpid returned unexpected status: {:?}", other),
+            // neither is asked for
+            WaitStatus::PtraceSyscall(_) | WaitStatus::Continued(_) | WaitStatus::StillAlive => {
+                return Err(nix::Error::Sys(nix::errno::Errno::EINVAL))
+            }
+        })
+    }
+}
+
+impl Target for Inferior {
+    fn get_registers(&self) -> Result<libc::user_regs_struct, nix::Error> {
+        ptrace::getregs(self.current)
+    }
+
+    fn read_bytes(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = Vec::with_capacity(len);
+        let mut aligned_addr = align_addr_to_word(addr);
+        while aligned_addr < addr + len {
+            let word = self.read_word(aligned_addr)? as u64;
+            bytes.extend_from_slice(&word.to_le_bytes());
+            aligned_addr += std::mem::size_of::<usize>();
+        }
+        let start = addr - align_addr_to_word(addr);
+        Ok(bytes[start..start + len].to_vec())
+    }
+
+    fn unwinder(&self) -> RefMut<'_, Unwinder> {
+        RefMut::map(self.unwinder.borrow_mut(), |unwinder| {
+            unwinder.get_or_insert_with(|| Unwinder::new(self.pid))
         })
     }
+
+    fn auxv(&self) -> Vec<u8> {
+        fs::read(format!("/proc/{}/auxv", self.pid)).unwrap_or_default()
+    }
+
+    fn read_word(&self, addr: usize) -> Result<usize, nix::Error> {
+        Ok(ptrace::read(self.current, addr as ptrace::AddressType)? as usize)
+    }
 }
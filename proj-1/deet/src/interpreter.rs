@@ -0,0 +1,67 @@
+use crate::json::Json;
+use std::cell::{Cell, RefCell};
+
+/// Who deet is talking to
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// A user at the terminal
+    Console,
+    /// A frontend reading JSON records, one per line (--interpreter=json)
+    Json,
+    /// A Debug Adapter Protocol client, which gets the records translated into DAP events
+    Dap,
+}
+
+thread_local! {
+    /// Who the debugger running on this thread is talking to. deet itself has a single thread,
+    /// but tests run sessions side by side.
+    static MODE: Cell<Mode> = const { Cell::new(Mode::Console) };
+    /// Records emitted in DAP mode that the adapter has yet to pick up
+    static RECORDS: RefCell<Vec<Json>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Print a line of output meant for the user. With --interpreter=json, the line is wrapped in a
+/// console record so that it does not get mixed up with the other records.
+macro_rules! console {
+    ($($arg:tt)*) => {
+        $crate::interpreter::console_line(&format!($($arg)*))
+    };
+}
+
+pub fn set_mode(mode: Mode) {
+    MODE.with(|current| current.set(mode));
+}
+
+pub fn mode() -> Mode {
+    MODE.with(Cell::get)
+}
+
+/// Whether deet is driven by a program rather than by a user
+pub fn machine_readable() -> bool {
+    mode() != Mode::Console
+}
+
+pub fn console_line(text: &str) {
+    if machine_readable() {
+        emit("console", vec![("text", Json::string(text))]);
+    } else {
+        println!("{}", text);
+    }
+}
+
+/// Print a record for the frontend, of the given type and with the given fields. Does nothing
+/// when talking to a user.
+pub fn emit(record_type: &str, fields: Vec<(&str, Json)>) {
+    let mut record = vec![("type", Json::string(record_type))];
+    record.extend(fields);
+    match mode() {
+        Mode::Console => (),
+        Mode::Json => println!("{}", Json::object(record)),
+        Mode::Dap => RECORDS.with(|records| records.borrow_mut().push(Json::object(record))),
+    }
+}
+
+/// Take the records emitted in DAP mode since the last call
+pub fn take_records() -> Vec<Json> {
+    RECORDS.with(|records| records.take())
+}
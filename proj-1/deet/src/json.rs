@@ -0,0 +1,356 @@
+use std::fmt;
+
+/// A JSON value, as written by --interpreter=json and exchanged with Debug Adapter Protocol
+/// clients
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    /// Numbers are integers: DAP and deet have no use for fractions
+    Int(i64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn string(text: &str) -> Json {
+        Json::String(text.to_string())
+    }
+
+    /// An address or register value, written in hex like everywhere else in deet
+    pub fn hex(value: usize) -> Json {
+        Json::String(format!("{:#x}", value))
+    }
+
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// The value of a field of an object
+    pub fn get(&self, name: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    /// Parse a JSON document, returning None if it is malformed
+    pub fn parse(text: &str) -> Option<Json> {
+        let mut parser = Parser {
+            chars: text.chars().collect(),
+            pos: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos == parser.chars.len() {
+            Some(value)
+        } else {
+            None
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, word: &str) -> Option<()> {
+        for c in word.chars() {
+            if self.next()? != c {
+                return None;
+            }
+        }
+        Some(())
+    }
+
+    fn value(&mut self) -> Option<Json> {
+        self.skip_whitespace();
+        match self.peek()? {
+            'n' => self.expect("null").map(|_| Json::Null),
+            't' => self.expect("true").map(|_| Json::Bool(true)),
+            'f' => self.expect("false").map(|_| Json::Bool(false)),
+            '"' => self.string().map(Json::String),
+            '[' => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                self.skip_whitespace();
+                if self.peek()? == ']' {
+                    self.pos += 1;
+                    return Some(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.next()? {
+                        ',' => (),
+                        ']' => return Some(Json::Array(values)),
+                        _ => return None,
+                    }
+                }
+            }
+            '{' => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                self.skip_whitespace();
+                if self.peek()? == '}' {
+                    self.pos += 1;
+                    return Some(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let name = self.string()?;
+                    self.skip_whitespace();
+                    self.expect(":")?;
+                    fields.push((name, self.value()?));
+                    self.skip_whitespace();
+                    match self.next()? {
+                        ',' => (),
+                        '}' => return Some(Json::Object(fields)),
+                        _ => return None,
+                    }
+                }
+            }
+            _ => self.number(),
+        }
+    }
+
+    fn number(&mut self) -> Option<Json> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_digit() || "+-.eE".contains(c)) {
+            self.pos += 1;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        match text.parse::<i64>() {
+            Ok(value) => Some(Json::Int(value)),
+            // a fraction is cut down to its integer part
+            Err(_) => text
+                .parse::<f64>()
+                .ok()
+                .map(|value| Json::Int(value as i64)),
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.expect("\"")?;
+        let mut text = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Some(text),
+                '\\' => match self.next()? {
+                    'n' => text.push('\n'),
+                    'r' => text.push('\r'),
+                    't' => text.push('\t'),
+                    'b' => text.push('\u{8}'),
+                    'f' => text.push('\u{c}'),
+                    'u' => {
+                        let mut code = self.hex_escape()?;
+                        // a character outside the basic plane comes as a surrogate pair
+                        if (0xd800..0xdc00).contains(&code) {
+                            self.expect("\\u")?;
+                            let low = self.hex_escape()?;
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low.checked_sub(0xdc00)?);
+                        }
+                        text.push(std::char::from_u32(code)?);
+                    }
+                    c => text.push(c),
+                },
+                c => text.push(c),
+            }
+        }
+    }
+
+    fn hex_escape(&mut self) -> Option<u32> {
+        let mut code = 0;
+        for _ in 0..4 {
+            code = code * 16 + self.next()?.to_digit(16)?;
+        }
+        Some(code)
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Int(value) => write!(f, "{}", value),
+            Json::String(text) => write_string(f, text),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (index, (name, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, name)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let value = Json::object(vec![
+            ("null", Json::Null),
+            (
+                "bools",
+                Json::Array(vec![Json::Bool(true), Json::Bool(false)]),
+            ),
+            ("int", Json::Int(-42)),
+            ("text", Json::string("hello")),
+            ("empty", Json::Array(vec![])),
+            (
+                "nested",
+                Json::object(vec![("inner", Json::object(vec![]))]),
+            ),
+        ]);
+        let text = value.to_string();
+        assert_eq!(
+            text,
+            r#"{"null":null,"bools":[true,false],"int":-42,"text":"hello","empty":[],"nested":{"inner":{}}}"#
+        );
+        assert_eq!(Json::parse(&text), Some(value));
+    }
+
+    #[test]
+    fn whitespace() {
+        assert_eq!(
+            Json::parse(" { \"a\" :\n[ 1 , 2 ] }\t"),
+            Some(Json::object(vec![(
+                "a",
+                Json::Array(vec![Json::Int(1), Json::Int(2)])
+            )]))
+        );
+    }
+
+    #[test]
+    fn fractions_are_truncated() {
+        assert_eq!(Json::parse("2.75"), Some(Json::Int(2)));
+        assert_eq!(Json::parse("1e3"), Some(Json::Int(1000)));
+    }
+
+    #[test]
+    fn escapes() {
+        let text = "quote \" backslash \\ newline \n tab \t bell \u{7} é 😀";
+        let written = Json::string(text).to_string();
+        assert_eq!(
+            written,
+            "\"quote \\\" backslash \\\\ newline \\n tab \\t bell \\u0007 é 😀\""
+        );
+        assert_eq!(Json::parse(&written), Some(Json::string(text)));
+        assert_eq!(
+            Json::parse(r#""\/\b\f\r\u00e9\ud83d\ude00""#),
+            Some(Json::string("/\u{8}\u{c}\ré😀"))
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        for text in &[
+            "",
+            "nul",
+            "tru",
+            "[1,",
+            "[1 2]",
+            "{\"a\" 1}",
+            "{\"a\":1,}",
+            "{a:1}",
+            "\"unterminated",
+            "\"\\u12\"",
+            "\"\\ud83d\"",
+            "\"\\udc00\"",
+            "-",
+            "1 2",
+            "[]]",
+        ] {
+            assert_eq!(Json::parse(text), None, "{}", text);
+        }
+    }
+}
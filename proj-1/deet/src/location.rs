@@ -0,0 +1,346 @@
+//! Evaluating the DWARF expressions that describe where variables are. Optimizing compilers
+//! keep variables in registers, move them around as the function runs (location lists), split
+//! them in pieces or drop them altogether, so the location of a variable may only be known once
+//! the registers and memory of a frame are.
+
+use crate::dwarf_data::{Expression, Location};
+use crate::target::Target;
+use crate::unwind::{Frame, REGISTER_COUNT};
+use gimli::{EndianSlice, LittleEndian, Operation};
+
+/// Upper bound on the number of operations evaluated, in case of a loop of DW_OP_bra/DW_OP_skip
+const MAX_STEPS: usize = 10000;
+
+/// Where the value of a variable is found
+pub enum Place {
+    Memory(usize),
+    /// The value has no address, e.g. it lives in registers or is a constant
+    Value(Vec<u8>),
+    OptimizedOut,
+}
+
+impl Place {
+    /// The place of the part of size bytes that starts offset bytes into this place, e.g. of a
+    /// member of a structure
+    pub fn offset(self, offset: isize, size: usize) -> Result<Place, String> {
+        Ok(match self {
+            Place::Memory(addr) => Place::Memory(addr.wrapping_add(offset as usize)),
+            Place::Value(bytes) => {
+                let start = offset as usize;
+                if offset < 0 || start + size > bytes.len() {
+                    return Err("no such vector element".to_string());
+                }
+                Place::Value(bytes[start..start + size].to_vec())
+            }
+            Place::OptimizedOut => Place::OptimizedOut,
+        })
+    }
+}
+
+/// What an expression needs to know about the frame it is evaluated in
+pub struct Context<'a> {
+    pub target: &'a dyn Target,
+    pub frame: &'a Frame,
+    pub cfa: Option<usize>,
+    /// The value of DW_AT_frame_base of the function of the frame
+    pub frame_base: Option<usize>,
+}
+
+/// Where a piece of a value is, as computed by an expression
+enum PieceLocation {
+    Memory(usize),
+    Register(u16),
+    Value(u64),
+    Bytes(Vec<u8>),
+    OptimizedOut,
+}
+
+/// A piece of a value, of the given size in bytes or else of the rest of the value
+struct Piece {
+    location: PieceLocation,
+    size: Option<usize>,
+}
+
+/// Find where a variable of the given size is in the frame of the context
+pub fn locate(location: &Location, context: &Context, size: usize) -> Result<Place, String> {
+    let expression = match location {
+        Location::Address(addr) => return Ok(Place::Memory(*addr)),
+        Location::FramePointerOffset(offset) => {
+            let frame_base = context.frame_base.ok_or("Frame base is not available")?;
+            return Ok(Place::Memory((frame_base as isize + offset) as usize));
+        }
+        Location::Value(bytes) => return Ok(Place::Value(bytes.clone())),
+        Location::OptimizedOut => return Ok(Place::OptimizedOut),
+        Location::Expression(expression) => expression,
+        Location::List(entries) => {
+            let pc = context.frame.lookup_pc();
+            match entries
+                .iter()
+                .find(|(start, end, _)| *start <= pc && pc < *end)
+            {
+                Some((_, _, expression)) => expression,
+                None => return Ok(Place::OptimizedOut),
+            }
+        }
+    };
+    let pieces = evaluate(expression, context)?;
+    if let [Piece {
+        location: PieceLocation::Memory(addr),
+        ..
+    }] = pieces[..]
+    {
+        return Ok(Place::Memory(addr));
+    }
+    // gather the pieces into the bytes of the value
+    let mut bytes = Vec::new();
+    for piece in pieces {
+        let len = piece
+            .size
+            .unwrap_or_else(|| size.saturating_sub(bytes.len()));
+        let mut piece_bytes = match piece.location {
+            PieceLocation::Memory(addr) => context
+                .target
+                .read_bytes(addr, len)
+                .map_err(|_| format!("Cannot access memory at address {:#x}", addr))?,
+            PieceLocation::Register(register) => match register_value(context, register)? {
+                Some(value) => value.to_le_bytes().to_vec(),
+                None => return Ok(Place::OptimizedOut),
+            },
+            PieceLocation::Value(value) => value.to_le_bytes().to_vec(),
+            PieceLocation::Bytes(bytes) => bytes,
+            PieceLocation::OptimizedOut => return Ok(Place::OptimizedOut),
+        };
+        piece_bytes.resize(len, 0);
+        bytes.extend(piece_bytes);
+    }
+    bytes.resize(size.max(bytes.len()), 0);
+    Ok(Place::Value(bytes))
+}
+
+/// The value of a register in the frame of the context, or None if it was not saved
+fn register_value(context: &Context, register: u16) -> Result<Option<u64>, String> {
+    if register as usize >= REGISTER_COUNT {
+        return Err(format!("Cannot read DWARF register {}", register));
+    }
+    Ok(context.frame.register(register).map(|value| value as u64))
+}
+
+fn pop(stack: &mut Vec<u64>) -> Result<u64, String> {
+    stack
+        .pop()
+        .ok_or_else(|| "Location expression pops an empty stack".to_string())
+}
+
+/// Run a DWARF expression on a stack machine, as described in section 2.5 of the DWARF 4
+/// standard, and return the pieces of the value it locates
+fn evaluate(expression: &Expression, context: &Context) -> Result<Vec<Piece>, String> {
+    let bytes = &expression.bytes[..];
+    let optimized_out = || {
+        Ok(vec![Piece {
+            location: PieceLocation::OptimizedOut,
+            size: None,
+        }])
+    };
+    let mut pc = EndianSlice::new(bytes, LittleEndian);
+    let mut stack: Vec<u64> = Vec::new();
+    let mut pieces = Vec::new();
+    // set by the operations that give the location of a piece rather than push its address
+    let mut location: Option<PieceLocation> = None;
+    let mut steps = 0;
+    while !pc.is_empty() {
+        steps += 1;
+        if steps > MAX_STEPS {
+            return Err("Location expression does not terminate".to_string());
+        }
+        let offset = bytes.len() - pc.len();
+        let opcode = gimli::DwOp(bytes[offset]);
+        let op = Operation::parse(&mut pc, expression.encoding)
+            .map_err(|err| format!("Malformed location expression: {}", err))?;
+        if location.is_some() && !matches!(op, Operation::Piece { .. } | Operation::Nop) {
+            return Err(format!("Unexpected {} in location expression", opcode));
+        }
+        match op {
+            Operation::Address { address } => {
+                stack.push(address.wrapping_add(expression.bias as u64))
+            }
+            Operation::UnsignedConstant { value } => stack.push(value),
+            Operation::SignedConstant { value } => stack.push(value as u64),
+            Operation::Register { register } => {
+                location = Some(PieceLocation::Register(register.0))
+            }
+            Operation::RegisterOffset {
+                register, offset, ..
+            } => match register_value(context, register.0)? {
+                Some(value) => stack.push(value.wrapping_add(offset as u64)),
+                None => return optimized_out(),
+            },
+            Operation::FrameOffset { offset } => {
+                let frame_base = context.frame_base.ok_or("Frame base is not available")?;
+                stack.push((frame_base as u64).wrapping_add(offset as u64));
+            }
+            Operation::CallFrameCFA => {
+                let cfa = context.cfa.ok_or("Call frame address is not available")?;
+                stack.push(cfa as u64);
+            }
+            Operation::Deref { size, space, .. } => {
+                if space {
+                    return Err(format!("Unsupported {} in location expression", opcode));
+                }
+                let addr = pop(&mut stack)? as usize;
+                let mut raw = [0; 8];
+                let value = context
+                    .target
+                    .read_bytes(addr, size as usize)
+                    .map_err(|_| format!("Cannot access memory at address {:#x}", addr))?;
+                raw[..value.len().min(8)].copy_from_slice(&value[..value.len().min(8)]);
+                stack.push(u64::from_le_bytes(raw));
+            }
+            Operation::Pick { index } => {
+                let index = index as usize;
+                if index >= stack.len() {
+                    return Err("Location expression picks past the bottom of the stack".into());
+                }
+                stack.push(stack[stack.len() - 1 - index]);
+            }
+            Operation::Drop => {
+                pop(&mut stack)?;
+            }
+            Operation::Swap => {
+                let top = pop(&mut stack)?;
+                let second = pop(&mut stack)?;
+                stack.push(top);
+                stack.push(second);
+            }
+            Operation::Rot => {
+                let top = pop(&mut stack)?;
+                let second = pop(&mut stack)?;
+                let third = pop(&mut stack)?;
+                stack.push(top);
+                stack.push(third);
+                stack.push(second);
+            }
+            Operation::Abs => {
+                let value = pop(&mut stack)? as i64;
+                stack.push(value.wrapping_abs() as u64);
+            }
+            Operation::Neg => {
+                let value = pop(&mut stack)? as i64;
+                stack.push(value.wrapping_neg() as u64);
+            }
+            Operation::Not => {
+                let value = pop(&mut stack)?;
+                stack.push(!value);
+            }
+            Operation::PlusConstant { value } => {
+                let top = pop(&mut stack)?;
+                stack.push(top.wrapping_add(value));
+            }
+            Operation::Plus
+            | Operation::Minus
+            | Operation::Mul
+            | Operation::Div
+            | Operation::Mod
+            | Operation::And
+            | Operation::Or
+            | Operation::Xor
+            | Operation::Shl
+            | Operation::Shr
+            | Operation::Shra
+            | Operation::Eq
+            | Operation::Ne
+            | Operation::Lt
+            | Operation::Le
+            | Operation::Gt
+            | Operation::Ge => {
+                let right = pop(&mut stack)?;
+                let left = pop(&mut stack)?;
+                stack.push(binary_operation(&op, left, right)?);
+            }
+            Operation::Bra { target } | Operation::Skip { target } => {
+                let taken = match op {
+                    Operation::Bra { .. } => pop(&mut stack)? != 0,
+                    _ => true,
+                };
+                if taken {
+                    let next = (bytes.len() - pc.len()) as isize + target as isize;
+                    if next < 0 || next as usize > bytes.len() {
+                        return Err("Location expression branches out of bounds".to_string());
+                    }
+                    pc = EndianSlice::new(&bytes[next as usize..], LittleEndian);
+                }
+            }
+            Operation::Nop => (),
+            Operation::StackValue => location = Some(PieceLocation::Value(pop(&mut stack)?)),
+            Operation::ImplicitValue { data } => {
+                location = Some(PieceLocation::Bytes(data.slice().to_vec()))
+            }
+            Operation::Piece {
+                size_in_bits,
+                bit_offset,
+            } => {
+                if bit_offset.is_some() || size_in_bits % 8 != 0 {
+                    return Err("Bit pieces are not supported".to_string());
+                }
+                // an empty location means that this piece was optimized out
+                let location = match location.take() {
+                    Some(location) => location,
+                    None => match stack.pop() {
+                        Some(addr) => PieceLocation::Memory(addr as usize),
+                        None => PieceLocation::OptimizedOut,
+                    },
+                };
+                pieces.push(Piece {
+                    location,
+                    size: Some((size_in_bits / 8) as usize),
+                });
+            }
+            // the value at function entry can only be recovered from the caller, if at all
+            Operation::EntryValue { .. }
+            | Operation::ParameterRef { .. }
+            | Operation::ImplicitPointer { .. } => return optimized_out(),
+            _ => return Err(format!("Unsupported {} in location expression", opcode)),
+        }
+    }
+    match location.or_else(|| stack.pop().map(|addr| PieceLocation::Memory(addr as usize))) {
+        Some(location) => pieces.push(Piece {
+            location,
+            size: None,
+        }),
+        None if pieces.is_empty() => return optimized_out(),
+        None => (),
+    }
+    Ok(pieces)
+}
+
+/// Apply an arithmetic, logical or comparison operation to the two values on top of the stack.
+/// Comparisons and divisions are signed.
+fn binary_operation(
+    op: &Operation<EndianSlice<LittleEndian>>,
+    left: u64,
+    right: u64,
+) -> Result<u64, String> {
+    let (signed_left, signed_right) = (left as i64, right as i64);
+    Ok(match op {
+        Operation::Plus => left.wrapping_add(right),
+        Operation::Minus => left.wrapping_sub(right),
+        Operation::Mul => left.wrapping_mul(right),
+        Operation::Div | Operation::Mod if right == 0 => {
+            return Err("Division by zero in location expression".to_string())
+        }
+        Operation::Div => signed_left.wrapping_div(signed_right) as u64,
+        Operation::Mod => left % right,
+        Operation::And => left & right,
+        Operation::Or => left | right,
+        Operation::Xor => left ^ right,
+        Operation::Shl => left.checked_shl(right as u32).unwrap_or(0),
+        Operation::Shr => left.checked_shr(right as u32).unwrap_or(0),
+        Operation::Shra => (signed_left >> right.min(63)) as u64,
+        Operation::Eq => (left == right) as u64,
+        Operation::Ne => (left != right) as u64,
+        Operation::Lt => (signed_left < signed_right) as u64,
+        Operation::Le => (signed_left <= signed_right) as u64,
+        Operation::Gt => (signed_left > signed_right) as u64,
+        Operation::Ge => (signed_left >= signed_right) as u64,
+        _ => unreachable!(),
+    })
+}
@@ -1,24 +1,115 @@
+#[macro_use]
+mod interpreter;
+
+mod core_dump;
+mod dap;
 mod debugger;
 mod debugger_command;
+mod disassembler;
 mod dwarf_data;
+mod expression;
 mod gimli_wrapper;
 mod inferior;
+mod json;
+mod location;
+#[cfg(test)]
+mod samples;
+mod shared_library;
+mod signals;
+mod target;
+mod unwind;
 
 use crate::debugger::Debugger;
 use nix::sys::signal::{signal, SigHandler, Signal};
-use std::env;
+use nix::unistd::Pid;
+use std::{env, fs};
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <target program>", args[0]);
-        std::process::exit(1);
+    let mut args: Vec<String> = env::args().collect();
+    // -x <command file>, -batch, --interpreter=json and --dap may come anywhere
+    let mut scripts = Vec::new();
+    let mut batch = false;
+    let mut dap = None;
+    let mut index = 1;
+    while index < args.len() {
+        match args[index].as_str() {
+            "-x" if index + 1 < args.len() => {
+                scripts.push(args.remove(index + 1));
+                args.remove(index);
+            }
+            "-batch" | "--batch" => {
+                batch = true;
+                args.remove(index);
+            }
+            "--interpreter=json" => {
+                interpreter::set_mode(interpreter::Mode::Json);
+                args.remove(index);
+            }
+            "--dap" => {
+                dap = Some(None);
+                args.remove(index);
+            }
+            arg if arg.starts_with("--dap=") => {
+                let port = arg["--dap=".len()..].parse().unwrap_or_else(|_| {
+                    println!("Invalid port {}", &arg["--dap=".len()..]);
+                    std::process::exit(1);
+                });
+                dap = Some(Some(port));
+                args.remove(index);
+            }
+            _ => index += 1,
+        }
+    }
+    // the program to debug comes from the launch or attach request of the client
+    if let Some(port) = dap {
+        dap::serve(port);
+        return;
     }
-    let target = &args[1];
+    let (target, pid, core) = match (args.len(), args.get(1).map(String::as_str)) {
+        (2, _) => (args[1].clone(), None, None),
+        (4, _) if args[2] == "--core" => (args[1].clone(), None, Some(args[3].clone())),
+        (3, Some("-p")) => {
+            let pid = args[2].parse().unwrap_or_else(|_| {
+                println!("Invalid pid {}", args[2]);
+                std::process::exit(1);
+            });
+            // load the debugging symbols of the executable the process is running
+            let target = fs::read_link(format!("/proc/{}/exe", pid)).unwrap_or_else(|err| {
+                println!("Could not find the executable of process {}: {}", pid, err);
+                std::process::exit(1);
+            });
+            (
+                target.to_string_lossy().to_string(),
+                Some(Pid::from_raw(pid)),
+                None,
+            )
+        }
+        _ => {
+            println!("Usage: {} [options] <target program>", args[0]);
+            println!("       {} [options] -p <pid>", args[0]);
+            println!("       {} [options] <target program> --core <core file>", args[0]);
+            println!("Options:");
+            println!("  -x <command file>  run the commands of the file first");
+            println!("  -batch             read commands from stdin instead of the terminal");
+            println!("  --interpreter=json talk to a frontend with one JSON record per line");
+            println!("  --dap[=<port>]     serve the Debug Adapter Protocol on stdio or a TCP port");
+            std::process::exit(1);
+        }
+    };
 
     // Disable handling of ctrl+c in this process (so that ctrl+c only gets delivered to child
     // processes)
     unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
 
-    Debugger::new(target).run();
+    let mut debugger = Debugger::new(&target, pid, core.as_deref()).unwrap_or_else(|err| {
+        console!("{}", err);
+        std::process::exit(1);
+    });
+    if batch {
+        debugger.set_batch();
+    }
+    for script in &scripts {
+        debugger.queue_script(script);
+    }
+    debugger.run();
 }
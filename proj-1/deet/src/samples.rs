@@ -0,0 +1,18 @@
+//! The sample programs of the samples directory, built for the tests that run deet on them
+
+use std::process::Command;
+
+/// Build a sample program like the Makefile does, out of the source tree. Returns the paths of
+/// the program and of its source.
+pub fn build_sample(name: &str) -> (String, String) {
+    let source = format!("{}/samples/{}.c", env!("CARGO_MANIFEST_DIR"), name);
+    let program = std::env::temp_dir().join(format!("deet-{}-{}", name, std::process::id()));
+    let status = Command::new("cc")
+        .args(["-O0", "-g", "-fno-omit-frame-pointer", "-o"])
+        .arg(&program)
+        .arg(&source)
+        .status()
+        .expect("cannot run cc");
+    assert!(status.success(), "cannot build {}", source);
+    (program.to_string_lossy().to_string(), source)
+}
@@ -0,0 +1,88 @@
+//! Finding the shared libraries of a dynamically linked program through the r_debug structure
+//! of the dynamic linker, like gdb does. The dynamic linker lists the libraries it loaded in
+//! r_debug.r_map, and calls the empty function at r_debug.r_brk before and after it changes the
+//! list, so that a debugger can set a breakpoint there.
+
+use crate::dwarf_data::DwarfData;
+use crate::target::Target;
+
+/// Key of the entry point of the program in the auxiliary vector
+pub const AT_ENTRY: usize = 9;
+
+const DT_NULL: usize = 0;
+const DT_DEBUG: usize = 21;
+
+/// Value of r_debug.r_state when the list of libraries is not being changed
+const RT_CONSISTENT: usize = 0;
+
+/// Offsets of r_map, r_brk and r_state in struct r_debug
+const R_MAP_OFFSET: usize = 8;
+const R_BRK_OFFSET: usize = 16;
+const R_STATE_OFFSET: usize = 24;
+
+/// Offsets of l_addr, l_name and l_next in struct link_map
+const L_ADDR_OFFSET: usize = 0;
+const L_NAME_OFFSET: usize = 8;
+const L_NEXT_OFFSET: usize = 24;
+
+/// Upper bound on the number of libraries, in case the list is corrupted into a cycle
+const MAX_LIBRARIES: usize = 4096;
+
+/// The address of the r_debug structure of the dynamic linker, which it publishes in the
+/// DT_DEBUG entry of the .dynamic section of the program. Returns None if the program is
+/// statically linked or the dynamic linker has not run yet.
+pub fn find_r_debug(target: &dyn Target, debug_data: &DwarfData) -> Option<usize> {
+    let mut entry = debug_data.dynamic_section()?;
+    loop {
+        let tag = target.read_word(entry).ok()?;
+        let value = target.read_word(entry + 8).ok()?;
+        match tag {
+            DT_NULL => return None,
+            DT_DEBUG if value != 0 => return Some(value),
+            DT_DEBUG => return None,
+            _ => entry += 16,
+        }
+    }
+}
+
+/// Read a NUL-terminated string from the memory of the program
+fn read_string(target: &dyn Target, mut addr: usize) -> Option<String> {
+    let mut bytes = Vec::new();
+    loop {
+        let word = target.read_word(addr).ok()?.to_le_bytes();
+        match word.iter().position(|byte| *byte == 0) {
+            Some(end) => {
+                bytes.extend_from_slice(&word[..end]);
+                return Some(String::from_utf8_lossy(&bytes).into_owned());
+            }
+            None => bytes.extend_from_slice(&word),
+        }
+        addr += word.len();
+    }
+}
+
+/// The address of the function the dynamic linker calls around changes to the list of
+/// libraries
+pub fn breakpoint_address(target: &dyn Target, r_debug: usize) -> Option<usize> {
+    target.read_word(r_debug + R_BRK_OFFSET).ok()
+}
+
+/// The path and load bias of every shared library listed by r_debug. Returns None while the
+/// dynamic linker is changing the list.
+pub fn read_libraries(target: &dyn Target, r_debug: usize) -> Option<Vec<(String, usize)>> {
+    if target.read_word(r_debug + R_STATE_OFFSET).ok()? as u32 as usize != RT_CONSISTENT {
+        return None;
+    }
+    let mut libraries = Vec::new();
+    let mut link_map = target.read_word(r_debug + R_MAP_OFFSET).ok()?;
+    while link_map != 0 && libraries.len() < MAX_LIBRARIES {
+        let bias = target.read_word(link_map + L_ADDR_OFFSET).ok()?;
+        let name = read_string(target, target.read_word(link_map + L_NAME_OFFSET).ok()?)?;
+        // the program itself and the vDSO have no file name
+        if name.starts_with('/') {
+            libraries.push((name, bias));
+        }
+        link_map = target.read_word(link_map + L_NEXT_OFFSET).ok()?;
+    }
+    Some(libraries)
+}
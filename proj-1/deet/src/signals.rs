@@ -0,0 +1,155 @@
+use nix::sys::signal::Signal;
+use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::str::FromStr;
+
+/// What to do when the inferior receives a signal
+#[derive(Clone, Copy)]
+pub struct SignalPolicy {
+    /// Stop the inferior and return to the prompt
+    pub stop: bool,
+    /// Tell the user that the signal was received
+    pub print: bool,
+    /// Deliver the signal to the inferior when it is resumed
+    pub pass: bool,
+}
+
+/// The signal handling policy set by the handle command, listed by info signals
+pub struct SignalTable {
+    policies: Vec<(Signal, SignalPolicy)>,
+}
+
+/// Parse a signal given by name (SIGUSR1) or number (10)
+pub fn parse_signal(name: &str) -> Option<Signal> {
+    match name.parse::<i32>() {
+        Ok(number) => Signal::try_from(number).ok(),
+        Err(_) => Signal::from_str(&name.to_uppercase()).ok(),
+    }
+}
+
+/// The description of a signal, e.g. "Segmentation fault" for SIGSEGV
+pub fn describe_signal(signal: Signal) -> String {
+    let description = unsafe { libc::strsignal(signal as libc::c_int) };
+    if description.is_null() {
+        return "Unknown signal".to_string();
+    }
+    unsafe { CStr::from_ptr(description) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+impl SignalTable {
+    /// The default policy: stop at and pass every signal, except that SIGINT and SIGTRAP are
+    /// meant for the debugger, and that signals used in normal operation are passed silently
+    pub fn new() -> SignalTable {
+        let policies = Signal::iterator()
+            .map(|signal| {
+                let policy = match signal {
+                    Signal::SIGINT | Signal::SIGTRAP => SignalPolicy {
+                        stop: true,
+                        print: true,
+                        pass: false,
+                    },
+                    Signal::SIGALRM
+                    | Signal::SIGURG
+                    | Signal::SIGCHLD
+                    | Signal::SIGWINCH
+                    | Signal::SIGIO
+                    | Signal::SIGVTALRM
+                    | Signal::SIGPROF => SignalPolicy {
+                        stop: false,
+                        print: false,
+                        pass: true,
+                    },
+                    _ => SignalPolicy {
+                        stop: true,
+                        print: true,
+                        pass: true,
+                    },
+                };
+                (signal, policy)
+            })
+            .collect();
+        SignalTable { policies }
+    }
+
+    pub fn policy(&self, signal: Signal) -> SignalPolicy {
+        self.policies
+            .iter()
+            .find(|(other, _)| *other == signal)
+            .map(|(_, policy)| *policy)
+            .unwrap_or(SignalPolicy {
+                stop: true,
+                print: true,
+                pass: true,
+            })
+    }
+
+    /// Change the policy of a signal with one of the keywords of the handle command. Returns
+    /// false for an unknown keyword.
+    pub fn handle(&mut self, signal: Signal, keyword: &str) -> bool {
+        let policy = match self.policies.iter_mut().find(|(other, _)| *other == signal) {
+            Some((_, policy)) => policy,
+            None => return false,
+        };
+        match keyword {
+            // stopping without telling the user would be confusing, and vice versa
+            "stop" => {
+                policy.stop = true;
+                policy.print = true;
+            }
+            "nostop" => policy.stop = false,
+            "print" => policy.print = true,
+            "noprint" => {
+                policy.print = false;
+                policy.stop = false;
+            }
+            "pass" | "noignore" => policy.pass = true,
+            "nopass" | "ignore" => policy.pass = false,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Print the policy of the given signals, or of all signals
+    pub fn print(&self, signals: &[Signal]) {
+        console!("Signal        Stop\tPrint\tPass to program\tDescription");
+        for (signal, policy) in &self.policies {
+            if !signals.is_empty() && !signals.contains(signal) {
+                continue;
+            }
+            let yes_no = |flag: bool| if flag { "Yes" } else { "No" };
+            console!(
+                "{:<14}{}\t{}\t{}\t\t{}",
+                signal.as_ref(),
+                yes_no(policy.stop),
+                yes_no(policy.print),
+                yes_no(policy.pass),
+                describe_signal(*signal)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signal_names() {
+        assert_eq!(parse_signal("SIGUSR1"), Some(Signal::SIGUSR1));
+        assert_eq!(parse_signal("sigsegv"), Some(Signal::SIGSEGV));
+        assert_eq!(parse_signal("SIGFOO"), None);
+        assert_eq!(parse_signal("USR1"), None);
+        assert_eq!(parse_signal(""), None);
+    }
+
+    #[test]
+    fn signal_numbers() {
+        assert_eq!(parse_signal("2"), Some(Signal::SIGINT));
+        assert_eq!(parse_signal("10"), Some(Signal::SIGUSR1));
+        assert_eq!(parse_signal("0"), None);
+        assert_eq!(parse_signal("-9"), None);
+        assert_eq!(parse_signal("65"), None);
+    }
+}
@@ -0,0 +1,76 @@
+use crate::dwarf_data::DwarfData;
+use crate::inferior::describe_frame;
+use crate::unwind::{Frame, Unwinder};
+use std::cell::RefMut;
+
+/// The registers and memory of the program being debugged, which come either from a live
+/// inferior or from a core dump
+pub trait Target {
+    /// Returns the register set of the current thread
+    fn get_registers(&self) -> Result<libc::user_regs_struct, nix::Error>;
+
+    /// Read len bytes starting at addr
+    fn read_bytes(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error>;
+
+    /// The unwinder for the files mapped into the address space of the program, which keeps the
+    /// call frame information it loads from one stop to the next
+    fn unwinder(&self) -> RefMut<'_, Unwinder>;
+
+    /// The auxiliary vector the kernel passed to the program, as raw bytes
+    fn auxv(&self) -> Vec<u8>;
+
+    /// Look up an entry of the auxiliary vector, e.g. AT_ENTRY
+    fn aux_value(&self, key: usize) -> Option<usize> {
+        let auxv = self.auxv();
+        auxv.chunks_exact(16)
+            .map(|entry| {
+                let mut key = [0; 8];
+                let mut value = [0; 8];
+                key.copy_from_slice(&entry[..8]);
+                value.copy_from_slice(&entry[8..]);
+                (usize::from_le_bytes(key), usize::from_le_bytes(value))
+            })
+            .find(|(entry_key, _)| *entry_key == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Read a word starting at addr
+    fn read_word(&self, addr: usize) -> Result<usize, nix::Error> {
+        let bytes = self.read_bytes(addr, std::mem::size_of::<usize>())?;
+        let mut word = [0; std::mem::size_of::<usize>()];
+        word.copy_from_slice(&bytes);
+        Ok(usize::from_le_bytes(word))
+    }
+
+    /// The innermost frame of the current thread
+    fn current_frame(&self) -> Result<Frame, nix::Error> {
+        let regs = self.get_registers()?;
+        Ok(self
+            .unwinder()
+            .innermost_frame(&regs, |addr| self.read_word(addr).ok()))
+    }
+
+    /// Unwind the call stack of the current thread from the innermost frame up to main
+    fn backtrace(&self, debug_data: &DwarfData) -> Result<Vec<Frame>, nix::Error> {
+        let regs = self.get_registers()?;
+        let mut unwinder = self.unwinder();
+        let mut frames = unwinder.backtrace(&regs, |addr| self.read_word(addr).ok());
+        if let Some(main) = frames.iter().position(|frame| {
+            debug_data
+                .get_function_from_addr(frame.lookup_pc())
+                .as_deref()
+                == Some("main")
+        }) {
+            frames.truncate(main + 1);
+        }
+        Ok(frames)
+    }
+
+    /// Print backtrace of current status till main
+    fn print_backtrace(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
+        for (level, frame) in self.backtrace(debug_data)?.iter().enumerate() {
+            console!("#{:<3}{}", level, describe_frame(frame, debug_data));
+        }
+        Ok(())
+    }
+}
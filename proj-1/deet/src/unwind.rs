@@ -0,0 +1,514 @@
+use gimli::{
+    BaseAddresses, CfaRule, DebugFrame, EhFrame, EndianSlice, EvaluationResult, Expression,
+    LittleEndian, Location, Piece, Register, RegisterRule, UninitializedUnwindContext,
+    UnwindSection, UnwindTableRow, Value, X86_64,
+};
+use nix::unistd::Pid;
+use object::{Object, ObjectSection, ObjectSegment};
+use std::collections::HashMap;
+use std::fs;
+
+/// Number of DWARF registers tracked while unwinding: rax to r15, plus the return address
+pub const REGISTER_COUNT: usize = 17;
+
+/// Upper bound on the number of frames, in case the stack is corrupted into a cycle
+const MAX_FRAMES: usize = 1024;
+
+/// How the DWARF expressions of the call frame information are encoded on x86-64
+const ENCODING: gimli::Encoding = gimli::Encoding {
+    format: gimli::Format::Dwarf32,
+    version: 4,
+    address_size: 8,
+};
+
+/// Registers that a callee must preserve according to the System V x86-64 ABI
+const CALLEE_SAVED: [Register; 6] = [
+    X86_64::RBX,
+    X86_64::RBP,
+    X86_64::R12,
+    X86_64::R13,
+    X86_64::R14,
+    X86_64::R15,
+];
+
+/// A frame on the call stack of the inferior
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// The address being executed in this frame. For every frame but the innermost one, this is
+    /// the return address of the call into the next frame.
+    pub pc: usize,
+    /// The canonical frame address, i.e. the value of rsp before the call that created this
+    /// frame, or None if there is no call frame information for pc
+    pub cfa: Option<usize>,
+    /// Values of the registers in this frame, indexed by DWARF register number
+    registers: [Option<usize>; REGISTER_COUNT],
+    innermost: bool,
+}
+
+impl Frame {
+    /// The address to use when looking up the function and line of this frame. The return
+    /// address of a caller frame may already belong to the next line, or even to the next
+    /// function when the call is the last instruction of a noreturn function.
+    pub fn lookup_pc(&self) -> usize {
+        if self.innermost {
+            self.pc
+        } else {
+            self.pc - 1
+        }
+    }
+
+    /// The value of a register, by DWARF register number, or None if it was not saved by the
+    /// callee of this frame
+    pub fn register(&self, number: u16) -> Option<usize> {
+        self.registers.get(number as usize).cloned().flatten()
+    }
+}
+
+/// How to recover a register of the caller from the frame of its callee
+#[derive(Clone)]
+enum Rule {
+    Undefined,
+    SameValue,
+    /// Saved at the given offset from the canonical frame address
+    Offset(i64),
+    /// The canonical frame address plus the given offset
+    ValOffset(i64),
+    /// Saved in another register
+    Register(Register),
+    /// Saved at the address computed by a DWARF expression, which starts with the canonical
+    /// frame address on its stack
+    Expression(Vec<u8>),
+    /// The value computed by a DWARF expression, which starts with the canonical frame address
+    /// on its stack
+    ValExpression(Vec<u8>),
+    /// An architecture specific rule
+    Unsupported,
+}
+
+/// How to compute the canonical frame address of a frame
+enum Cfa {
+    RegisterAndOffset(Register, i64),
+    /// Computed by a DWARF expression, e.g. in PLT entries and signal trampolines
+    Expression(Vec<u8>),
+}
+
+/// The rules for unwinding one frame, taken from a row of the call frame information table
+struct FrameRules {
+    cfa: Cfa,
+    registers: Vec<Rule>,
+}
+
+/// Copy a DWARF expression out of the call frame information
+fn expression_bytes<R: gimli::Reader>(expression: &Expression<R>) -> Option<Vec<u8>> {
+    Some(expression.0.to_slice().ok()?.to_vec())
+}
+
+/// Evaluate a DWARF expression of the call frame information in a frame with the given register
+/// values, pushing initial_value first if there is one. Returns the value the expression
+/// computes, e.g. an address.
+fn evaluate<F>(
+    bytes: &[u8],
+    initial_value: Option<usize>,
+    registers: &[Option<usize>],
+    read_word: F,
+) -> Option<usize>
+where
+    F: Fn(usize) -> Option<usize>,
+{
+    let mut evaluation = Expression(EndianSlice::new(bytes, LittleEndian)).evaluation(ENCODING);
+    if let Some(value) = initial_value {
+        evaluation.set_initial_value(value as u64);
+    }
+    let mut result = evaluation.evaluate().ok()?;
+    loop {
+        result = match result {
+            EvaluationResult::Complete => break,
+            EvaluationResult::RequiresRegister { register, .. } => {
+                let value = registers.get(register.0 as usize).cloned().flatten()?;
+                evaluation
+                    .resume_with_register(Value::Generic(value as u64))
+                    .ok()?
+            }
+            EvaluationResult::RequiresMemory { address, size, .. } => {
+                let mut value = read_word(address as usize)? as u64;
+                if size < 8 {
+                    value &= (1 << (size * 8)) - 1;
+                }
+                evaluation.resume_with_memory(Value::Generic(value)).ok()?
+            }
+            _ => return None,
+        };
+    }
+    match evaluation.result()[..] {
+        [Piece {
+            location: Location::Address { address },
+            ..
+        }] => Some(address as usize),
+        [Piece {
+            location: Location::Value { value },
+            ..
+        }] => Some(value.to_u64(!0).ok()? as usize),
+        _ => None,
+    }
+}
+
+impl FrameRules {
+    fn from_row<R: gimli::Reader>(row: &UnwindTableRow<R>) -> Option<FrameRules> {
+        let cfa = match row.cfa() {
+            CfaRule::RegisterAndOffset { register, offset } => {
+                Cfa::RegisterAndOffset(*register, *offset)
+            }
+            CfaRule::Expression(expression) => Cfa::Expression(expression_bytes(expression)?),
+        };
+        let mut registers = Vec::with_capacity(REGISTER_COUNT);
+        for index in 0..REGISTER_COUNT {
+            registers.push(match row.register(Register(index as u16)) {
+                RegisterRule::Undefined => Rule::Undefined,
+                RegisterRule::SameValue => Rule::SameValue,
+                RegisterRule::Offset(offset) => Rule::Offset(offset),
+                RegisterRule::ValOffset(offset) => Rule::ValOffset(offset),
+                RegisterRule::Register(register) => Rule::Register(register),
+                RegisterRule::Expression(expression) => {
+                    Rule::Expression(expression_bytes(&expression)?)
+                }
+                RegisterRule::ValExpression(expression) => {
+                    Rule::ValExpression(expression_bytes(&expression)?)
+                }
+                _ => Rule::Unsupported,
+            });
+        }
+        Some(FrameRules { cfa, registers })
+    }
+
+    /// The canonical frame address of a frame with the given register values
+    fn cfa<F>(&self, registers: &[Option<usize>], read_word: F) -> Option<usize>
+    where
+        F: Fn(usize) -> Option<usize>,
+    {
+        match &self.cfa {
+            Cfa::RegisterAndOffset(register, offset) => registers
+                .get(register.0 as usize)
+                .cloned()
+                .flatten()
+                .map(|value| (value as i64 + offset) as usize),
+            Cfa::Expression(bytes) => evaluate(bytes, None, registers, read_word),
+        }
+    }
+}
+
+struct Section {
+    address: u64,
+    data: Vec<u8>,
+}
+
+/// The call frame information of an ELF file mapped into the inferior
+struct UnwindInfo {
+    /// Difference between the addresses in memory and the addresses in the ELF file
+    bias: usize,
+    endian: gimli::RunTimeEndian,
+    text_address: Option<u64>,
+    eh_frame: Option<Section>,
+    debug_frame: Option<Section>,
+}
+
+impl UnwindInfo {
+    fn load(mapping: &Mapping) -> Option<UnwindInfo> {
+        let file = fs::File::open(&mapping.path).ok()?;
+        let mmap = unsafe { memmap::Mmap::map(&file).ok()? };
+        let object = object::File::parse(&mmap).ok()?;
+        let endian = if object.is_little_endian() {
+            gimli::RunTimeEndian::Little
+        } else {
+            gimli::RunTimeEndian::Big
+        };
+        let load_section = |name: &str| -> Option<Section> {
+            Some(Section {
+                address: object.section_by_name(name)?.address(),
+                data: object.section_data_by_name(name)?.to_vec(),
+            })
+        };
+        // the file offset 0 is mapped at the lowest loadable segment
+        let lowest_segment = object.segments().map(|segment| segment.address()).min()? as usize;
+        Some(UnwindInfo {
+            bias: mapping.base.wrapping_sub(lowest_segment & !0xfff),
+            endian,
+            text_address: object.section_by_name(".text").map(|text| text.address()),
+            eh_frame: load_section(".eh_frame"),
+            debug_frame: load_section(".debug_frame"),
+        })
+    }
+
+    /// Find the unwinding rules at an address in memory, preferring .eh_frame over .debug_frame
+    fn rules_at(&self, addr: usize) -> Option<FrameRules> {
+        let address = addr.wrapping_sub(self.bias) as u64;
+        let mut bases = BaseAddresses::default();
+        if let Some(text_address) = self.text_address {
+            bases = bases.set_text(text_address);
+        }
+        let mut ctx = UninitializedUnwindContext::new();
+        if let Some(section) = &self.eh_frame {
+            let eh_frame = EhFrame::new(&section.data, self.endian);
+            let bases = bases.clone().set_eh_frame(section.address);
+            let row = eh_frame.unwind_info_for_address(
+                &bases,
+                &mut ctx,
+                address,
+                EhFrame::cie_from_offset,
+            );
+            if let Ok(row) = row {
+                return FrameRules::from_row(&row);
+            }
+        }
+        let section = self.debug_frame.as_ref()?;
+        let debug_frame = DebugFrame::new(&section.data, self.endian);
+        let row = debug_frame
+            .unwind_info_for_address(&bases, &mut ctx, address, DebugFrame::cie_from_offset)
+            .ok()?;
+        FrameRules::from_row(&row)
+    }
+}
+
+/// An ELF file mapped into the address space of the inferior
+struct Mapping {
+    path: String,
+    start: usize,
+    end: usize,
+    /// Address at which offset 0 of the file is mapped
+    base: usize,
+}
+
+/// A region of a file mapped into memory, as listed by /proc/<pid>/maps or by a core dump
+pub struct FileMapping {
+    pub path: String,
+    pub start: usize,
+    pub end: usize,
+    /// Offset in the file of the start of the region
+    pub offset: usize,
+}
+
+fn read_mappings(pid: Pid) -> Vec<FileMapping> {
+    let maps = fs::read_to_string(format!("/proc/{}/maps", pid)).unwrap_or_default();
+    let mut mappings = Vec::new();
+    for line in maps.lines() {
+        // start-end perms offset dev inode path
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 6 || !fields[5].starts_with('/') {
+            continue;
+        }
+        let mut range = fields[0].split('-');
+        if let (Some(start), Some(end), Some(offset)) = (
+            range.next().and_then(|s| usize::from_str_radix(s, 16).ok()),
+            range.next().and_then(|s| usize::from_str_radix(s, 16).ok()),
+            usize::from_str_radix(fields[2], 16).ok(),
+        ) {
+            mappings.push(FileMapping {
+                path: fields[5].to_string(),
+                start,
+                end,
+                offset,
+            });
+        }
+    }
+    mappings
+}
+
+/// Merge the regions mapped from each file into a single mapping
+fn merge_mappings(regions: &[FileMapping]) -> Vec<Mapping> {
+    let mut mappings: Vec<Mapping> = Vec::new();
+    for region in regions {
+        let (start, end, offset) = (region.start, region.end, region.offset);
+        match mappings
+            .iter_mut()
+            .find(|mapping| mapping.path == region.path)
+        {
+            Some(mapping) => {
+                mapping.start = mapping.start.min(start);
+                mapping.end = mapping.end.max(end);
+                if offset == 0 {
+                    mapping.base = mapping.base.min(start);
+                }
+            }
+            None => mappings.push(Mapping {
+                path: region.path.clone(),
+                start,
+                end,
+                base: if offset == 0 { start } else { start - offset },
+            }),
+        }
+    }
+    mappings
+}
+
+/// Unwinds the stack of the inferior using the DWARF call frame information of the target and
+/// of the shared libraries it has loaded
+pub struct Unwinder {
+    mappings: Vec<Mapping>,
+    /// Call frame information of the mappings by path and base address, loaded the first time a
+    /// frame falls into them
+    unwind_info: HashMap<(String, usize), Option<UnwindInfo>>,
+}
+
+impl Unwinder {
+    pub fn new(pid: Pid) -> Unwinder {
+        Unwinder::with_files(&read_mappings(pid))
+    }
+
+    /// An unwinder for an address space made of the given file mappings
+    pub fn with_files(regions: &[FileMapping]) -> Unwinder {
+        Unwinder {
+            mappings: merge_mappings(regions),
+            unwind_info: HashMap::new(),
+        }
+    }
+
+    /// Read the mappings of the inferior again after the dynamic linker loaded or unloaded
+    /// libraries, keeping the call frame information of the files that stayed in place
+    pub fn update(&mut self, pid: Pid) {
+        self.mappings = merge_mappings(&read_mappings(pid));
+        let mappings = &self.mappings;
+        self.unwind_info.retain(|(path, base), _| {
+            mappings
+                .iter()
+                .any(|mapping| mapping.path == *path && mapping.base == *base)
+        });
+    }
+
+    fn rules_at(&mut self, addr: usize) -> Option<FrameRules> {
+        let mapping = self
+            .mappings
+            .iter()
+            .find(|mapping| mapping.start <= addr && addr < mapping.end)?;
+        self.unwind_info
+            .entry((mapping.path.clone(), mapping.base))
+            .or_insert_with(|| UnwindInfo::load(mapping))
+            .as_ref()?
+            .rules_at(addr)
+    }
+
+    fn make_frame<F>(
+        &mut self,
+        pc: usize,
+        registers: [Option<usize>; REGISTER_COUNT],
+        innermost: bool,
+        read_word: F,
+    ) -> Frame
+    where
+        F: Fn(usize) -> Option<usize>,
+    {
+        let mut frame = Frame {
+            pc,
+            cfa: None,
+            registers,
+            innermost,
+        };
+        if let Some(rules) = self.rules_at(frame.lookup_pc()) {
+            frame.cfa = rules.cfa(&registers, read_word);
+        }
+        frame
+    }
+
+    /// The innermost frame, whose registers are the current registers of the inferior. read_word
+    /// reads a word from the memory of the inferior.
+    pub fn innermost_frame<F>(&mut self, regs: &libc::user_regs_struct, read_word: F) -> Frame
+    where
+        F: Fn(usize) -> Option<usize>,
+    {
+        let values = [
+            regs.rax, regs.rdx, regs.rcx, regs.rbx, regs.rsi, regs.rdi, regs.rbp, regs.rsp,
+            regs.r8, regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15, regs.rip,
+        ];
+        let mut registers = [None; REGISTER_COUNT];
+        for (register, value) in registers.iter_mut().zip(values.iter()) {
+            *register = Some(*value as usize);
+        }
+        self.make_frame(regs.rip as usize, registers, true, read_word)
+    }
+
+    /// Unwind the stack starting from the given registers of the innermost frame. read_word
+    /// reads a word from the memory of the inferior. Unwinding stops at the outermost frame, or
+    /// at the first frame that has no usable call frame information.
+    pub fn backtrace<F>(&mut self, regs: &libc::user_regs_struct, read_word: F) -> Vec<Frame>
+    where
+        F: Fn(usize) -> Option<usize>,
+    {
+        let mut frames = vec![self.innermost_frame(regs, &read_word)];
+        while frames.len() < MAX_FRAMES {
+            let frame = frames.last().unwrap();
+            let cfa = match frame.cfa {
+                Some(cfa) => cfa,
+                None => break,
+            };
+            let rules = match self.rules_at(frame.lookup_pc()) {
+                Some(rules) => rules,
+                None => break,
+            };
+            let mut registers = [None; REGISTER_COUNT];
+            for (index, register) in registers.iter_mut().enumerate() {
+                *register = match &rules.registers[index] {
+                    Rule::Undefined if CALLEE_SAVED.contains(&Register(index as u16)) => {
+                        frame.registers[index]
+                    }
+                    Rule::Undefined | Rule::Unsupported => None,
+                    Rule::SameValue => frame.registers[index],
+                    Rule::Offset(offset) => read_word((cfa as i64 + offset) as usize),
+                    Rule::ValOffset(offset) => Some((cfa as i64 + offset) as usize),
+                    Rule::Register(other) => {
+                        frame.registers.get(other.0 as usize).cloned().flatten()
+                    }
+                    Rule::Expression(bytes) => {
+                        evaluate(bytes, Some(cfa), &frame.registers, &read_word)
+                            .and_then(&read_word)
+                    }
+                    Rule::ValExpression(bytes) => {
+                        evaluate(bytes, Some(cfa), &frame.registers, &read_word)
+                    }
+                };
+            }
+            // the caller's stack pointer is the canonical frame address of the callee
+            registers[X86_64::RSP.0 as usize] = Some(cfa);
+            // an undefined return address marks the outermost frame
+            let return_addr = match registers[X86_64::RA.0 as usize] {
+                Some(addr) if addr != 0 => addr,
+                _ => break,
+            };
+            // the stack grows down, so a caller's frame must be above its callee's
+            let caller = self.make_frame(return_addr, registers, false, &read_word);
+            if caller.cfa.is_some_and(|caller_cfa| caller_cfa <= cfa) {
+                break;
+            }
+            frames.push(caller);
+        }
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plt_cfa() {
+        // rsp + 8 + ((rip & 15) >= 11) * 8, the CFA of the entries of .plt
+        let bytes = [
+            0x77, 0x08, 0x80, 0x00, 0x3f, 0x1a, 0x3b, 0x2a, 0x33, 0x24, 0x22,
+        ];
+        let mut registers = [None; REGISTER_COUNT];
+        registers[X86_64::RSP.0 as usize] = Some(0x7000);
+        registers[X86_64::RA.0 as usize] = Some(0x1040);
+        assert_eq!(evaluate(&bytes, None, &registers, |_| None), Some(0x7008));
+        registers[X86_64::RA.0 as usize] = Some(0x104b);
+        assert_eq!(evaluate(&bytes, None, &registers, |_| None), Some(0x7010));
+        // the expression needs rip
+        registers[X86_64::RA.0 as usize] = None;
+        assert_eq!(evaluate(&bytes, None, &registers, |_| None), None);
+    }
+
+    #[test]
+    fn memory_and_initial_value() {
+        // *(cfa + 16) + 1
+        let bytes = [0x23, 0x10, 0x06, 0x23, 0x01];
+        let read_word = |addr| if addr == 0x7010 { Some(41) } else { None };
+        assert_eq!(evaluate(&bytes, Some(0x7000), &[], read_word), Some(42));
+        assert_eq!(evaluate(&bytes, Some(0x8000), &[], read_word), None);
+    }
+}